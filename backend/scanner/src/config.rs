@@ -1,5 +1,14 @@
 use serde::Deserialize;
 
+use crate::db::writer::WriterConfig;
+
+use common::{
+    db::presence::PresenceConfig,
+    geo::GeoConfig,
+    packets::frame::{DEFAULT_MAX_FRAME_SIZE, Limits},
+    probe::{retry::RetryConfig, transcript::CaptureConfig},
+};
+
 #[derive(Deserialize)]
 pub struct Config {
     pub blacklist_file: String,
@@ -9,4 +18,88 @@ pub struct Config {
     pub enable_isp_scan: bool,
     pub isp_scan_subnet: u8,
     pub extended_port_scan: bool,
+    #[serde(default)]
+    pub login_probe: bool,
+    #[serde(default = "default_login_probe_username")]
+    pub login_probe_username: String,
+    /// FML marker (e.g. "FML2", "FML3") appended to the handshake hostname so
+    /// Forge servers include their mod list in the status response.
+    #[serde(default)]
+    pub fml_marker: Option<String>,
+    #[serde(default = "default_max_size")]
+    pub max_frame_size: usize,
+    #[serde(default = "default_max_size")]
+    pub max_string_len: usize,
+    /// How often probe outcome counters are written to `scan_results`.
+    #[serde(default = "default_scan_results_interval")]
+    pub scan_results_interval_secs: u64,
+    #[serde(flatten)]
+    pub capture: CaptureConfig,
+    #[serde(flatten)]
+    pub retry: RetryConfig,
+    #[serde(flatten)]
+    pub writer: WriterConfig,
+    #[serde(flatten)]
+    pub geo: GeoConfig,
+    #[serde(flatten)]
+    pub presence: PresenceConfig,
+}
+
+fn default_max_size() -> usize {
+    DEFAULT_MAX_FRAME_SIZE
+}
+
+fn default_scan_results_interval() -> u64 {
+    60
+}
+
+impl Config {
+    pub fn handshake_host(&self, ip: &str) -> String {
+        match &self.fml_marker {
+            Some(marker) => format!("{}\0{}\0", ip, marker),
+            None => ip.to_string(),
+        }
+    }
+
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_frame_size: self.max_frame_size,
+            max_string_len: self.max_string_len,
+        }
+    }
+}
+
+fn default_login_probe_username() -> String {
+    "kybe".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flattened_sections() {
+        let config: Config = toml::from_str(
+            r#"
+                blacklist_file = "blacklist.txt"
+                worker_count = 1
+                timeout_ms = 1000
+                db_url = "postgres://localhost"
+                enable_isp_scan = false
+                isp_scan_subnet = 24
+                extended_port_scan = false
+                transcript_failures = true
+                retry_outcomes = ["connect_timeout"]
+                retry_attempts = 3
+            "#,
+        )
+        .unwrap();
+        assert!(config.capture.transcript_failures);
+        assert_eq!(config.retry.retry_attempts, 3);
+        assert_eq!(config.retry.retry_backoff_ms, 2_000);
+        assert_eq!(config.scan_results_interval_secs, 60);
+        assert_eq!(config.writer.writer_batch_size, 500);
+        assert!(config.geo.iptoasn_file.is_none());
+        assert_eq!(config.presence.presence_grace_secs, 600);
+    }
 }
@@ -1,94 +0,0 @@
-pub async fn db_init(client: &tokio_postgres::Client) -> Result<(), tokio_postgres::Error> {
-    client
-        .batch_execute(
-            r#"
-        -- Conditionally create custom types if they do not exist
-        DO $$ BEGIN
-            IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'action_type') THEN
-                CREATE TYPE action_type AS ENUM ('JOINED', 'LEFT');
-            END IF;
-        END$$;
-        DO $$ BEGIN
-            IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'player') THEN
-                CREATE TYPE player AS (
-                    name TEXT,
-                    id TEXT
-                );
-            END IF;
-        END$$;
-        DO $$ BEGIN
-            IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'version') THEN
-                CREATE TYPE version AS (
-                    name TEXT,
-                    protocol INTEGER
-                );
-            END IF;
-        END$$;
-        DO $$ BEGIN
-            IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'players') THEN
-                CREATE TYPE players AS (
-                    max INTEGER,
-                    online INTEGER,
-                    sample player[]
-                );
-            END IF;
-        END$$;
-
-        -- Create servers table
-        CREATE TABLE IF NOT EXISTS servers (
-            id SERIAL PRIMARY KEY,
-            ip TEXT NOT NULL UNIQUE,
-            description TEXT,
-            raw_description JSONB,
-            players players,
-            version version,
-            favicon TEXT,
-            enforces_secure_chat BOOLEAN,
-            extra JSONB,
-            last_pinged TIMESTAMPTZ NOT NULL DEFAULT NOW()
-        );
-
-        -- Create player list table
-        CREATE TABLE IF NOT EXISTS player_list (
-            id SERIAL PRIMARY KEY,
-            name TEXT NOT NULL,
-            uuid TEXT NOT NULL,
-            cracked BOOLEAN NOT NULL,
-            UNIQUE (uuid, name)
-        );
-
-        -- Create player actions table
-        CREATE TABLE IF NOT EXISTS player_actions (
-            id SERIAL PRIMARY KEY,
-            user_id INTEGER NOT NULL REFERENCES player_list(id),
-            server_id INTEGER NOT NULL REFERENCES servers(id),
-            action action_type NOT NULL,
-            timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW()
-        );
-
-        -- Create validator status table (for validator only)
-        CREATE TABLE IF NOT EXISTS validator_status (
-            id SERIAL PRIMARY KEY,
-            ips_validated INTEGER NOT NULL,
-            ips_active INTEGER NOT NULL,
-            ips_validated_list TEXT[] NOT NULL,
-            timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW()
-        );
-
-        -- Create scanner status table (for scanner only)
-        CREATE TABLE IF NOT EXISTS status (
-            id SERIAL PRIMARY KEY,
-            ips_scanned INTEGER NOT NULL,
-            ips_active INTEGER NOT NULL,
-            ips_active_list TEXT[] NOT NULL,
-            timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW()
-        );
-
-        -- Create index on player_list for faster lookups
-        CREATE INDEX IF NOT EXISTS idx_player_list_name_uuid ON player_list (name, uuid);
-        "#,
-        )
-        .await?;
-
-    Ok(())
-}
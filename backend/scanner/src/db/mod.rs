@@ -1,242 +1,133 @@
-use std::collections::HashSet;
+use std::time::Duration;
 
 use serde_json::Value;
 
-use crate::{
-    db::structs::{
-        ActionType, Players, Version, extract_players, get_user_id, parse_description,
-        parse_players, parse_version,
+use common::{
+    db::{
+        favicons::save_favicon,
+        servers::UpsertedServer,
+        status::{ParsedStatus, clean_status_json},
+        suspicion::save_suspicion,
     },
-    utils::name_to_uuid,
+    packets::login::LoginState,
 };
 
-pub mod init;
-pub mod structs;
+use crate::{
+    db::mods::{ModList, parse_mod_list},
+    fingerprint::{Fingerprint, fingerprint},
+};
+
+pub mod mods;
+pub mod writer;
 
 /// Parse and clean the server JSON, returning all extracted fields.
-fn parse_server_json(json_str: &str) -> Option<ParsedServerJson> {
-    let json_str = json_str.replace("\\u0000", "").replace('\u{0000}', "");
-    let json: Value = serde_json::from_str(&json_str).ok()?;
-    let description = json.get("description").cloned();
-    let parsed_description = description.as_ref().map(parse_description);
-    let enforces_secure_chat = json.get("enforcesSecureChat").and_then(|v| v.as_bool());
-    let favicon = json
-        .get("favicon")
-        .and_then(|v| v.as_str())
-        .map(|s| s.to_string());
-    let players = json.get("players").map(parse_players);
-    let version = json.get("version").map(parse_version);
-    let mut extra = json.clone();
-    if let Some(obj) = extra.as_object_mut() {
-        obj.remove("description");
-        obj.remove("enforcesSecureChat");
-        obj.remove("favicon");
-        obj.remove("players");
-        obj.remove("version");
+fn parse_server_json(json_str: &str, latency: Duration) -> Option<ParsedServerJson> {
+    let (json_str, json) = clean_status_json(json_str).ok()?;
+    let mut status = ParsedStatus::new(&json_str, &json, latency);
+    let mods = parse_mod_list(&json);
+    if mods.is_some()
+        && let Some(obj) = status.extra.as_object_mut()
+    {
+        obj.remove("forgeData");
+        obj.remove("modinfo");
     }
+    let fingerprint = fingerprint(&json_str, &json, mods.as_ref());
     Some(ParsedServerJson {
-        parsed_description,
-        raw_description: description,
-        enforces_secure_chat,
-        favicon,
-        players,
-        version,
-        extra,
+        status,
+        mods,
+        fingerprint,
     })
 }
 
 struct ParsedServerJson {
-    parsed_description: Option<String>,
-    raw_description: Option<Value>,
-    enforces_secure_chat: Option<bool>,
-    favicon: Option<String>,
-    players: Option<Players>,
-    version: Option<Version>,
-    extra: Value,
+    status: ParsedStatus,
+    mods: Option<ModList>,
+    fingerprint: Fingerprint,
 }
 
-async fn upsert_server_row(
+/// Everything that follows the `servers` upsert of one hit: mods, favicon and
+/// suspicion. Errors are logged, not returned, so one bad
+/// server does not hold up the rest of its batch.
+async fn save_details(
     addr: &str,
     parsed: &ParsedServerJson,
+    latency: Duration,
+    row: &UpsertedServer,
     client: &tokio_postgres::Client,
-) -> Result<(i32, Option<Players>, bool), tokio_postgres::Error> {
-    let existing = client
-        .query_opt(
-            "SELECT id, players FROM servers WHERE ip = $1 ORDER BY id DESC LIMIT 1;",
-            &[&addr],
-        )
-        .await?;
-    let raw_description_json = parsed.raw_description.clone().unwrap_or(Value::Null);
-    if let Some(row) = existing {
-        let old_players: Option<Players> = row.get("players");
-        let updated_row = client
-            .query_one(
-                "UPDATE servers SET description = $2, raw_description = $3, players = $4, version = $5, favicon = $6, enforces_secure_chat = $7, extra = $8, last_pinged = NOW() WHERE id = $1 RETURNING id;",
-                &[&row.get::<_, i32>("id"), &parsed.parsed_description, &raw_description_json, &parsed.players, &parsed.version, &parsed.favicon, &parsed.enforces_secure_chat, &parsed.extra],
-            )
-            .await?;
-        let server_id = updated_row.get::<_, i32>("id");
-        Ok((server_id, old_players, false))
-    } else {
-        let inserted_row = client
-            .query_one(
-                "INSERT INTO servers (ip, description, raw_description, players, version, favicon, enforces_secure_chat, extra) VALUES ($1, $2, $3, $4, $5, $6, $7, $8) RETURNING id;",
-                &[&addr, &parsed.parsed_description, &raw_description_json, &parsed.players, &parsed.version, &parsed.favicon, &parsed.enforces_secure_chat, &parsed.extra],
-            )
-            .await?;
-        let server_id = inserted_row.get::<_, i32>("id");
-        Ok((server_id, None, true))
-    }
-}
-
-/// Save server JSON: orchestrates parsing, upserting, and player join/leave logic.
-pub async fn save_json(addr: &str, json_str: &str, client: &tokio_postgres::Client) {
-    let parsed = match parse_server_json(json_str) {
-        Some(v) => v,
-        None => return,
-    };
-    let (server_id, old_players_opt, is_new_server) =
-        match upsert_server_row(addr, &parsed, client).await {
-            Ok(v) => v,
-            Err(e) => {
-                tracing::error!("Error updating/inserting database: {}", e);
-                return;
-            }
-        };
-    if is_new_server {
+) {
+    if row.inserted {
         tracing::info!("Active server found: {}", addr);
     }
-    if let Some(old_players) = old_players_opt {
-        if let Some(players) = &parsed.players {
-            if old_players != *players {
-                save_player_leaves(&old_players, players, server_id, client).await;
-            }
-        }
+    if let Some(mods) = &parsed.mods {
+        save_mods(mods, row.id, client).await;
     }
-    save_player_joins(&parsed.players, server_id, client).await;
+    save_favicon(addr, parsed.status.icon.as_ref(), client).await;
+    save_suspicion(
+        addr,
+        parsed.status.response_hash.as_deref(),
+        latency,
+        &parsed.status.suspicion,
+        client,
+    )
+    .await;
 }
 
-async fn save_player_joins(
-    players: &Option<Players>,
-    server_id: i32,
-    client: &tokio_postgres::Client,
-) {
-    if let Some(players) = players {
-        if let Some(sample) = &players.sample {
-            for player in sample {
-                let name = match player.name.clone() {
-                    Some(name) => name,
-                    None => continue,
-                };
-                let id = match player.id.clone() {
-                    Some(id) => id,
-                    None => continue,
-                };
-                if name.trim().is_empty() || id.trim().is_empty() {
-                    continue;
-                }
-                if name.contains(" ")
-                    || id.contains(" ")
-                    || name.contains("§")
-                    || id.contains("§")
-                    || name.contains(".")
-                    || id.contains(".")
-                {
-                    continue;
-                }
-                let user_id = match get_user_id(client, &name, &id).await {
-                    Some(uid) => uid,
-                    None => {
-                        let row = client
-                            .query_one(
-                                r#"
-                                    INSERT INTO player_list (name, uuid, cracked)
-                                    VALUES ($1, $2, $3)
-                                    ON CONFLICT (uuid, name) DO NOTHING
-                                    RETURNING id
-                                "#,
-                                &[&name, &id, &(name_to_uuid(&name) == id)],
-                            )
-                            .await;
-                        match row {
-                            Ok(row) => row.get("id"),
-                            Err(_) => continue,
-                        }
-                    }
-                };
-                let _ = client
-                    .execute(
-                        r#"
-                            INSERT INTO player_actions (user_id, server_id, action)
-                            VALUES ($1, $2, $3)
-                        "#,
-                        &[&user_id, &server_id, &ActionType::Joined],
-                    )
-                    .await;
-            }
-        }
+/// Replace the stored mod list of a server with the one from the latest response.
+/// One statement, so it is one transaction even though the writer pipelines
+/// the details of a whole batch on one connection.
+async fn save_mods(mods: &ModList, server_id: i32, client: &tokio_postgres::Client) {
+    let channels = serde_json::to_value(&mods.channels).unwrap_or(Value::Null);
+    let mod_ids: Vec<&str> = mods.mods.iter().map(|m| m.mod_id.as_str()).collect();
+    let versions: Vec<Option<&str>> = mods.mods.iter().map(|m| m.version.as_deref()).collect();
+    let mod_channels: Vec<Value> = mods
+        .mods
+        .iter()
+        .map(|m| serde_json::to_value(&m.channels).unwrap_or(Value::Null))
+        .collect();
+    if let Err(e) = client
+        .execute(
+            r#"
+                WITH loader AS (
+                    UPDATE servers SET mod_loader = $2, mods_truncated = $3, mod_channels = $4
+                    WHERE id = $1
+                ), removed AS (
+                    DELETE FROM server_mods WHERE server_id = $1 AND mod_id <> ALL($5)
+                )
+                INSERT INTO server_mods (server_id, mod_id, version, channels)
+                -- The first entry wins if a mod is listed twice.
+                SELECT DISTINCT ON (mod_id) $1, mod_id, version, channels
+                FROM UNNEST($5::text[], $6::text[], $7::jsonb[]) WITH ORDINALITY
+                    AS m (mod_id, version, channels, n)
+                ORDER BY mod_id, n
+                ON CONFLICT (server_id, mod_id) DO UPDATE SET
+                    version = EXCLUDED.version,
+                    channels = EXCLUDED.channels
+            "#,
+            &[
+                &server_id,
+                &mods.loader,
+                &mods.truncated,
+                &channels,
+                &mod_ids,
+                &versions,
+                &mod_channels,
+            ],
+        )
+        .await
+    {
+        tracing::error!("Error saving mods for server {}: {}", server_id, e);
     }
 }
 
-async fn save_player_leaves(
-    old_players: &Players,
-    players: &Players,
-    server_id: i32,
-    client: &tokio_postgres::Client,
-) {
-    let old_set: HashSet<_> = extract_players(Some(old_players.clone()))
-        .into_iter()
-        .collect();
-    let new_set: HashSet<_> = extract_players(Some(players.clone())).into_iter().collect();
-    for (name, id) in old_set.difference(&new_set) {
-        if name.trim().is_empty() || id.trim().is_empty() {
-            continue;
-        }
-        if name.contains(" ")
-            || id.contains(" ")
-            || name.contains("§")
-            || id.contains("§")
-            || name.contains(".")
-            || id.contains(".")
-        {
-            continue;
-        }
-        let mut user_id = get_user_id(client, name, id).await;
-        if user_id.is_none() {
-            let row = client
-                .query_one(
-                    r#"
-                        INSERT INTO player_list (name, uuid, cracked)
-                        VALUES ($1, $2, $3)
-                        ON CONFLICT (uuid, name) DO NOTHING
-                        RETURNING id
-                    "#,
-                    &[name, id, &(name_to_uuid(name) == *id)],
-                )
-                .await;
-            let row = match row {
-                Ok(row) => row,
-                Err(_) => {
-                    continue;
-                }
-            };
-            let res = row.try_get("id");
-            let res = match res {
-                Ok(id) => id,
-                Err(e) => {
-                    tracing::error!("Error getting player ID: {}", e);
-                    continue;
-                }
-            };
-            user_id = Some(res);
-        }
-        if let Some(user_id) = user_id {
-            let _ = client
-                .execute(
-                    "INSERT INTO player_actions (user_id, server_id, action) VALUES ($1, $2, $3)",
-                    &[&user_id, &server_id, &ActionType::Left],
-                )
-                .await;
-        }
+/// Store the result of a login-state probe on an already saved server.
+pub async fn save_login_probe(addr: &str, state: &LoginState, client: &tokio_postgres::Client) {
+    if let Err(e) = client
+        .execute(
+            "UPDATE servers SET login_state = $2, disconnect_reason = $3, login_checked = NOW() WHERE ip = $1;",
+            &[&addr, &state.as_str(), &state.disconnect_reason()],
+        )
+        .await
+    {
+        tracing::error!("Error saving login probe for {}: {}", addr, e);
     }
 }
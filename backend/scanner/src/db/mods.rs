@@ -0,0 +1,344 @@
+use serde::Serialize;
+use serde_json::Value;
+
+use common::packets::{string::read_string, u16::read_u16, varint::read_var_int};
+
+/// Upper bound for the decoded `forgeData.d` buffer, Forge itself stops around 32k chars.
+const MAX_OPTIMIZED_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct ModChannel {
+    /// The full `namespace:path` resource location.
+    pub name: String,
+    pub version: String,
+    pub required: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct ModInfo {
+    pub mod_id: String,
+    /// `None` when the mod is marked as server-only (IGNORESERVERONLY).
+    pub version: Option<String>,
+    pub channels: Vec<ModChannel>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ModList {
+    pub loader: String,
+    pub mods: Vec<ModInfo>,
+    /// Channels that do not belong to any listed mod.
+    pub channels: Vec<ModChannel>,
+    pub truncated: bool,
+}
+
+/// Extract the mod list from a status response, if the server advertises one.
+/// Fabric and Quilt add nothing to the status response, their servers are only
+/// told apart by [`crate::fingerprint`].
+pub fn parse_mod_list(json: &Value) -> Option<ModList> {
+    if let Some(forge) = json.get("forgeData") {
+        return parse_forge_data(forge);
+    }
+    if let Some(modinfo) = json.get("modinfo") {
+        return parse_legacy_modinfo(modinfo);
+    }
+    None
+}
+
+/// Forge 1.13+ `forgeData`, either plain or with the compressed `d` field.
+fn parse_forge_data(forge: &Value) -> Option<ModList> {
+    let mut list = match forge.get("d").and_then(|v| v.as_str()) {
+        Some(d) => {
+            let buffer = decode_optimized(d)?;
+            read_optimized_mod_list(&buffer)?
+        }
+        None => {
+            let mut channels: Vec<(String, ModChannel)> = forge
+                .get("channels")
+                .and_then(|v| v.as_array())
+                .map(|arr| arr.iter().filter_map(parse_json_channel).collect())
+                .unwrap_or_default();
+            let mods = forge
+                .get("mods")
+                .and_then(|v| v.as_array())
+                .map(|arr| {
+                    arr.iter()
+                        .filter_map(|m| {
+                            let mod_id = m.get("modId")?.as_str()?.to_string();
+                            let version = m
+                                .get("modmarker")
+                                .and_then(|v| v.as_str())
+                                .map(|s| s.to_string());
+                            let (own, rest) = channels
+                                .drain(..)
+                                .partition::<Vec<_>, _>(|(namespace, _)| *namespace == mod_id);
+                            channels = rest;
+                            Some(ModInfo {
+                                mod_id,
+                                version,
+                                channels: own.into_iter().map(|(_, c)| c).collect(),
+                            })
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            ModList {
+                loader: String::new(),
+                mods,
+                channels: channels.into_iter().map(|(_, c)| c).collect(),
+                truncated: false,
+            }
+        }
+    };
+    if forge.get("truncated").and_then(|v| v.as_bool()) == Some(true) {
+        list.truncated = true;
+    }
+    list.loader = if list.mods.iter().any(|m| m.mod_id == "neoforge") {
+        "neoforge".to_string()
+    } else {
+        "forge".to_string()
+    };
+    Some(list)
+}
+
+/// Channel entry of the uncompressed `forgeData.channels`, keyed by its namespace.
+fn parse_json_channel(value: &Value) -> Option<(String, ModChannel)> {
+    let res = value.get("res")?.as_str()?;
+    let (namespace, path) = res.split_once(':').unwrap_or(("minecraft", res));
+    Some((
+        namespace.to_string(),
+        ModChannel {
+            name: format!("{}:{}", namespace, path),
+            version: value
+                .get("version")
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string(),
+            required: value
+                .get("required")
+                .and_then(|v| v.as_bool())
+                .unwrap_or(false),
+        },
+    ))
+}
+
+/// Forge 1.7 - 1.12 `modinfo` (`{"type":"FML","modList":[{"modid":..,"version":..}]}`).
+fn parse_legacy_modinfo(modinfo: &Value) -> Option<ModList> {
+    let loader = modinfo
+        .get("type")
+        .and_then(|v| v.as_str())
+        .unwrap_or("FML")
+        .to_lowercase();
+    let mods = modinfo
+        .get("modList")?
+        .as_array()?
+        .iter()
+        .filter_map(|m| {
+            Some(ModInfo {
+                mod_id: m.get("modid")?.as_str()?.to_string(),
+                version: m
+                    .get("version")
+                    .and_then(|v| v.as_str())
+                    .map(|s| s.to_string()),
+                channels: Vec::new(),
+            })
+        })
+        .collect();
+    Some(ModList {
+        loader,
+        mods,
+        channels: Vec::new(),
+        truncated: false,
+    })
+}
+
+/// Undo Forge's `encodeOptimized`: every UTF-16 unit carries 15 bits, the first two
+/// units hold the byte length of the buffer.
+fn decode_optimized(s: &str) -> Option<Vec<u8>> {
+    let units: Vec<u16> = s.encode_utf16().collect();
+    if units.len() < 2 {
+        return None;
+    }
+    let size = (units[0] as usize & 0x7FFF) | ((units[1] as usize & 0x7FFF) << 15);
+    if size > MAX_OPTIMIZED_SIZE || size > (units.len() - 2) * 15 / 8 + 1 {
+        return None;
+    }
+    let mut out = Vec::with_capacity(size);
+    let mut buffer = 0u32;
+    let mut bits = 0u32;
+    for unit in &units[2..] {
+        while bits >= 8 {
+            out.push(buffer as u8);
+            buffer >>= 8;
+            bits -= 8;
+        }
+        buffer |= (*unit as u32 & 0x7FFF) << bits;
+        bits += 15;
+    }
+    while out.len() < size && bits >= 8 {
+        out.push(buffer as u8);
+        buffer >>= 8;
+        bits -= 8;
+    }
+    out.truncate(size);
+    (out.len() == size).then_some(out)
+}
+
+fn read_bool(data: &[u8], index: &mut usize) -> Option<bool> {
+    let byte = *data.get(*index)?;
+    *index += 1;
+    Some(byte != 0)
+}
+
+fn read_channel(data: &[u8], index: &mut usize) -> Option<ModChannel> {
+    Some(ModChannel {
+        name: read_string(data, index).ok()?,
+        version: read_string(data, index).ok()?,
+        required: read_bool(data, index)?,
+    })
+}
+
+/// Read the buffer written by Forge's `serializeOptimized`.
+fn read_optimized_mod_list(data: &[u8]) -> Option<ModList> {
+    let mut index = 0;
+    let truncated = read_bool(data, &mut index)?;
+    let mod_count = read_u16(data, Some(&mut index)).ok()?;
+    let mut mods = Vec::with_capacity(mod_count as usize);
+    for _ in 0..mod_count {
+        let flags = read_var_int(data, Some(&mut index)).ok()?;
+        let channel_count = (flags as u32 >> 1) as usize;
+        let server_only = flags & 0b1 != 0;
+        let mod_id = read_string(data, &mut index).ok()?;
+        let version = if server_only {
+            None
+        } else {
+            Some(read_string(data, &mut index).ok()?)
+        };
+        let mut channels = Vec::new();
+        for _ in 0..channel_count {
+            // Only the path is sent, the namespace is the mod id.
+            let mut channel = read_channel(data, &mut index)?;
+            channel.name = format!("{}:{}", mod_id, channel.name);
+            channels.push(channel);
+        }
+        mods.push(ModInfo {
+            mod_id,
+            version,
+            channels,
+        });
+    }
+    let mut channels = Vec::new();
+    if index < data.len() {
+        let channel_count = read_var_int(data, Some(&mut index)).ok()? as u32 as usize;
+        for _ in 0..channel_count {
+            channels.push(read_channel(data, &mut index)?);
+        }
+    }
+    Some(ModList {
+        loader: String::new(),
+        mods,
+        channels,
+        truncated,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::packets::{string::write_string, u16::write_u16, varint::write_var_int};
+
+    /// Port of Forge's `encodeOptimized`, used to build fixtures.
+    fn encode_optimized(data: &[u8]) -> String {
+        let mut units = vec![
+            (data.len() & 0x7FFF) as u16,
+            ((data.len() >> 15) & 0x7FFF) as u16,
+        ];
+        let mut buffer = 0u32;
+        let mut bits = 0u32;
+        for byte in data {
+            if bits >= 15 {
+                units.push((buffer & 0x7FFF) as u16);
+                buffer >>= 15;
+                bits -= 15;
+            }
+            buffer |= (*byte as u32) << bits;
+            bits += 8;
+        }
+        if bits > 0 {
+            units.push((buffer & 0x7FFF) as u16);
+        }
+        String::from_utf16(&units).unwrap()
+    }
+
+    #[test]
+    fn test_decode_compressed_forge_data() {
+        let mut data = Vec::new();
+        data.push(1);
+        write_u16(&mut data, 2);
+        write_var_int(&mut data, &(1 << 1));
+        write_string(&mut data, "forge").unwrap();
+        write_string(&mut data, "47.2.0").unwrap();
+        write_string(&mut data, "tier_sorting").unwrap();
+        write_string(&mut data, "1.0").unwrap();
+        data.push(0);
+        write_var_int(&mut data, &1);
+        write_string(&mut data, "serveronly").unwrap();
+        write_var_int(&mut data, &1);
+        write_string(&mut data, "minecraft:register").unwrap();
+        write_string(&mut data, "FML3").unwrap();
+        data.push(1);
+
+        let json = serde_json::json!({
+            "forgeData": {
+                "channels": [],
+                "mods": [],
+                "fmlNetworkVersion": 3,
+                "truncated": false,
+                "d": encode_optimized(&data),
+            }
+        });
+        let list = parse_mod_list(&json).unwrap();
+        assert_eq!(list.loader, "forge");
+        assert!(list.truncated);
+        assert_eq!(list.mods.len(), 2);
+        assert_eq!(list.mods[0].mod_id, "forge");
+        assert_eq!(list.mods[0].version.as_deref(), Some("47.2.0"));
+        assert_eq!(list.mods[0].channels[0].name, "forge:tier_sorting");
+        assert_eq!(list.mods[1].version, None);
+        assert_eq!(list.channels[0].name, "minecraft:register");
+        assert!(list.channels[0].required);
+    }
+
+    #[test]
+    fn test_plain_forge_data() {
+        let json = serde_json::json!({
+            "forgeData": {
+                "channels": [
+                    {"res": "jei:channel", "version": "1", "required": false},
+                    {"res": "fml:handshake", "version": "1.2.3.4", "required": true}
+                ],
+                "mods": [{"modId": "jei", "modmarker": "9.7.2"}],
+                "fmlNetworkVersion": 2
+            }
+        });
+        let list = parse_mod_list(&json).unwrap();
+        assert_eq!(list.mods[0].channels[0].name, "jei:channel");
+        assert_eq!(list.channels[0].name, "fml:handshake");
+        assert!(!list.truncated);
+    }
+
+    #[test]
+    fn test_legacy_modinfo() {
+        let json = serde_json::json!({
+            "modinfo": {"type": "FML", "modList": [{"modid": "mcp", "version": "9.42"}]}
+        });
+        let list = parse_mod_list(&json).unwrap();
+        assert_eq!(list.loader, "fml");
+        assert_eq!(list.mods[0].mod_id, "mcp");
+    }
+
+    #[test]
+    fn test_garbage_d_field() {
+        let json = serde_json::json!({"forgeData": {"d": "\u{7fff}\u{7fff}ab"}});
+        assert_eq!(parse_mod_list(&json), None);
+    }
+}
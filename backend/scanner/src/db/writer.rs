@@ -0,0 +1,529 @@
+//! Batched result writing. Probe workers parse a status response and hand it to
+//! a [`Writer`], one background task collects the results and writes a whole
+//! batch with a single multi-row upsert instead of a round trip per column
+//! group and server. The queue is bounded, so a slow database slows down the
+//! workers instead of growing memory.
+
+use std::{collections::HashMap, net::SocketAddr, slice, sync::Arc, time::Duration};
+
+use deadpool_postgres::Pool;
+use futures::future::join_all;
+use serde::Deserialize;
+use tokio::{
+    sync::mpsc,
+    task::JoinHandle,
+    time::{Instant, timeout_at},
+};
+use tokio_util::sync::CancellationToken;
+
+use common::{
+    db::servers::{ServerRow, save_servers},
+    geo::{Geo, GeoInfo},
+    packets::login::LoginState,
+};
+
+use super::{ParsedServerJson, parse_server_json, save_details, save_login_probe};
+
+/// Writer settings, flattened into the scanner config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct WriterConfig {
+    /// Results written per batch at most.
+    #[serde(default = "default_writer_batch_size")]
+    pub writer_batch_size: usize,
+    /// How long a batch may wait to fill up before it is written anyway.
+    #[serde(default = "default_writer_flush_ms")]
+    pub writer_flush_ms: u64,
+    /// Results waiting to be written before workers have to wait.
+    #[serde(default = "default_writer_queue_size")]
+    pub writer_queue_size: usize,
+}
+
+fn default_writer_batch_size() -> usize {
+    500
+}
+
+fn default_writer_flush_ms() -> u64 {
+    1_000
+}
+
+fn default_writer_queue_size() -> usize {
+    10_000
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        WriterConfig {
+            writer_batch_size: default_writer_batch_size(),
+            writer_flush_ms: default_writer_flush_ms(),
+            writer_queue_size: default_writer_queue_size(),
+        }
+    }
+}
+
+/// Tries per batch before its servers are written one by one, a second apart.
+const FLUSH_ATTEMPTS: u32 = 3;
+/// How often flush latency and queue depth are logged.
+const STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+enum Record {
+    Server {
+        addr: String,
+        latency: Duration,
+        parsed: Box<ParsedServerJson>,
+    },
+    Login {
+        addr: String,
+        state: LoginState,
+    },
+}
+
+/// Handle for queueing results, cheap to clone.
+#[derive(Clone)]
+pub struct Writer {
+    tx: mpsc::Sender<Record>,
+}
+
+/// The running writer task, see [`WriterTask::finish`].
+pub struct WriterTask {
+    shutdown: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl WriterTask {
+    /// Close the queue and wait until everything queued so far is written.
+    /// Results sent after this are dropped with an error.
+    pub async fn finish(self) {
+        self.shutdown.cancel();
+        if let Err(e) = self.task.await {
+            tracing::error!("Result writer failed: {}", e);
+        }
+    }
+}
+
+impl Writer {
+    /// Start the writer task. It stops once every handle is dropped or
+    /// [`WriterTask::finish`] is called, after the queue is written. Servers
+    /// are looked up in `geo` before they are saved, `left_grace` is passed on
+    /// to [`save_servers`].
+    pub fn spawn(
+        pool: Pool,
+        geo: Arc<Geo>,
+        left_grace: Duration,
+        config: &WriterConfig,
+    ) -> (Writer, WriterTask) {
+        let (tx, rx) = mpsc::channel(config.writer_queue_size.max(1));
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(run(
+            pool,
+            geo,
+            left_grace,
+            rx,
+            shutdown.clone(),
+            config.writer_batch_size.max(1),
+            Duration::from_millis(config.writer_flush_ms),
+        ));
+        (Writer { tx }, WriterTask { shutdown, task })
+    }
+
+    /// Parse a status response and queue it. Waits while the queue is full.
+    pub async fn save_json(&self, addr: &str, json_str: &str, latency: Duration) {
+        let Some(parsed) = parse_server_json(json_str, latency) else {
+            return;
+        };
+        self.send(Record::Server {
+            addr: addr.to_string(),
+            latency,
+            parsed: Box::new(parsed),
+        })
+        .await;
+    }
+
+    /// Queue a login probe result. Written after the server in the same or an
+    /// earlier batch, so the row already exists.
+    pub async fn save_login_probe(&self, addr: &str, state: LoginState) {
+        self.send(Record::Login {
+            addr: addr.to_string(),
+            state,
+        })
+        .await;
+    }
+
+    async fn send(&self, record: Record) {
+        if self.tx.send(record).await.is_err() {
+            tracing::error!("Result writer has stopped, dropping result");
+        }
+    }
+}
+
+/// Wait for the first record, then take more until the batch is full or
+/// `flush_interval` has passed. Once `shutdown` is cancelled the channel is
+/// closed and the rest is taken without waiting. Empty once the channel is
+/// closed and drained.
+async fn next_batch<T>(
+    rx: &mut mpsc::Receiver<T>,
+    shutdown: &CancellationToken,
+    batch_size: usize,
+    flush_interval: Duration,
+) -> Vec<T> {
+    let mut batch = Vec::with_capacity(batch_size);
+    let received = tokio::select! {
+        biased;
+        _ = shutdown.cancelled() => {
+            rx.close();
+            rx.recv_many(&mut batch, batch_size).await
+        }
+        n = rx.recv_many(&mut batch, batch_size) => n,
+    };
+    if received == 0 || shutdown.is_cancelled() {
+        return batch;
+    }
+    let deadline = Instant::now() + flush_interval;
+    while batch.len() < batch_size {
+        let limit = batch_size - batch.len();
+        match timeout_at(deadline, rx.recv_many(&mut batch, limit)).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+    }
+    batch
+}
+
+#[derive(Default)]
+struct FlushStats {
+    flushes: u64,
+    records: u64,
+    total: Duration,
+    max: Duration,
+    max_queue: usize,
+}
+
+impl FlushStats {
+    fn record(&mut self, records: usize, took: Duration, queue: usize) {
+        self.flushes += 1;
+        self.records += records as u64;
+        self.total += took;
+        self.max = self.max.max(took);
+        self.max_queue = self.max_queue.max(queue);
+    }
+
+    fn log(&self, queue: usize) {
+        if self.flushes == 0 {
+            return;
+        }
+        tracing::info!(
+            "Writer: {} results in {} batches, flush avg {:?} max {:?}, queue {} (max {})",
+            self.records,
+            self.flushes,
+            self.total / self.flushes as u32,
+            self.max,
+            queue,
+            self.max_queue,
+        );
+    }
+}
+
+async fn run(
+    pool: Pool,
+    geo: Arc<Geo>,
+    left_grace: Duration,
+    mut rx: mpsc::Receiver<Record>,
+    shutdown: CancellationToken,
+    batch_size: usize,
+    flush_interval: Duration,
+) {
+    let mut stats = FlushStats::default();
+    let mut stats_since = Instant::now();
+    loop {
+        let batch = next_batch(&mut rx, &shutdown, batch_size, flush_interval).await;
+        if batch.is_empty() {
+            break;
+        }
+        let records = batch.len();
+        let started = Instant::now();
+        flush(&pool, &geo, left_grace, batch).await;
+        let took = started.elapsed();
+        tracing::debug!(
+            "Writer: flushed {} results in {:?}, {} queued",
+            records,
+            took,
+            rx.len()
+        );
+        stats.record(records, took, rx.len());
+        if stats_since.elapsed() >= STATS_INTERVAL {
+            stats.log(rx.len());
+            stats = FlushStats::default();
+            stats_since = Instant::now();
+        }
+    }
+    stats.log(0);
+}
+
+struct ServerHit {
+    addr: String,
+    latency: Duration,
+    parsed: Box<ParsedServerJson>,
+}
+
+/// Split a batch into servers and login results. Only the latest response of
+/// an address is kept, a multi-row upsert may not touch a row twice.
+fn split_batch(batch: Vec<Record>) -> (Vec<ServerHit>, Vec<(String, LoginState)>) {
+    let mut servers: Vec<ServerHit> = Vec::new();
+    let mut index = HashMap::new();
+    let mut logins = Vec::new();
+    for record in batch {
+        match record {
+            Record::Server {
+                addr,
+                latency,
+                parsed,
+            } => {
+                let hit = ServerHit {
+                    addr: addr.clone(),
+                    latency,
+                    parsed,
+                };
+                match index.get(&addr) {
+                    Some(&i) => servers[i] = hit,
+                    None => {
+                        index.insert(addr, servers.len());
+                        servers.push(hit);
+                    }
+                }
+            }
+            Record::Login { addr, state } => logins.push((addr, state)),
+        }
+    }
+    (servers, logins)
+}
+
+async fn flush(pool: &Pool, geo: &Geo, left_grace: Duration, batch: Vec<Record>) {
+    let (servers, logins) = split_batch(batch);
+    let geo: Vec<Option<GeoInfo>> = servers
+        .iter()
+        .map(|hit| {
+            hit.addr
+                .parse::<SocketAddr>()
+                .ok()
+                .filter(|_| geo.is_enabled())
+                .map(|socket| geo.lookup(socket.ip()))
+        })
+        .collect();
+    for attempt in 1..=FLUSH_ATTEMPTS {
+        let result = match pool.get().await {
+            Ok(mut client) => write_batch(&servers, &geo, &logins, left_grace, &mut client)
+                .await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        match result {
+            Ok(()) => return,
+            Err(e) if attempt < FLUSH_ATTEMPTS => {
+                tracing::warn!("Error writing batch (try {}): {}", attempt, e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            Err(e) => tracing::error!(
+                "Error writing batch of {} servers, writing them one by one: {}",
+                servers.len(),
+                e
+            ),
+        }
+    }
+    write_each(pool, &servers, &geo, &logins, left_grace).await;
+}
+
+/// Last resort for a batch that keeps failing, so one bad row only loses
+/// itself.
+async fn write_each(
+    pool: &Pool,
+    servers: &[ServerHit],
+    geo: &[Option<GeoInfo>],
+    logins: &[(String, LoginState)],
+    left_grace: Duration,
+) {
+    let mut client = match pool.get().await {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(
+                "Error writing batch, dropping {} servers: {}",
+                servers.len(),
+                e
+            );
+            return;
+        }
+    };
+    for (hit, geo) in servers.iter().zip(geo) {
+        let result = write_batch(
+            slice::from_ref(hit),
+            slice::from_ref(geo),
+            &[],
+            left_grace,
+            &mut client,
+        )
+        .await;
+        if let Err(e) = result {
+            tracing::error!("Error writing {}, dropping it: {}", hit.addr, e);
+        }
+    }
+    // Login results log their own errors.
+    join_all(
+        logins
+            .iter()
+            .map(|(addr, state)| save_login_probe(addr, state, &client)),
+    )
+    .await;
+}
+
+async fn write_batch(
+    servers: &[ServerHit],
+    geo: &[Option<GeoInfo>],
+    logins: &[(String, LoginState)],
+    left_grace: Duration,
+    client: &mut tokio_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    let rows: Vec<ServerRow> = servers
+        .iter()
+        .zip(geo)
+        .map(|(hit, geo)| server_row(hit, geo.as_ref()))
+        .collect();
+    let upserted = save_servers(&rows, left_grace, client).await?;
+    let client = &*client;
+    // Statements of concurrent futures on one client are pipelined.
+    join_all(servers.iter().filter_map(|hit| {
+        upserted
+            .get(&hit.addr)
+            .map(|row| save_details(&hit.addr, &hit.parsed, hit.latency, row, client))
+    }))
+    .await;
+    join_all(
+        logins
+            .iter()
+            .map(|(addr, state)| save_login_probe(addr, state, client)),
+    )
+    .await;
+    Ok(())
+}
+
+fn server_row<'a>(hit: &'a ServerHit, geo: Option<&'a GeoInfo>) -> ServerRow<'a> {
+    let fingerprint = &hit.parsed.fingerprint;
+    let software = (
+        fingerprint.software.as_str(),
+        fingerprint.software.is_proxy(),
+        fingerprint.confidence,
+    );
+    hit.parsed
+        .status
+        .row(&hit.addr, hit.latency, Some(software), geo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_next_batch() {
+        let shutdown = CancellationToken::new();
+        let (tx, mut rx) = mpsc::channel(16);
+        for i in 0..5 {
+            tx.send(i).await.unwrap();
+        }
+        // Full batch right away.
+        assert_eq!(
+            next_batch(&mut rx, &shutdown, 3, Duration::from_secs(1)).await,
+            [0, 1, 2]
+        );
+
+        // Partial batch once the interval is over.
+        let started = Instant::now();
+        assert_eq!(
+            next_batch(&mut rx, &shutdown, 3, Duration::from_secs(1)).await,
+            [3, 4]
+        );
+        assert_eq!(started.elapsed(), Duration::from_secs(1));
+
+        // Records arriving while the batch fills up are included.
+        let sender = tx.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(300)).await;
+            sender.send(6).await.unwrap();
+        });
+        tx.send(5).await.unwrap();
+        assert_eq!(
+            next_batch(&mut rx, &shutdown, 3, Duration::from_secs(1)).await,
+            [5, 6]
+        );
+
+        drop(tx);
+        assert!(
+            next_batch(&mut rx, &shutdown, 3, Duration::from_secs(1))
+                .await
+                .is_empty()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_next_batch_shutdown() {
+        let shutdown = CancellationToken::new();
+        let (tx, mut rx) = mpsc::channel(16);
+        for i in 0..5 {
+            tx.send(i).await.unwrap();
+        }
+        shutdown.cancel();
+        // Queued records are taken right away, senders are shut out.
+        let started = Instant::now();
+        assert_eq!(
+            next_batch(&mut rx, &shutdown, 3, Duration::from_secs(1)).await,
+            [0, 1, 2]
+        );
+        assert!(tx.send(5).await.is_err());
+        assert_eq!(
+            next_batch(&mut rx, &shutdown, 3, Duration::from_secs(1)).await,
+            [3, 4]
+        );
+        assert!(
+            next_batch(&mut rx, &shutdown, 3, Duration::from_secs(1))
+                .await
+                .is_empty()
+        );
+        assert_eq!(started.elapsed(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_split_batch() {
+        let parsed = |online: i32| {
+            let json = format!(
+                r#"{{"version":{{"name":"1.21","protocol":767}},"players":{{"max":20,"online":{}}}}}"#,
+                online
+            );
+            Box::new(parse_server_json(&json, Duration::from_millis(50)).unwrap())
+        };
+        let server = |addr: &str, online| Record::Server {
+            addr: addr.to_string(),
+            latency: Duration::from_millis(50),
+            parsed: parsed(online),
+        };
+        let batch = vec![
+            server("1.2.3.4:25565", 1),
+            server("5.6.7.8:25565", 2),
+            Record::Login {
+                addr: "1.2.3.4:25565".into(),
+                state: LoginState::OnlineMode,
+            },
+            server("1.2.3.4:25565", 3),
+        ];
+        let (servers, logins) = split_batch(batch);
+        let online: Vec<_> = servers
+            .iter()
+            .map(|s| {
+                (
+                    s.addr.as_str(),
+                    s.parsed.status.players.as_ref().unwrap().online,
+                )
+            })
+            .collect();
+        assert_eq!(
+            online,
+            [("1.2.3.4:25565", Some(3)), ("5.6.7.8:25565", Some(2))]
+        );
+        assert_eq!(logins.len(), 1);
+    }
+}
@@ -0,0 +1,340 @@
+use std::fmt;
+
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde_json::Value;
+
+use crate::db::mods::ModList;
+
+/// Protocol version sent in our handshake; proxies echo it back when they support it.
+const HANDSHAKE_PROTOCOL: i64 = 757;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Software {
+    Vanilla,
+    CraftBukkit,
+    Spigot,
+    Paper,
+    Purpur,
+    Pufferfish,
+    Folia,
+    Forge,
+    NeoForge,
+    Fabric,
+    Velocity,
+    BungeeCord,
+    Waterfall,
+    Geyser,
+    /// A proxy that hides its name (custom version text, but proxy-like behaviour).
+    UnknownProxy,
+    /// Hosting panels and sleeping servers answering with a fake status.
+    Placeholder,
+    Unknown,
+}
+
+impl Software {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Software::Vanilla => "vanilla",
+            Software::CraftBukkit => "craftbukkit",
+            Software::Spigot => "spigot",
+            Software::Paper => "paper",
+            Software::Purpur => "purpur",
+            Software::Pufferfish => "pufferfish",
+            Software::Folia => "folia",
+            Software::Forge => "forge",
+            Software::NeoForge => "neoforge",
+            Software::Fabric => "fabric",
+            Software::Velocity => "velocity",
+            Software::BungeeCord => "bungeecord",
+            Software::Waterfall => "waterfall",
+            Software::Geyser => "geyser",
+            Software::UnknownProxy => "proxy",
+            Software::Placeholder => "placeholder",
+            Software::Unknown => "unknown",
+        }
+    }
+
+    pub fn is_proxy(&self) -> bool {
+        matches!(
+            self,
+            Software::Velocity
+                | Software::BungeeCord
+                | Software::Waterfall
+                | Software::Geyser
+                | Software::UnknownProxy
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fingerprint {
+    pub software: Software,
+    /// 0.0 - 1.0, how sure the classification is.
+    pub confidence: f32,
+}
+
+/// Version name prefixes the common server implementations put in front of the version.
+const NAME_PREFIXES: &[(&str, Software)] = &[
+    ("velocity", Software::Velocity),
+    ("waterfall", Software::Waterfall),
+    ("bungeecord", Software::BungeeCord),
+    ("geyser", Software::Geyser),
+    ("folia", Software::Folia),
+    ("purpur", Software::Purpur),
+    ("pufferfish", Software::Pufferfish),
+    ("paper", Software::Paper),
+    ("spigot", Software::Spigot),
+    ("craftbukkit", Software::CraftBukkit),
+    ("neoforge", Software::NeoForge),
+    ("forge", Software::Forge),
+    ("fabric", Software::Fabric),
+];
+
+/// Words and phrases hosting panels and server sleepers put into
+/// `version.name`, matched as whole words.
+const PLACEHOLDER_WORDS: &[&str] = &[
+    "offline",
+    "sleeping",
+    "starting",
+    "aternos",
+    "minehut",
+    "exaroton",
+    "requires mc",
+    "maintenance",
+];
+
+/// Status symbols of the same panels, matched anywhere.
+const PLACEHOLDER_SYMBOLS: &[char] = &['⚠', '✘', '●'];
+
+fn is_placeholder_name(lower: &str) -> bool {
+    let words: Vec<&str> = lower
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let padded = format!(" {} ", words.join(" "));
+    PLACEHOLDER_WORDS
+        .iter()
+        .any(|w| padded.contains(&format!(" {} ", w)))
+        || lower.contains(PLACEHOLDER_SYMBOLS)
+}
+
+/// Classify the server software behind a status response.
+///
+/// `raw` is the response as received, it is only used for quirks that the
+/// parsed `Value` loses (top level field order). `mods` is the parsed mod
+/// list, see [`crate::db::mods`].
+pub fn fingerprint(raw: &str, json: &Value, mods: Option<&ModList>) -> Fingerprint {
+    let name = json
+        .get("version")
+        .and_then(|v| v.get("name"))
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    let protocol = json
+        .get("version")
+        .and_then(|v| v.get("protocol"))
+        .and_then(|v| v.as_i64());
+    let lower = strip_formatting(name).to_lowercase();
+    let lower = lower.trim();
+
+    if let Some(software) = NAME_PREFIXES
+        .iter()
+        .find(|(prefix, _)| lower.starts_with(prefix))
+        .map(|(_, software)| *software)
+    {
+        return Fingerprint {
+            software,
+            confidence: 0.95,
+        };
+    }
+
+    if is_placeholder_name(lower) || protocol.is_some_and(|p| p <= 0) {
+        return Fingerprint {
+            software: Software::Placeholder,
+            confidence: 0.8,
+        };
+    }
+
+    if let Some(mods) = mods {
+        return Fingerprint {
+            software: if mods.loader == "neoforge" {
+                Software::NeoForge
+            } else {
+                Software::Forge
+            },
+            confidence: 0.9,
+        };
+    }
+    if let Some(obj) = json.as_object() {
+        // A mod list we could not read, Forge or NeoForge.
+        if obj.contains_key("forgeData") || obj.contains_key("modinfo") {
+            return Fingerprint {
+                software: Software::Forge,
+                confidence: 0.5,
+            };
+        }
+        // Set by several loaders, but modded is not vanilla.
+        if obj.get("isModded").and_then(|v| v.as_bool()) == Some(true) {
+            return Fingerprint {
+                software: Software::Unknown,
+                confidence: 0.0,
+            };
+        }
+    }
+
+    let keys = top_level_keys(raw);
+    // BungeeCord and Velocity serialize `version` first, vanilla starts with `description`.
+    let proxy_order = keys.first().is_some_and(|k| k == "version");
+    let is_range = lower.contains(".x") || lower.contains('-') || lower.contains(',');
+    let echoes_protocol = protocol == Some(HANDSHAKE_PROTOCOL) && !lower.starts_with("1.18");
+
+    if is_range {
+        return Fingerprint {
+            software: if proxy_order {
+                Software::BungeeCord
+            } else {
+                Software::UnknownProxy
+            },
+            confidence: if proxy_order { 0.6 } else { 0.4 },
+        };
+    }
+    if proxy_order {
+        return Fingerprint {
+            software: Software::UnknownProxy,
+            confidence: if echoes_protocol { 0.6 } else { 0.4 },
+        };
+    }
+    if is_release_version(lower) {
+        return Fingerprint {
+            software: Software::Vanilla,
+            confidence: if keys.len() <= 5 { 0.6 } else { 0.4 },
+        };
+    }
+
+    Fingerprint {
+        software: Software::Unknown,
+        confidence: 0.0,
+    }
+}
+
+/// Remove legacy `§x` formatting codes.
+fn strip_formatting(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '§' {
+            chars.next();
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// `1.20`, `1.20.4` and similar plain release names.
+fn is_release_version(s: &str) -> bool {
+    let parts: Vec<&str> = s.split('.').collect();
+    (2..=3).contains(&parts.len())
+        && parts
+            .iter()
+            .all(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_digit()))
+}
+
+/// Keys of the top level JSON object in the order they were sent.
+fn top_level_keys(raw: &str) -> Vec<String> {
+    struct KeyVisitor;
+
+    impl<'de> Visitor<'de> for KeyVisitor {
+        type Value = Vec<String>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a JSON object")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut keys = Vec::new();
+            while let Some(key) = map.next_key::<String>()? {
+                map.next_value::<IgnoredAny>()?;
+                keys.push(key);
+            }
+            Ok(keys)
+        }
+    }
+
+    let mut de = serde_json::Deserializer::from_str(raw);
+    de.deserialize_map(KeyVisitor).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::mods::parse_mod_list;
+
+    fn classify(raw: &str) -> Fingerprint {
+        let json: Value = serde_json::from_str(raw).unwrap();
+        fingerprint(raw, &json, parse_mod_list(&json).as_ref())
+    }
+
+    #[test]
+    fn test_named_software() {
+        let fp = classify(r#"{"version":{"name":"Paper 1.20.4","protocol":765}}"#);
+        assert_eq!(fp.software, Software::Paper);
+        let fp = classify(r#"{"version":{"name":"Velocity 3.3.0","protocol":757}}"#);
+        assert_eq!(fp.software, Software::Velocity);
+        assert!(fp.software.is_proxy());
+        let fp = classify(r#"{"version":{"name":"BungeeCord 1.8.x-1.21.x","protocol":757}}"#);
+        assert_eq!(fp.software, Software::BungeeCord);
+    }
+
+    #[test]
+    fn test_placeholder() {
+        let fp = classify(r#"{"version":{"name":"Requires MC 1.20","protocol":763}}"#);
+        assert_eq!(fp.software, Software::Placeholder);
+        let fp = classify(r#"{"version":{"name":"§4● Offline","protocol":-1}}"#);
+        assert_eq!(fp.software, Software::Placeholder);
+        let fp = classify(r#"{"version":{"name":"Server is starting...","protocol":765}}"#);
+        assert_eq!(fp.software, Software::Placeholder);
+        // Only whole words count.
+        for name in [
+            "StartingBlocks 1.20.4",
+            "Offlineland 1.20.4",
+            "Aternosity 1.20",
+        ] {
+            let raw = format!(r#"{{"version":{{"name":"{}","protocol":765}}}}"#, name);
+            assert_ne!(classify(&raw).software, Software::Placeholder, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_field_order() {
+        let fp = classify(
+            r#"{"version":{"name":"MyNetwork 1.8-1.21","protocol":757},"players":{},"description":""}"#,
+        );
+        assert_eq!(fp.software, Software::BungeeCord);
+        let fp = classify(
+            r#"{"description":"A Minecraft Server","players":{"max":20,"online":0},"version":{"name":"1.20.4","protocol":765}}"#,
+        );
+        assert_eq!(fp.software, Software::Vanilla);
+    }
+
+    #[test]
+    fn test_modded() {
+        let fp = classify(
+            r#"{"description":"","version":{"name":"1.20.1","protocol":763},"forgeData":{"mods":[]}}"#,
+        );
+        assert_eq!(fp.software, Software::Forge);
+
+        // The loader comes from the mod list, also when it was compressed.
+        let raw = r#"{"version":{"name":"1.20.4","protocol":765},"forgeData":{"d":"..."}}"#;
+        let json: Value = serde_json::from_str(raw).unwrap();
+        let mods = ModList {
+            loader: "neoforge".into(),
+            ..ModList::default()
+        };
+        assert_eq!(
+            fingerprint(raw, &json, Some(&mods)).software,
+            Software::NeoForge
+        );
+        let fp = classify(r#"{"version":{"name":"1.20.4","protocol":765},"isModded":true}"#);
+        assert_eq!(fp.software, Software::Unknown);
+    }
+}
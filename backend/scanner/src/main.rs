@@ -1,70 +1,119 @@
-mod blacklist;
 mod config;
 mod db;
-mod packets;
-mod utils;
+mod fingerprint;
 mod worker;
 
 use std::{
     net::{Ipv4Addr, SocketAddr},
+    path::Path,
     sync::Arc,
     time::Duration,
 };
 
 use deadpool_postgres::{Manager, Pool};
 use rand::random;
+use tokio::{sync::mpsc::Sender, task::JoinSet};
 use tokio_postgres::NoTls;
 
-use crate::{
+use common::{
     blacklist::{Blacklist, load_blacklist},
+    db::{
+        favicons::{backfill_favicons, dump_favicons},
+        geo::run_backfill,
+        init::db_init,
+        migrations::run_command,
+        scan_results::save_scan_results,
+    },
+    geo::load_geo,
+    probe::{
+        outcome::OutcomeCounter,
+        retry::{Attempt, RetryQueue, run_retries},
+        transcript::print_files,
+    },
+};
+
+use crate::{
     config::Config,
-    db::init::db_init,
-    worker::handle_ip::handle_ip,
+    db::writer::Writer,
+    worker::{
+        handle_ip::handle_ip,
+        scanner::{IspScan, subnet_targets},
+    },
 };
 
 async fn start_scanning_workers(
-    thread_count: usize,
-    pool: Pool,
+    writer: Writer,
     blacklist: Arc<Blacklist>,
     config: Arc<Config>,
+    outcomes: Arc<OutcomeCounter>,
     rounds: u8,
     seed: u64,
     timeout_duration: Duration,
 ) {
+    let thread_count = config.worker_count;
     if thread_count == 0 {
         tracing::info!("worker_count is 0, scanning is disabled.");
         return;
     }
 
     let total_ips = u64::from(u32::MAX) + 1;
-    let (tx, rx) = tokio::sync::mpsc::channel::<u32>(thread_count * 100);
+    let (tx, rx) = tokio::sync::mpsc::channel::<SocketAddr>(thread_count * 100);
+    let (isp_scan, mut subnets) = IspScan::new(config.isp_scan_subnet);
+    let isp_scan = Arc::new(isp_scan);
 
-    let mut handles = Vec::with_capacity(thread_count);
+    let (retries, retry_rx) = RetryQueue::new(config.retry.clone());
+    let retries = Arc::new(retries);
+    {
+        let writer = writer.clone();
+        let config = Arc::clone(&config);
+        let isp_scan = Arc::clone(&isp_scan);
+        let outcomes = Arc::clone(&outcomes);
+        let retries = Arc::clone(&retries);
+        tokio::spawn(run_retries(
+            retry_rx,
+            config.retry.retry_workers,
+            move |attempt| {
+                handle_ip(
+                    attempt,
+                    writer.clone(),
+                    timeout_duration,
+                    isp_scan.clone(),
+                    config.clone(),
+                    outcomes.clone(),
+                    retries.clone(),
+                )
+            },
+        ));
+    }
+
+    // Dropped with this future when the scan is interrupted, which stops the
+    // workers along with whatever they were probing.
+    let mut workers = JoinSet::new();
     let rx = Arc::new(tokio::sync::Mutex::new(rx));
 
     for _ in 0..thread_count {
         let rx = Arc::clone(&rx);
-        let pool = pool.clone();
+        let writer = writer.clone();
         let config = Arc::clone(&config);
-        let blacklist = Arc::clone(&blacklist);
-        let handle = tokio::spawn(async move {
+        let isp_scan = Arc::clone(&isp_scan);
+        let outcomes = Arc::clone(&outcomes);
+        let retries = Arc::clone(&retries);
+        workers.spawn(async move {
             loop {
-                let ip = {
+                let addr = {
                     let mut rx = rx.lock().await;
                     rx.recv().await
                 };
 
-                if let Some(ip) = ip {
-                    let socket = SocketAddr::new(Ipv4Addr::from(ip).into(), 25565);
-                    let _ = tokio::time::timeout(
+                if let Some(addr) = addr {
+                    handle_ip(
+                        Attempt::first(addr),
+                        writer.clone(),
                         timeout_duration,
-                        handle_ip(
-                            socket,
-                            pool.clone(),
-                            timeout_duration,
-                            blacklist.clone(),
-                            config.clone(),
-                        ),
+                        isp_scan.clone(),
+                        config.clone(),
+                        outcomes.clone(),
+                        retries.clone(),
                     )
                     .await;
                 } else {
@@ -72,21 +121,54 @@ async fn start_scanning_workers(
                 }
             }
         });
-        handles.push(handle);
     }
 
-    let blacklist = blacklist.clone();
-    tokio::spawn(async move {
+    let extended = config.extended_port_scan;
+    let prefix = config.isp_scan_subnet;
+    let generator = tokio::spawn(async move {
+        // Hosts near a found server go ahead of the sweep. They wait in the
+        // same queue, so a subnet scan is throttled like the rest.
+        let targets = |ip| subnet_targets(ip, prefix, extended, &blacklist);
         for i in 0..total_ips {
+            while let Ok(ip) = subnets.try_recv() {
+                if !send_all(&tx, targets(ip)).await {
+                    return;
+                }
+            }
             let ip = permute_u32(i as u32, rounds, seed);
             let ip_addr = Ipv4Addr::from(ip);
-            if !blacklist.contains(&ip_addr) && tx.send(ip).await.is_err() {
-                break;
+            if blacklist.contains(&ip_addr) {
+                continue;
+            }
+            if tx
+                .send(SocketAddr::new(ip_addr.into(), 25565))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        // Subnets found from here on are dropped, the queued ones are scanned.
+        subnets.close();
+        while let Some(ip) = subnets.recv().await {
+            if !send_all(&tx, targets(ip)).await {
+                return;
             }
         }
-    })
-    .await
-    .unwrap();
+    });
+    // The workers stop once the queue of addresses is empty.
+    generator.await.unwrap();
+    workers.join_all().await;
+}
+
+/// Queue `addrs` for the workers, false once they have stopped.
+async fn send_all(tx: &Sender<SocketAddr>, addrs: impl Iterator<Item = SocketAddr>) -> bool {
+    for addr in addrs {
+        if tx.send(addr).await.is_err() {
+            return false;
+        }
+    }
+    true
 }
 
 fn feistel_round(x: u32, key: u32) -> u32 {
@@ -106,15 +188,77 @@ fn permute_u32(mut x: u32, rounds: u8, seed: u64) -> u32 {
     x
 }
 
+/// Periodically add the probe outcome counters to `scan_results`.
+async fn flush_scan_results(pool: Pool, outcomes: Arc<OutcomeCounter>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        write_scan_results(&pool, &outcomes).await;
+    }
+}
+
+/// Add the probe outcome counters to `scan_results`, they are put back if
+/// that fails.
+async fn write_scan_results(pool: &Pool, outcomes: &OutcomeCounter) {
+    let counts = outcomes.take();
+    if counts.is_empty() {
+        return;
+    }
+    let result = match pool.get().await {
+        Ok(client) => save_scan_results(&counts, &client)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e.to_string()),
+    };
+    if let Err(e) = result {
+        tracing::error!("Error saving scan results: {}", e);
+        outcomes.merge(counts);
+    }
+}
+
+/// `scanner favicons <dir> [min_refs]`: write the stored favicons as PNG files.
+async fn dump_favicon_files(config: &Config, args: &[String]) {
+    let Some(dir) = args.first() else {
+        eprintln!("usage: scanner favicons <dir> [min_refs]");
+        return;
+    };
+    let min_refs = match args.get(1).map(|s| s.parse::<i32>()) {
+        Some(Ok(n)) => n,
+        Some(Err(_)) => {
+            eprintln!("min_refs must be a number");
+            return;
+        }
+        None => 1,
+    };
+    let (client, connection) = tokio_postgres::connect(&config.db_url, NoTls)
+        .await
+        .expect("Failed to connect to DB");
+    tokio::spawn(connection);
+    match dump_favicons(&client, Path::new(dir), min_refs).await {
+        Ok(n) => println!("wrote {} favicons to {}", n, dir),
+        Err(e) => eprintln!("Error dumping favicons: {}", e),
+    }
+}
+
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt::init();
 
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).map(String::as_str) == Some("transcript") {
+        print_files(&args[2..]).await;
+        return;
+    }
+
     let config_data = tokio::fs::read_to_string("config.toml")
         .await
         .expect("Failed to read config.toml");
     let config: Config = toml::from_str(&config_data).expect("Invalid config format");
 
+    if args.get(1).map(String::as_str) == Some("favicons") {
+        dump_favicon_files(&config, &args[2..]).await;
+        return;
+    }
+
     let blacklist = Arc::new(
         load_blacklist(&config.blacklist_file)
             .await
@@ -130,22 +274,57 @@ async fn main() {
     let pool = Pool::builder(mgr).max_size(100).build().unwrap();
 
     let client = pool.get().await.expect("Failed to get DB client");
+    if args.get(1).map(String::as_str) == Some("migrate") {
+        run_command(&client, &args[2..]).await;
+        return;
+    }
     db_init(&client).await.expect("Failed to initialize DB");
 
+    let geo = Arc::new(load_geo(&config.geo).expect("Failed to load GeoIP databases"));
+    if args.get(1).map(String::as_str) == Some("geo-backfill") {
+        run_backfill(&client, &geo).await;
+        return;
+    }
+    if args.get(1).map(String::as_str) == Some("favicon-backfill") {
+        match backfill_favicons(&client).await {
+            Ok(n) => println!("moved the favicons of {} servers", n),
+            Err(e) => eprintln!("Favicon backfill failed: {}", e),
+        }
+        return;
+    }
+
     let seed: u64 = random();
     let rounds = 6;
-    let thread_count = config.worker_count;
     let timeout_duration = Duration::from_millis(config.timeout_ms);
     let config = Arc::new(config);
+    let outcomes = Arc::new(OutcomeCounter::default());
+
+    tokio::spawn(flush_scan_results(
+        pool.clone(),
+        Arc::clone(&outcomes),
+        Duration::from_secs(config.scan_results_interval_secs.max(1)),
+    ));
 
-    start_scanning_workers(
-        thread_count,
+    let (writer, writer_task) = Writer::spawn(
         pool.clone(),
-        Arc::clone(&blacklist),
-        Arc::clone(&config),
-        rounds,
-        seed,
-        timeout_duration,
-    )
-    .await;
+        geo,
+        config.presence.left_grace(),
+        &config.writer,
+    );
+
+    tokio::select! {
+        _ = start_scanning_workers(
+            writer,
+            Arc::clone(&blacklist),
+            Arc::clone(&config),
+            Arc::clone(&outcomes),
+            rounds,
+            seed,
+            timeout_duration,
+        ) => {}
+        _ = tokio::signal::ctrl_c() => tracing::info!("Interrupted, writing queued results"),
+    }
+    writer_task.finish().await;
+    // The workers are done, count what they probed since the last flush.
+    write_scan_results(&pool, &outcomes).await;
 }
@@ -1,32 +0,0 @@
-use crate::packets::{string::write_string, u16::write_u16, varint::write_var_int};
-
-pub mod string;
-pub mod u16;
-pub mod varint;
-
-pub async fn create_handshake_packet(
-    protocol_version: i32,
-    server_address: &str,
-    server_port: u16,
-    next_state: i32,
-) -> Vec<u8> {
-    let mut outer = Vec::new();
-    let mut inner = Vec::new();
-    write_var_int(&mut inner, &0x0);
-    write_var_int(&mut inner, &protocol_version);
-    write_string(&mut inner, server_address);
-    write_u16(&mut inner, server_port);
-    write_var_int(&mut inner, &next_state);
-    write_var_int(&mut outer, &(inner.len() as i32));
-    outer.extend_from_slice(&inner);
-    outer
-}
-
-pub async fn create_status_request() -> Vec<u8> {
-    let mut outer = Vec::new();
-    let mut inner = Vec::new();
-    write_var_int(&mut inner, &0x0);
-    write_var_int(&mut outer, &(inner.len() as i32));
-    outer.extend_from_slice(&inner);
-    outer
-}
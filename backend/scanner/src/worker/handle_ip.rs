@@ -5,62 +5,30 @@ use std::{
     time::Duration,
 };
 
-use deadpool_postgres::Pool;
-use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
-    time::timeout,
+use common::{
+    blacklist::Blacklist,
+    probe::{
+        StatusReply,
+        outcome::{OutcomeCounter, ProbeOutcome, connect},
+        retry::{Attempt, RetryQueue},
+        transcript::status_with_capture,
+    },
 };
 
 use crate::{
-    blacklist::Blacklist,
     config::Config,
-    db::save_json,
-    packets::{
-        create_handshake_packet, create_status_request,
-        string::read_string,
-        varint::{read_var_int, read_var_int_from_stream},
-    },
-    worker::scanner::scan_subnet_and_ports,
+    db::writer::Writer,
+    worker::{login_probe::run_login_probe, scanner::IspScan},
 };
 
-pub async fn try_handshake_and_status(
-    stream: &mut TcpStream,
-    ip: &str,
-    port: u16,
-) -> Result<String, String> {
-    let handshake = create_handshake_packet(757, ip, port, 1).await;
-    stream
-        .write_all(&handshake)
-        .await
-        .map_err(|e| format!("handshake failed: {}", e))?;
-    let status = create_status_request().await;
-    stream
-        .write_all(&status)
-        .await
-        .map_err(|e| format!("status request failed: {}", e))?;
-    let len = read_var_int_from_stream(stream)
-        .await
-        .map_err(|_| "read_var_int_from_stream failed".to_string())?;
-    let mut buffer = vec![0; len as usize];
-    stream
-        .read_exact(&mut buffer)
-        .await
-        .map_err(|e| format!("read failed: {}", e))?;
-    let mut index = 0;
-    let _ = read_var_int(&buffer, Some(&mut index));
-    let response =
-        read_string(&buffer, &mut index).map_err(|_| "read_string failed".to_string())?;
-    Ok(response)
-}
-
 #[allow(dead_code)]
 async fn extended_port_scan(
     ip: Ipv4Addr,
-    pool: Pool,
+    writer: Writer,
     timeout_duration: Duration,
-    _config: Arc<Config>,
+    config: Arc<Config>,
     blacklist: Arc<Blacklist>,
+    outcomes: Arc<OutcomeCounter>,
 ) {
     let mut found = false;
     for port in 25500..=25700 {
@@ -68,7 +36,15 @@ async fn extended_port_scan(
             break;
         }
         let socket = SocketAddr::new(ip.into(), port);
-        if try_port(socket, pool.clone(), timeout_duration, _config.clone()).await {
+        if try_port(
+            socket,
+            writer.clone(),
+            timeout_duration,
+            config.clone(),
+            &outcomes,
+        )
+        .await
+        {
             found = true;
             break;
         }
@@ -79,7 +55,14 @@ async fn extended_port_scan(
                 break;
             }
             let socket = SocketAddr::new(ip.into(), port);
-            let _ = try_port(socket, pool.clone(), timeout_duration, _config.clone()).await;
+            let _ = try_port(
+                socket,
+                writer.clone(),
+                timeout_duration,
+                config.clone(),
+                &outcomes,
+            )
+            .await;
         }
     }
 }
@@ -87,92 +70,114 @@ async fn extended_port_scan(
 #[allow(dead_code)]
 async fn try_port(
     socket: SocketAddr,
-    pool: Pool,
+    writer: Writer,
     timeout_duration: Duration,
-    _config: Arc<Config>,
+    config: Arc<Config>,
+    outcomes: &OutcomeCounter,
 ) -> bool {
     let ip = match socket.ip() {
         std::net::IpAddr::V4(ip) => ip,
         _ => return false,
     };
-    let port = socket.port();
-    if let Ok(Ok(mut stream)) =
-        tokio::time::timeout(timeout_duration, TcpStream::connect(socket)).await
-    {
-        let handshake = create_handshake_packet(757, &ip.to_string(), port, 1).await;
-        if stream.write_all(&handshake).await.is_err() {
-            return false;
-        }
-        let status = create_status_request().await;
-        if stream.write_all(&status).await.is_err() {
-            return false;
-        }
-        let len = match read_var_int_from_stream(&mut stream).await {
-            Ok(l) => l,
-            Err(_) => return false,
-        };
-        let mut buffer = vec![0; len as usize];
-        if stream.read_exact(&mut buffer).await.is_err() {
-            return false;
-        }
-        let mut index = 0;
-        let _ = read_var_int(&buffer, Some(&mut index));
-        let response = read_string(&buffer, &mut index).ok();
-        let client = match pool.get().await {
-            Ok(c) => c,
-            Err(_) => return false,
-        };
-        if let Some(resp) = response {
-            save_json(&socket.to_string(), &resp, &client).await;
-            return true;
-        }
+    let host = config.handshake_host(&ip.to_string());
+    let result = probe_address(socket, &host, &config, timeout_duration).await;
+    outcomes.record_result(socket, &result);
+    if let Ok(resp) = result {
+        writer
+            .save_json(&socket.to_string(), &resp.json, resp.latency)
+            .await;
+        return true;
     }
     false
 }
 
-pub fn handle_ip(
+/// Connect to `addr` and ask for its status. Failures are only logged at
+/// debug level, `scan_results` has the totals. The outcome is not recorded,
+/// see [`OutcomeCounter`].
+pub async fn probe_address(
     addr: SocketAddr,
-    pool: Pool,
+    host: &str,
+    config: &Config,
     timeout_duration: Duration,
-    blacklist: Arc<Blacklist>,
+) -> Result<StatusReply, ProbeOutcome> {
+    let mut stream = connect(addr, timeout_duration).await?;
+    let result = status_with_capture(
+        &mut stream,
+        &config.capture,
+        addr,
+        host,
+        &config.limits(),
+        timeout_duration,
+    )
+    .await;
+    let outcome = ProbeOutcome::from_status(&result);
+    result.map_err(|e| {
+        tracing::debug!("{}: {}", addr, e);
+        if let Some(body) = e.body() {
+            tracing::debug!("{} sent {:?}", addr, body);
+        }
+        outcome
+    })
+}
+
+/// Probe one address, checked against the blacklist before it was queued.
+/// Transient failures go to `retries`, which calls back into this function
+/// once their backoff has passed.
+pub fn handle_ip(
+    attempt: Attempt,
+    writer: Writer,
+    timeout_duration: Duration,
+    isp_scan: Arc<IspScan>,
     config: Arc<Config>,
+    outcomes: Arc<OutcomeCounter>,
+    retries: Arc<RetryQueue>,
 ) -> Pin<Box<dyn Future<Output = ()> + Send>> {
     Box::pin(async move {
+        let addr = attempt.addr;
         let ip = match addr.ip() {
             std::net::IpAddr::V4(ip) => ip,
             _ => return,
         };
 
-        if blacklist.contains(&ip) {
-            tracing::debug!("{}:{} is in blacklist, skipping", ip, addr.port());
+        let port = addr.port();
+        let host = config.handshake_host(&ip.to_string());
+        // Connecting and reading each get their own timeout_duration, this
+        // bounds servers that trickle out their response. Only the probe is
+        // limited, saving the result may wait for the writer.
+        let probe = probe_address(addr, &host, &config, timeout_duration);
+        let result = match tokio::time::timeout(timeout_duration * 2, probe).await {
+            Ok(result) => result,
+            Err(_) => Err(ProbeOutcome::ReadTimeout),
+        };
+        // Only the last attempt of a probe is counted.
+        if let Err(outcome) = result
+            && retries.schedule(attempt, outcome)
+        {
+            tracing::debug!("{}: {}, retrying", addr, outcome.as_str());
             return;
         }
+        outcomes.record_result(addr, &result);
+        let Ok(resp) = result else {
+            return;
+        };
+        tracing::info!("Got response for {}:{}", ip, port);
+        writer
+            .save_json(&addr.to_string(), &resp.json, resp.latency)
+            .await;
 
-        let port = addr.port();
-        let stream_result = timeout(timeout_duration, TcpStream::connect(addr)).await;
-        if let Ok(Ok(mut stream)) = stream_result {
-            match try_handshake_and_status(&mut stream, &ip.to_string(), port).await {
-                Ok(resp) => {
-                    tracing::info!("Got response for {}:{}", ip, port);
-                    if let Ok(client) = pool.get().await {
-                        save_json(&addr.to_string(), &resp, &client).await;
-                    }
+        if config.login_probe {
+            run_login_probe(
+                addr,
+                resp.login_protocol(),
+                &writer,
+                &config,
+                timeout_duration,
+            )
+            .await;
+        }
 
-                    if config.enable_isp_scan {
-                        scan_subnet_and_ports(
-                            ip,
-                            pool.clone(),
-                            timeout_duration,
-                            config.clone(),
-                            blacklist.clone(),
-                        )
-                        .await;
-                    }
-                }
-                Err(e) => {
-                    tracing::warn!("{}:{}: {}", ip, port, e);
-                }
-            }
+        if config.enable_isp_scan {
+            isp_scan.queue(ip);
         }
     })
 }
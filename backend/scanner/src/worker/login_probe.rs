@@ -0,0 +1,67 @@
+use std::{net::SocketAddr, time::Duration};
+
+use tokio::{
+    net::TcpStream,
+    time::{Instant, timeout},
+};
+
+use common::{
+    packets::{frame::Limits, login::LoginState},
+    probe::try_login,
+};
+
+use crate::{config::Config, db::writer::Writer};
+
+/// Connect in login state, send Login Start for `protocol_version` and
+/// classify the first reply. The connection is dropped right after, so the
+/// server never sees a join.
+pub async fn probe_login(
+    addr: SocketAddr,
+    protocol_version: i32,
+    username: &str,
+    limits: &Limits,
+    timeout_duration: Duration,
+) -> Result<LoginState, String> {
+    let deadline = Instant::now() + timeout_duration;
+    let mut stream = timeout(timeout_duration, TcpStream::connect(addr))
+        .await
+        .map_err(|_| "connect timed out".to_string())?
+        .map_err(|e| format!("connect failed: {}", e))?;
+
+    try_login(
+        &mut stream,
+        &addr.ip().to_string(),
+        addr.port(),
+        protocol_version,
+        username,
+        limits,
+        deadline.saturating_duration_since(Instant::now()),
+    )
+    .await
+}
+
+pub async fn run_login_probe(
+    addr: SocketAddr,
+    protocol_version: i32,
+    writer: &Writer,
+    config: &Config,
+    timeout_duration: Duration,
+) {
+    match probe_login(
+        addr,
+        protocol_version,
+        &config.login_probe_username,
+        &config.limits(),
+        timeout_duration,
+    )
+    .await
+    {
+        Ok(state) => {
+            tracing::info!("Login probe for {}: {}", addr, state.as_str());
+            writer.save_login_probe(&addr.to_string(), state).await;
+        }
+        Err(e) => {
+            tracing::warn!("{}: login probe: {}", addr, e);
+        }
+    }
+}
@@ -1,2 +1,3 @@
 pub mod handle_ip;
+pub mod login_probe;
 pub mod scanner;
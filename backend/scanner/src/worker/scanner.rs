@@ -1,75 +1,95 @@
-use crate::{
-    blacklist::Blacklist, config::Config, db::save_json,
-    worker::handle_ip::try_handshake_and_status,
+use std::{
+    collections::HashSet,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Mutex,
 };
-use deadpool_postgres::Pool;
-use std::net::SocketAddr;
-use std::{net::Ipv4Addr, sync::Arc, time::Duration};
-use tokio::net::TcpStream;
 
-pub async fn scan_subnet_and_ports(
-    ip: Ipv4Addr,
-    pool: Pool,
-    timeout_duration: Duration,
-    config: Arc<Config>,
-    blacklist: Arc<Blacklist>,
-) {
-    let subnet_prefix = config.isp_scan_subnet;
-    let extended_port_scan = config.extended_port_scan;
-    let subnet = format!("{}/{}", ip, subnet_prefix);
-    let net: ipnet::Ipv4Net = match subnet.parse() {
-        Ok(n) => n,
-        Err(_) => return,
-    };
-    let mut handles = vec![];
-    for host in net.hosts() {
-        if host == ip || blacklist.contains(&host) {
-            continue;
+use common::blacklist::Blacklist;
+use ipnet::Ipv4Net;
+use tokio::sync::mpsc;
+
+/// Subnets of found servers waiting to be scanned, further ones are dropped.
+const ISP_QUEUE_SIZE: usize = 1_000;
+
+/// Hands the subnets of found servers to the address generator, which queues
+/// their hosts for the scanning workers. Every subnet is scanned once, so
+/// servers found by the subnet scan do not start another one.
+pub struct IspScan {
+    prefix: u8,
+    tx: mpsc::Sender<Ipv4Addr>,
+    seen: Mutex<HashSet<Ipv4Net>>,
+}
+
+impl IspScan {
+    pub fn new(prefix: u8) -> (Self, mpsc::Receiver<Ipv4Addr>) {
+        let (tx, rx) = mpsc::channel(ISP_QUEUE_SIZE);
+        let scan = IspScan {
+            prefix,
+            tx,
+            seen: Mutex::new(HashSet::new()),
+        };
+        (scan, rx)
+    }
+
+    /// Queue the subnet of `ip`, a server that answered.
+    pub fn queue(&self, ip: Ipv4Addr) {
+        let Ok(net) = Ipv4Net::new(ip, self.prefix) else {
+            return;
+        };
+        if !self.seen.lock().unwrap().insert(net.trunc()) {
+            return;
+        }
+        if self.tx.try_send(ip).is_err() {
+            tracing::debug!("ISP scan queue full, dropping {}", net.trunc());
         }
-        let pool = pool.clone();
-        let h = tokio::spawn(async move {
-            let socket = SocketAddr::new(host.into(), 25565);
-            let host_str = host.to_string();
-            if let Ok(Ok(mut stream)) =
-                tokio::time::timeout(timeout_duration, TcpStream::connect(socket)).await
-            {
-                if let Ok(resp) =
-                    try_handshake_and_status(&mut stream, host_str.as_str(), 25565).await
-                {
-                    if let Ok(client) = pool.get().await {
-                        save_json(&socket.to_string(), &resp, &client).await;
-                        tracing::info!("[ISP SCAN] Found server at {}:25565", host);
-                    }
-                }
-                if extended_port_scan {
-                    for port in 1024..=65535u16 {
-                        let port_socket = SocketAddr::new(host.into(), port);
-                        let port_str = host.to_string();
-                        if let Ok(Ok(mut port_stream)) =
-                            tokio::time::timeout(timeout_duration, TcpStream::connect(port_socket))
-                                .await
-                        {
-                            if let Ok(resp) =
-                                try_handshake_and_status(&mut port_stream, port_str.as_str(), port)
-                                    .await
-                            {
-                                if let Ok(client) = pool.get().await {
-                                    save_json(&port_socket.to_string(), &resp, &client).await;
-                                    tracing::info!(
-                                        "[EXT PORT SCAN] Found server at {}:{}",
-                                        host,
-                                        port
-                                    );
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-        });
-        handles.push(h);
     }
-    for h in handles {
-        let _ = h.await;
+}
+
+/// The addresses to probe around `ip`: port 25565 of every other host in its
+/// subnet, followed by ports 1024 to 65535 of that host if `extended`.
+pub fn subnet_targets(
+    ip: Ipv4Addr,
+    prefix: u8,
+    extended: bool,
+    blacklist: &Blacklist,
+) -> impl Iterator<Item = SocketAddr> + '_ {
+    let hosts = Ipv4Net::new(ip, prefix)
+        .into_iter()
+        .flat_map(|net| net.hosts());
+    hosts
+        .filter(move |host| *host != ip && !blacklist.contains(host))
+        .flat_map(move |host| {
+            let extra = (1024..=65535u16).filter(move |port| extended && *port != 25565);
+            std::iter::once(25565)
+                .chain(extra)
+                .map(move |port| SocketAddr::new(host.into(), port))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subnet_targets() {
+        let blacklist = Blacklist::default();
+        let ip = Ipv4Addr::new(10, 0, 0, 5);
+        let targets: Vec<_> = subnet_targets(ip, 30, false, &blacklist).collect();
+        assert_eq!(targets, vec!["10.0.0.6:25565".parse().unwrap()]);
+        let extended: Vec<_> = subnet_targets(ip, 30, true, &blacklist).collect();
+        assert_eq!(extended.len(), 65535 - 1024 + 1);
+        assert_eq!(extended[0].port(), 25565);
+        assert_eq!(extended[1].port(), 1024);
+    }
+
+    #[test]
+    fn test_queue_once_per_subnet() {
+        let (scan, mut rx) = IspScan::new(24);
+        scan.queue(Ipv4Addr::new(10, 0, 0, 5));
+        scan.queue(Ipv4Addr::new(10, 0, 0, 9));
+        scan.queue(Ipv4Addr::new(10, 0, 1, 5));
+        assert_eq!(rx.try_recv().unwrap(), Ipv4Addr::new(10, 0, 0, 5));
+        assert_eq!(rx.try_recv().unwrap(), Ipv4Addr::new(10, 0, 1, 5));
+        assert!(rx.try_recv().is_err());
     }
 }
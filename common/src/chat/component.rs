@@ -0,0 +1,415 @@
+//! Flattening of chat components into styled text spans.
+
+use serde_json::{Map, Value};
+
+use super::translate::{keybind, translation};
+
+/// Components come from untrusted servers, so nesting, the number of
+/// components visited (`%1$s` can repeat an argument) and output are bounded.
+const MAX_DEPTH: usize = 32;
+const MAX_COMPONENTS: usize = 8 * 1024;
+const MAX_CHARS: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+/// The 16 named colours, in legacy code order (`§0` to `§f`).
+pub const NAMED_COLORS: [(&str, Color); 16] = [
+    ("black", Color::rgb(0x000000)),
+    ("dark_blue", Color::rgb(0x0000AA)),
+    ("dark_green", Color::rgb(0x00AA00)),
+    ("dark_aqua", Color::rgb(0x00AAAA)),
+    ("dark_red", Color::rgb(0xAA0000)),
+    ("dark_purple", Color::rgb(0xAA00AA)),
+    ("gold", Color::rgb(0xFFAA00)),
+    ("gray", Color::rgb(0xAAAAAA)),
+    ("dark_gray", Color::rgb(0x555555)),
+    ("blue", Color::rgb(0x5555FF)),
+    ("green", Color::rgb(0x55FF55)),
+    ("aqua", Color::rgb(0x55FFFF)),
+    ("red", Color::rgb(0xFF5555)),
+    ("light_purple", Color::rgb(0xFF55FF)),
+    ("yellow", Color::rgb(0xFFFF55)),
+    ("white", Color::rgb(0xFFFFFF)),
+];
+
+impl Color {
+    pub const fn rgb(value: u32) -> Self {
+        Color {
+            r: (value >> 16) as u8,
+            g: (value >> 8) as u8,
+            b: value as u8,
+        }
+    }
+
+    /// A named colour or `#rrggbb`.
+    pub fn parse(name: &str) -> Option<Self> {
+        if let Some(hex) = name.strip_prefix('#') {
+            if hex.len() != 6 {
+                return None;
+            }
+            return u32::from_str_radix(hex, 16).ok().map(Color::rgb);
+        }
+        NAMED_COLORS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, c)| *c)
+    }
+
+    fn from_legacy(code: char) -> Option<Self> {
+        code.to_digit(16).map(|i| NAMED_COLORS[i as usize].1)
+    }
+
+    pub fn hex(&self) -> String {
+        format!("#{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Style {
+    pub color: Option<Color>,
+    pub bold: bool,
+    pub italic: bool,
+    pub underlined: bool,
+    pub strikethrough: bool,
+    pub obfuscated: bool,
+}
+
+impl Style {
+    /// This style with the fields a component sets itself, the rest is inherited.
+    fn with(mut self, component: &Map<String, Value>) -> Self {
+        if let Some(color) = component
+            .get("color")
+            .and_then(|v| v.as_str())
+            .and_then(Color::parse)
+        {
+            self.color = Some(color);
+        }
+        for (key, flag) in [
+            ("bold", &mut self.bold),
+            ("italic", &mut self.italic),
+            ("underlined", &mut self.underlined),
+            ("strikethrough", &mut self.strikethrough),
+            ("obfuscated", &mut self.obfuscated),
+        ] {
+            if let Some(value) = component.get(key).and_then(|v| v.as_bool()) {
+                *flag = value;
+            }
+        }
+        self
+    }
+
+    /// Apply a legacy `§` format code. Colour codes clear the formatting like
+    /// the vanilla client does, `§r` goes back to `base`.
+    fn legacy(self, code: char, base: Style) -> Option<Self> {
+        if let Some(color) = Color::from_legacy(code) {
+            return Some(Style {
+                color: Some(color),
+                ..Style::default()
+            });
+        }
+        let mut style = self;
+        match code.to_ascii_lowercase() {
+            'k' => style.obfuscated = true,
+            'l' => style.bold = true,
+            'm' => style.strikethrough = true,
+            'n' => style.underlined = true,
+            'o' => style.italic = true,
+            'r' => style = base,
+            _ => return None,
+        }
+        Some(style)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+/// Flatten a chat component (plain string, array or object) into styled spans.
+/// Adjacent spans with the same style are merged.
+pub fn flatten(value: &Value) -> Vec<Span> {
+    let mut flattener = Flattener {
+        spans: Vec::new(),
+        components: MAX_COMPONENTS,
+        budget: MAX_CHARS,
+    };
+    flattener.walk(value, Style::default(), 0);
+    flattener.spans
+}
+
+struct Flattener {
+    spans: Vec<Span>,
+    components: usize,
+    budget: usize,
+}
+
+impl Flattener {
+    fn walk(&mut self, value: &Value, parent: Style, depth: usize) {
+        if depth > MAX_DEPTH || self.components == 0 || self.budget == 0 {
+            return;
+        }
+        self.components -= 1;
+        match value {
+            Value::String(s) => self.legacy_text(s, parent),
+            Value::Number(n) => self.push(&n.to_string(), parent),
+            Value::Bool(b) => self.push(&b.to_string(), parent),
+            // The first element is the parent of the rest.
+            Value::Array(items) => {
+                let Some((first, rest)) = items.split_first() else {
+                    return;
+                };
+                self.walk(first, parent, depth + 1);
+                let base = match first {
+                    Value::Object(obj) => parent.with(obj),
+                    _ => parent,
+                };
+                for item in rest {
+                    self.walk(item, base, depth + 1);
+                }
+            }
+            Value::Object(obj) => {
+                let style = parent.with(obj);
+                self.content(obj, style, depth);
+                if let Some(extra) = obj.get("extra").and_then(|v| v.as_array()) {
+                    for e in extra {
+                        self.walk(e, style, depth + 1);
+                    }
+                }
+            }
+            Value::Null => {}
+        }
+    }
+
+    fn content(&mut self, obj: &Map<String, Value>, style: Style, depth: usize) {
+        if let Some(text) = obj.get("text") {
+            match text {
+                Value::String(s) => self.legacy_text(s, style),
+                Value::Number(_) | Value::Bool(_) => self.walk(text, style, depth + 1),
+                _ => {}
+            }
+        } else if let Some(key) = obj.get("translate").and_then(|v| v.as_str()) {
+            let format = translation(key)
+                .or_else(|| obj.get("fallback").and_then(|v| v.as_str()))
+                .unwrap_or(key);
+            let args = obj
+                .get("with")
+                .and_then(|v| v.as_array())
+                .map(Vec::as_slice)
+                .unwrap_or_default();
+            self.translate(format, args, style, depth);
+        } else if let Some(key) = obj.get("keybind").and_then(|v| v.as_str()) {
+            self.push(keybind(key), style);
+        } else if let Some(score) = obj.get("score") {
+            if let Some(value) = score.get("value").and_then(|v| v.as_str()) {
+                self.push(value, style);
+            }
+        } else if let Some(selector) = obj.get("selector").and_then(|v| v.as_str()) {
+            self.push(selector, style);
+        }
+    }
+
+    /// Fill `%s`, `%1$s` and `%%` in a translation with the `with` arguments.
+    fn translate(&mut self, format: &str, args: &[Value], style: Style, depth: usize) {
+        let mut next_arg = 0;
+        let mut rest = format;
+        while let Some(pos) = rest.find('%') {
+            self.legacy_text(&rest[..pos], style);
+            let spec = &rest[pos + 1..];
+            if let Some(after) = spec.strip_prefix('%') {
+                self.push("%", style);
+                rest = after;
+            } else if let Some(after) = spec.strip_prefix('s') {
+                if let Some(arg) = args.get(next_arg) {
+                    self.walk(arg, style, depth + 1);
+                }
+                next_arg += 1;
+                rest = after;
+            } else if let Some((index, after)) = spec.split_once("$s")
+                && let Ok(index) = index.parse::<usize>()
+            {
+                if let Some(arg) = index.checked_sub(1).and_then(|i| args.get(i)) {
+                    self.walk(arg, style, depth + 1);
+                }
+                rest = after;
+            } else {
+                self.push("%", style);
+                rest = spec;
+            }
+        }
+        self.legacy_text(rest, style);
+    }
+
+    /// Text that may contain legacy `§` codes, including BungeeCord's
+    /// `§x§r§r§g§g§b§b` hex colours.
+    fn legacy_text(&mut self, text: &str, base: Style) {
+        let mut style = base;
+        let mut chars = text.char_indices().peekable();
+        let mut start = 0;
+        while let Some((i, c)) = chars.next() {
+            if c != '§' {
+                continue;
+            }
+            self.push(&text[start..i], style);
+            let Some((_, code)) = chars.next() else {
+                start = text.len();
+                break;
+            };
+            if code.eq_ignore_ascii_case(&'x') {
+                let rest = &text[i + c.len_utf8() + code.len_utf8()..];
+                if let Some(color) = bungee_hex(rest) {
+                    style = Style {
+                        color: Some(color),
+                        ..Style::default()
+                    };
+                    for _ in 0..12 {
+                        chars.next();
+                    }
+                }
+            } else if let Some(next) = style.legacy(code, base) {
+                style = next;
+            }
+            start = chars.peek().map(|(i, _)| *i).unwrap_or(text.len());
+        }
+        self.push(&text[start..], style);
+    }
+
+    fn push(&mut self, text: &str, style: Style) {
+        if text.is_empty() || self.budget == 0 {
+            return;
+        }
+        let mut end = text.len().min(self.budget);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let text = &text[..end];
+        self.budget -= text.len();
+        match self.spans.last_mut() {
+            Some(last) if last.style == style => last.text.push_str(text),
+            _ => self.spans.push(Span {
+                text: text.to_string(),
+                style,
+            }),
+        }
+    }
+}
+
+/// The six `§<digit>` pairs following `§x`.
+fn bungee_hex(rest: &str) -> Option<Color> {
+    let mut chars = rest.chars();
+    let mut hex = String::with_capacity(6);
+    for _ in 0..6 {
+        if chars.next()? != '§' {
+            return None;
+        }
+        hex.push(chars.next().filter(|c| c.is_ascii_hexdigit())?);
+    }
+    u32::from_str_radix(&hex, 16).ok().map(Color::rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn red() -> Option<Color> {
+        Color::parse("red")
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let spans = flatten(&json!({
+            "text": "a",
+            "color": "red",
+            "bold": true,
+            "extra": ["b", {"text": "c", "bold": false, "color": "#123456"}, {"text": "d", "italic": true}]
+        }));
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[0].text, "ab");
+        assert_eq!((spans[0].style.color, spans[0].style.bold), (red(), true));
+        assert_eq!(spans[1].style.color, Some(Color::rgb(0x123456)));
+        assert!(!spans[1].style.bold);
+        assert!(spans[2].style.bold && spans[2].style.italic);
+        assert_eq!(spans[2].style.color, red());
+    }
+
+    #[test]
+    fn test_array_first_is_parent() {
+        let spans = flatten(&json!([{"text": "x", "color": "gold"}, "y", 3, true]));
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].text, "xy3true");
+        assert_eq!(spans[0].style.color, Color::parse("gold"));
+    }
+
+    #[test]
+    fn test_legacy_codes() {
+        let spans = flatten(&json!({"text": "§lA§cB§oC§rD§zE§", "color": "blue"}));
+        let texts: Vec<_> = spans.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["A", "B", "C", "DE"]);
+        assert!(spans[0].style.bold);
+        assert_eq!(spans[0].style.color, Color::parse("blue"));
+        // Colour codes clear the formatting.
+        assert_eq!(spans[1].style.color, red());
+        assert!(!spans[1].style.bold);
+        assert!(spans[2].style.italic);
+        assert_eq!(
+            spans[3].style,
+            Style::default().with(json!({"color": "blue"}).as_object().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_bungee_hex() {
+        let spans = flatten(&json!("§x§f§f§0§0§a§aPink §x§zbroken"));
+        // A broken hex colour and the unknown code are dropped, the colour stays.
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].text, "Pink broken");
+        assert_eq!(spans[0].style.color, Some(Color::rgb(0xFF00AA)));
+    }
+
+    #[test]
+    fn test_translate() {
+        let spans = flatten(&json!({
+            "translate": "multiplayer.disconnect.outdated_client",
+            "with": [{"text": "1.20.4", "color": "red"}]
+        }));
+        let text: String = spans.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(text, "Incompatible client! Please use 1.20.4");
+        assert_eq!(spans[1].style.color, red());
+
+        let text = |value| -> String { flatten(&value).into_iter().map(|s| s.text).collect() };
+        assert_eq!(
+            text(
+                json!({"translate": "custom.key", "fallback": "%2$s and %1$s, 100%%", "with": ["a", "b"]})
+            ),
+            "b and a, 100%"
+        );
+        assert_eq!(text(json!({"translate": "unknown.key"})), "unknown.key");
+        assert_eq!(text(json!({"keybind": "key.jump"})), "Space");
+        assert_eq!(
+            text(json!({"score": {"name": "x", "objective": "y", "value": "42"}})),
+            "42"
+        );
+    }
+
+    #[test]
+    fn test_bounded() {
+        // Every level doubles the work, and nothing below MAX_DEPTH is output.
+        for leaf in [json!("ab"), json!({"text": "deep"})] {
+            let mut value = leaf;
+            for _ in 0..40 {
+                value = json!({"translate": "%1$s%1$s", "with": [value]});
+            }
+            let spans = flatten(&value);
+            let len: usize = spans.iter().map(|s| s.text.len()).sum();
+            assert!(len <= MAX_CHARS);
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! Chat components (MOTDs, disconnect reasons) rendered to plain text, ANSI
+//! and HTML.
+
+use serde_json::Value;
+
+pub mod component;
+pub mod render;
+mod translate;
+
+/// Flatten a chat component (MOTD, disconnect reason) into plain text.
+pub fn parse_description(value: &Value) -> String {
+    render::plain(&component::flatten(value))
+}
+
+/// A chat component as HTML, stored next to the plain text for consumers that show colours.
+pub fn description_html(value: &Value) -> String {
+    render::html(&component::flatten(value))
+}
+
+/// A chat component with ANSI colours, for terminal output.
+pub fn description_ansi(value: &Value) -> String {
+    render::ansi(&component::flatten(value))
+}
@@ -0,0 +1,139 @@
+//! Output formats for flattened chat components.
+
+use std::fmt::Write as _;
+
+use super::component::{Span, Style};
+
+pub fn plain(spans: &[Span]) -> String {
+    spans.iter().map(|s| s.text.as_str()).collect()
+}
+
+/// 24-bit ANSI escapes, for terminals.
+pub fn ansi(spans: &[Span]) -> String {
+    let mut out = String::new();
+    let mut current = Style::default();
+    for span in spans {
+        if span.style != current {
+            out.push_str("\x1b[0m");
+            if let Some(c) = span.style.color {
+                let _ = write!(out, "\x1b[38;2;{};{};{}m", c.r, c.g, c.b);
+            }
+            for (on, code) in [
+                (span.style.bold, 1),
+                (span.style.italic, 3),
+                (span.style.underlined, 4),
+                (span.style.strikethrough, 9),
+            ] {
+                if on {
+                    let _ = write!(out, "\x1b[{}m", code);
+                }
+            }
+            current = span.style;
+        }
+        out.push_str(&span.text);
+    }
+    if current != Style::default() {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+/// Escaped HTML with inline styles, obfuscated text gets the `mc-obfuscated` class.
+pub fn html(spans: &[Span]) -> String {
+    let mut out = String::new();
+    for span in spans {
+        let style = span.style;
+        let mut css = Vec::new();
+        if let Some(c) = style.color {
+            css.push(format!("color:{}", c.hex()));
+        }
+        if style.bold {
+            css.push("font-weight:bold".to_string());
+        }
+        if style.italic {
+            css.push("font-style:italic".to_string());
+        }
+        let decorations: Vec<&str> = [
+            (style.underlined, "underline"),
+            (style.strikethrough, "line-through"),
+        ]
+        .into_iter()
+        .filter_map(|(on, name)| on.then_some(name))
+        .collect();
+        if !decorations.is_empty() {
+            css.push(format!("text-decoration:{}", decorations.join(" ")));
+        }
+        let styled = !css.is_empty() || style.obfuscated;
+        if styled {
+            out.push_str("<span");
+            if style.obfuscated {
+                out.push_str(" class=\"mc-obfuscated\"");
+            }
+            if !css.is_empty() {
+                let _ = write!(out, " style=\"{}\"", css.join(";"));
+            }
+            out.push('>');
+        }
+        escape_html(&mut out, &span.text);
+        if styled {
+            out.push_str("</span>");
+        }
+    }
+    out
+}
+
+fn escape_html(out: &mut String, text: &str) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            '\n' => out.push_str("<br>"),
+            _ => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::chat::component::flatten;
+
+    fn motd() -> Vec<Span> {
+        flatten(&json!({
+            "text": "",
+            "extra": [
+                {"text": "Hi <all>", "color": "red", "bold": true},
+                "\n§nplain & §kx"
+            ]
+        }))
+    }
+
+    #[test]
+    fn test_plain() {
+        assert_eq!(plain(&motd()), "Hi <all>\nplain & x");
+    }
+
+    #[test]
+    fn test_ansi() {
+        assert_eq!(
+            ansi(&motd()),
+            "\x1b[0m\x1b[38;2;255;85;85m\x1b[1mHi <all>\x1b[0m\n\x1b[0m\x1b[4mplain & \x1b[0m\x1b[4mx\x1b[0m"
+        );
+        assert_eq!(ansi(&flatten(&json!("no style"))), "no style");
+    }
+
+    #[test]
+    fn test_html() {
+        assert_eq!(
+            html(&motd()),
+            "<span style=\"color:#ff5555;font-weight:bold\">Hi &lt;all&gt;</span><br>\
+             <span style=\"text-decoration:underline\">plain &amp; </span>\
+             <span class=\"mc-obfuscated\" style=\"text-decoration:underline\">x</span>"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! English strings for the translation keys servers commonly send in MOTDs
+//! and disconnect reasons. Anything else falls back to `fallback` or the key.
+
+const TRANSLATIONS: &[(&str, &str)] = &[
+    ("disconnect.closed", "Connection closed"),
+    ("disconnect.timeout", "Timed out"),
+    (
+        "multiplayer.disconnect.authservers_down",
+        "Authentication servers are down. Please try again later, sorry!",
+    ),
+    (
+        "multiplayer.disconnect.banned",
+        "You are banned from this server.",
+    ),
+    (
+        "multiplayer.disconnect.banned.expiration",
+        "\nYour ban will be removed on %s",
+    ),
+    (
+        "multiplayer.disconnect.banned.reason",
+        "You are banned from this server.\nReason: %s",
+    ),
+    (
+        "multiplayer.disconnect.banned_ip.expiration",
+        "\nYour ban will be removed on %s",
+    ),
+    (
+        "multiplayer.disconnect.banned_ip.reason",
+        "Your IP address is banned from this server.\nReason: %s",
+    ),
+    (
+        "multiplayer.disconnect.duplicate_login",
+        "You logged in from another location",
+    ),
+    (
+        "multiplayer.disconnect.incompatible",
+        "Incompatible client! Please use %s",
+    ),
+    ("multiplayer.disconnect.kicked", "Kicked by an operator"),
+    (
+        "multiplayer.disconnect.name_taken",
+        "That name is already taken",
+    ),
+    (
+        "multiplayer.disconnect.not_whitelisted",
+        "You are not white-listed on this server!",
+    ),
+    (
+        "multiplayer.disconnect.outdated_client",
+        "Incompatible client! Please use %s",
+    ),
+    (
+        "multiplayer.disconnect.outdated_server",
+        "Incompatible client! Please use %s",
+    ),
+    ("multiplayer.disconnect.server_full", "The server is full!"),
+    ("multiplayer.disconnect.server_shutdown", "Server closed"),
+    (
+        "multiplayer.disconnect.unverified_username",
+        "Failed to verify username!",
+    ),
+];
+
+const KEYBINDS: &[(&str, &str)] = &[
+    ("key.attack", "Left Button"),
+    ("key.back", "S"),
+    ("key.chat", "T"),
+    ("key.command", "/"),
+    ("key.drop", "Q"),
+    ("key.forward", "W"),
+    ("key.inventory", "E"),
+    ("key.jump", "Space"),
+    ("key.left", "A"),
+    ("key.playerlist", "Tab"),
+    ("key.right", "D"),
+    ("key.sneak", "Left Shift"),
+    ("key.sprint", "Left Control"),
+    ("key.swapOffhand", "F"),
+    ("key.use", "Right Button"),
+];
+
+pub fn translation(key: &str) -> Option<&'static str> {
+    TRANSLATIONS
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| *v)
+}
+
+/// The default binding of a key, or the key name itself.
+pub fn keybind(key: &str) -> &str {
+    KEYBINDS
+        .iter()
+        .find(|(k, _)| *k == key)
+        .map(|(_, v)| *v)
+        .unwrap_or(key)
+}
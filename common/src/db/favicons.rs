@@ -0,0 +1,122 @@
+use std::path::Path;
+
+use futures_util::{TryStreamExt, pin_mut};
+
+use crate::favicon::{Favicon, decode_favicon};
+
+/// Point a server at its favicon (or at none) and move one reference from the
+/// old favicon to the new one. Nothing changes if the favicon is the same.
+pub async fn save_favicon(addr: &str, favicon: Option<&Favicon>, client: &tokio_postgres::Client) {
+    if let Some(favicon) = favicon
+        && let Err(e) = client
+            .execute(
+                r#"
+                    INSERT INTO favicons (hash, png, phash)
+                    VALUES ($1, $2, $3)
+                    ON CONFLICT (hash) DO NOTHING
+                "#,
+                &[&favicon.hash, &favicon.png, &favicon.phash],
+            )
+            .await
+    {
+        tracing::error!("Error saving favicon for {}: {}", addr, e);
+        return;
+    }
+    let hash = favicon.map(|f| f.hash.as_str());
+    if let Err(e) = client
+        .execute(
+            r#"
+                WITH changed AS (
+                    UPDATE servers s
+                    SET favicon_hash = $2
+                    FROM (SELECT id, favicon_hash AS old_hash FROM servers WHERE ip = $1 FOR UPDATE) o
+                    WHERE s.id = o.id AND o.old_hash IS DISTINCT FROM $2
+                    RETURNING o.old_hash
+                ), released AS (
+                    UPDATE favicons SET refs = refs - 1
+                    WHERE hash IN (SELECT old_hash FROM changed)
+                )
+                UPDATE favicons SET refs = refs + 1, last_seen = NOW()
+                WHERE hash = $2 AND EXISTS (SELECT 1 FROM changed)
+            "#,
+            &[&addr, &hash],
+        )
+        .await
+    {
+        tracing::error!("Error linking favicon for {}: {}", addr, e);
+    }
+}
+
+/// Servers read per query by [`backfill_favicons`], data URIs are large.
+const BACKFILL_BATCH: i64 = 500;
+
+/// Move the valid favicons still stored raw in `servers.favicon`, from before
+/// the `favicons` table, to the table. Invalid ones stay. Returns the number
+/// of servers moved.
+pub async fn backfill_favicons(
+    client: &tokio_postgres::Client,
+) -> Result<u64, tokio_postgres::Error> {
+    let mut moved = 0;
+    let mut last_id = 0;
+    loop {
+        let rows = client
+            .query(
+                r#"
+                    SELECT id, ip, favicon FROM servers
+                    WHERE favicon IS NOT NULL AND id > $1
+                    ORDER BY id
+                    LIMIT $2
+                "#,
+                &[&last_id, &BACKFILL_BATCH],
+            )
+            .await?;
+        let Some(last) = rows.last() else {
+            return Ok(moved);
+        };
+        last_id = last.get("id");
+        for row in &rows {
+            let Ok(favicon) = decode_favicon(row.get("favicon")) else {
+                continue;
+            };
+            save_favicon(row.get("ip"), Some(&favicon), client).await;
+            // Only once the server points at the favicon, save_favicon logs
+            // its errors instead of returning them.
+            moved += client
+                .execute(
+                    "UPDATE servers SET favicon = NULL WHERE id = $1 AND favicon_hash = $2",
+                    &[&row.get::<_, i32>("id"), &favicon.hash],
+                )
+                .await?;
+        }
+    }
+}
+
+/// Write every favicon used by at least `min_refs` servers to
+/// `<dir>/<hash>.png`. Returns the number of files written.
+pub async fn dump_favicons(
+    client: &tokio_postgres::Client,
+    dir: &Path,
+    min_refs: i32,
+) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+    tokio::fs::create_dir_all(dir).await?;
+    let rows = client
+        .query_raw(
+            "SELECT hash, png FROM favicons WHERE refs >= $1",
+            &[&min_refs],
+        )
+        .await?;
+    pin_mut!(rows);
+    let mut written = 0;
+    while let Some(row) = rows.try_next().await? {
+        let hash: String = row.get(0);
+        // Only ever written by save_favicon, but it ends up in a path.
+        if !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            tracing::warn!("Skipping favicon with bad hash {:?}", hash);
+            continue;
+        }
+        let png: Vec<u8> = row.get(1);
+        tokio::fs::write(dir.join(format!("{}.png", hash)), png).await?;
+        written += 1;
+    }
+    Ok(written)
+}
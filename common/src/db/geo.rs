@@ -0,0 +1,67 @@
+use std::net::IpAddr;
+
+use crate::geo::{Geo, GeoInfo};
+
+/// Rows updated per statement by [`backfill_geo`].
+const BACKFILL_BATCH: usize = 1000;
+
+/// Look up every server with a typed address again and store the result.
+/// Fields the databases do not know keep their stored value, like in the save
+/// path. Returns the number of servers the databases knew anything about.
+pub async fn backfill_geo(
+    client: &tokio_postgres::Client,
+    geo: &Geo,
+) -> Result<u64, tokio_postgres::Error> {
+    let rows = client
+        .query("SELECT id, addr FROM servers WHERE addr IS NOT NULL", &[])
+        .await?;
+    let statement = client
+        .prepare(
+            r#"
+                UPDATE servers s SET
+                    country = COALESCE(t.country, s.country),
+                    city = COALESCE(t.city, s.city),
+                    asn = COALESCE(t.asn, s.asn),
+                    as_org = COALESCE(t.as_org, s.as_org)
+                FROM UNNEST($1::int[], $2::text[], $3::text[], $4::bigint[], $5::text[])
+                    AS t (id, country, city, asn, as_org)
+                WHERE s.id = t.id
+            "#,
+        )
+        .await?;
+    let mut updated = 0;
+    for chunk in rows.chunks(BACKFILL_BATCH) {
+        let mut id = Vec::with_capacity(chunk.len());
+        let mut country = Vec::with_capacity(chunk.len());
+        let mut city = Vec::with_capacity(chunk.len());
+        let mut asn = Vec::with_capacity(chunk.len());
+        let mut as_org = Vec::with_capacity(chunk.len());
+        for row in chunk {
+            let info = geo.lookup(row.get::<_, IpAddr>("addr"));
+            if info == GeoInfo::default() {
+                continue;
+            }
+            id.push(row.get::<_, i32>("id"));
+            country.push(info.country);
+            city.push(info.city);
+            asn.push(info.asn.map(i64::from));
+            as_org.push(info.as_org);
+        }
+        updated += client
+            .execute(&statement, &[&id, &country, &city, &asn, &as_org])
+            .await?;
+    }
+    Ok(updated)
+}
+
+/// `geo-backfill`: fill in the geo columns of the existing servers.
+pub async fn run_backfill(client: &tokio_postgres::Client, geo: &Geo) {
+    if !geo.is_enabled() {
+        eprintln!("no GeoIP database configured, set geoip_city_db, geoip_asn_db or iptoasn_file");
+        return;
+    }
+    match backfill_geo(client, geo).await {
+        Ok(n) => println!("updated {} servers", n),
+        Err(e) => eprintln!("Geo backfill failed: {}", e),
+    }
+}
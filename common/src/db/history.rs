@@ -0,0 +1,272 @@
+use std::{collections::HashMap, time::SystemTime};
+
+use crate::db::{
+    servers::{ServerRow, UpsertedServer},
+    structs::Version,
+};
+
+/// What `servers` held before an update, snapshots store what changed from it.
+pub struct Previous {
+    pub version: Option<Version>,
+    pub software: Option<String>,
+    pub description_hash: Option<String>,
+}
+
+/// Which text fields of a snapshot differ from the previous state.
+#[derive(Debug, PartialEq, Default)]
+struct Changes {
+    version: bool,
+    software: bool,
+    description: bool,
+}
+
+pub fn description_hash(description: Option<&str>) -> Option<String> {
+    description.map(|d| format!("{:x}", md5::compute(d)))
+}
+
+fn changes(
+    previous: Option<&Previous>,
+    row: &ServerRow,
+    description_hash: &Option<String>,
+) -> Changes {
+    let software = row.software.map(|(name, _, _)| name);
+    match previous {
+        None => Changes {
+            version: true,
+            software: software.is_some(),
+            description: true,
+        },
+        Some(previous) => Changes {
+            version: previous.version.as_ref() != row.version,
+            // `None` means this caller does not fingerprint, not "no software".
+            software: software.is_some() && previous.software.as_deref() != software,
+            description: previous.description_hash != *description_hash,
+        },
+    }
+}
+
+/// Add a snapshot for every upserted server.
+pub(crate) async fn save_snapshots(
+    rows: &[&ServerRow<'_>],
+    servers: &HashMap<String, UpsertedServer>,
+    previous: &HashMap<String, Previous>,
+    client: &tokio_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    let mut server_id = Vec::with_capacity(rows.len());
+    let mut online = Vec::with_capacity(rows.len());
+    let mut max = Vec::with_capacity(rows.len());
+    let mut latency_ms = Vec::with_capacity(rows.len());
+    let mut version_changed = Vec::with_capacity(rows.len());
+    let mut version_name = Vec::with_capacity(rows.len());
+    let mut protocol = Vec::with_capacity(rows.len());
+    let mut software_changed = Vec::with_capacity(rows.len());
+    let mut software = Vec::with_capacity(rows.len());
+    let mut description_changed = Vec::with_capacity(rows.len());
+    let mut description = Vec::with_capacity(rows.len());
+    for row in rows {
+        let Some(server) = servers.get(row.addr) else {
+            continue;
+        };
+        let hash = description_hash(row.description);
+        let changes = changes(previous.get(row.addr), row, &hash);
+        server_id.push(server.id);
+        online.push(row.players.and_then(|p| p.online));
+        max.push(row.players.and_then(|p| p.max));
+        latency_ms.push(row.latency.as_millis().min(i32::MAX as u128) as i32);
+        version_changed.push(changes.version);
+        version_name.push(
+            row.version
+                .filter(|_| changes.version)
+                .and_then(|v| v.name.as_deref()),
+        );
+        protocol.push(
+            row.version
+                .filter(|_| changes.version)
+                .and_then(|v| v.protocol),
+        );
+        software_changed.push(changes.software);
+        software.push(row.software.filter(|_| changes.software).map(|s| s.0));
+        description_changed.push(changes.description);
+        description.push(hash.filter(|_| changes.description));
+    }
+    client
+        .execute(
+            r#"
+                INSERT INTO server_snapshots (
+                    server_id, online, max, latency_ms, changed,
+                    version_name, protocol, software, description_hash
+                )
+                SELECT
+                    server_id, online, max, latency_ms,
+                    array_remove(ARRAY[
+                        CASE WHEN version_changed THEN 'version' END,
+                        CASE WHEN software_changed THEN 'software' END,
+                        CASE WHEN description_changed THEN 'description' END
+                    ], NULL),
+                    version_name, protocol, software, description_hash
+                FROM UNNEST(
+                    $1::int[], $2::int[], $3::int[], $4::int[], $5::bool[], $6::text[],
+                    $7::int[], $8::bool[], $9::text[], $10::bool[], $11::text[]
+                ) AS t (
+                    server_id, online, max, latency_ms, version_changed, version_name,
+                    protocol, software_changed, software, description_changed, description_hash
+                )
+            "#,
+            &[
+                &server_id,
+                &online,
+                &max,
+                &latency_ms,
+                &version_changed,
+                &version_name,
+                &protocol,
+                &software_changed,
+                &software,
+                &description_changed,
+                &description,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Recompute the hourly rollups from `since` on, the daily ones from the
+/// hourly ones, and delete snapshots older than `retention_days` that did not
+/// change anything. `since` is the start of the previous run, `None` rolls up
+/// everything.
+pub async fn roll_up_snapshots(
+    client: &tokio_postgres::Client,
+    since: Option<SystemTime>,
+    retention_days: i32,
+) -> Result<(), tokio_postgres::Error> {
+    let since = since.unwrap_or(SystemTime::UNIX_EPOCH);
+    client
+        .execute(
+            r#"
+                INSERT INTO server_rollups (
+                    server_id, period, bucket, samples, online_avg, online_min,
+                    online_max, max_players, latency_avg
+                )
+                SELECT
+                    server_id, 'hour', date_trunc('hour', at), COUNT(*), AVG(online)::real,
+                    MIN(online), MAX(online), MAX(max), AVG(latency_ms)::real
+                FROM server_snapshots
+                WHERE at >= date_trunc('hour', $1::timestamptz)
+                GROUP BY server_id, date_trunc('hour', at)
+                ON CONFLICT (server_id, period, bucket) DO UPDATE SET
+                    samples = EXCLUDED.samples,
+                    online_avg = EXCLUDED.online_avg,
+                    online_min = EXCLUDED.online_min,
+                    online_max = EXCLUDED.online_max,
+                    max_players = EXCLUDED.max_players,
+                    latency_avg = EXCLUDED.latency_avg
+            "#,
+            &[&since],
+        )
+        .await?;
+    client
+        .execute(
+            r#"
+                INSERT INTO server_rollups (
+                    server_id, period, bucket, samples, online_avg, online_min,
+                    online_max, max_players, latency_avg
+                )
+                SELECT
+                    server_id, 'day', date_trunc('day', bucket), SUM(samples),
+                    (SUM(online_avg * samples) / SUM(samples))::real,
+                    MIN(online_min), MAX(online_max), MAX(max_players),
+                    (SUM(latency_avg * samples) / SUM(samples))::real
+                FROM server_rollups
+                WHERE period = 'hour' AND bucket >= date_trunc('day', $1::timestamptz)
+                GROUP BY server_id, date_trunc('day', bucket)
+                ON CONFLICT (server_id, period, bucket) DO UPDATE SET
+                    samples = EXCLUDED.samples,
+                    online_avg = EXCLUDED.online_avg,
+                    online_min = EXCLUDED.online_min,
+                    online_max = EXCLUDED.online_max,
+                    max_players = EXCLUDED.max_players,
+                    latency_avg = EXCLUDED.latency_avg
+            "#,
+            &[&since],
+        )
+        .await?;
+    // Snapshots with changes are the version / software / MOTD history, keep them.
+    client
+        .execute(
+            r#"
+                DELETE FROM server_snapshots
+                WHERE at < NOW() - make_interval(days => $1) AND changed = '{}'
+            "#,
+            &[&retention_days],
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::versions::VersionRange;
+
+    #[test]
+    fn test_changes() {
+        let version = Version {
+            name: Some("Paper 1.21".into()),
+            protocol: Some(767),
+        };
+        let range = VersionRange::default();
+        let extra = Value::Null;
+        let row = ServerRow {
+            addr: "1.2.3.4:25565",
+            description: Some("A Minecraft Server"),
+            description_html: None,
+            raw_description: None,
+            players: None,
+            version: Some(&version),
+            version_range: &range,
+            favicon: None,
+            enforces_secure_chat: None,
+            extra: &extra,
+            software: None,
+            latency: Default::default(),
+            geo: None,
+        };
+        let hash = description_hash(row.description);
+
+        // First snapshot of a server has everything it knows.
+        assert_eq!(
+            changes(None, &row, &hash),
+            Changes {
+                version: true,
+                software: false,
+                description: true,
+            }
+        );
+
+        let mut previous = Previous {
+            version: Some(version.clone()),
+            software: Some("paper".into()),
+            description_hash: hash.clone(),
+        };
+        assert_eq!(changes(Some(&previous), &row, &hash), Changes::default());
+
+        previous.version = Some(Version {
+            name: Some("Paper 1.20.4".into()),
+            protocol: Some(765),
+        });
+        let row = ServerRow {
+            software: Some(("purpur", false, 0.8)),
+            ..row
+        };
+        assert_eq!(
+            changes(Some(&previous), &row, &hash),
+            Changes {
+                version: true,
+                software: true,
+                description: false,
+            }
+        );
+    }
+}
@@ -0,0 +1,7 @@
+use crate::db::migrations::{MigrationError, migrate};
+
+/// Bring the schema up to date, see [`crate::db::migrations`].
+pub async fn db_init(client: &tokio_postgres::Client) -> Result<(), MigrationError> {
+    migrate(client, false).await?;
+    Ok(())
+}
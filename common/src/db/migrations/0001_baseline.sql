@@ -0,0 +1,160 @@
+-- Schema as created by db_init before migrations existed. Every statement is
+-- idempotent so deployments from that time record it without changes.
+
+-- Conditionally create custom types if they do not exist
+DO $$ BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'action_type') THEN
+        CREATE TYPE action_type AS ENUM ('JOINED', 'LEFT');
+    END IF;
+END$$;
+DO $$ BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'player') THEN
+        CREATE TYPE player AS (
+            name TEXT,
+            id TEXT
+        );
+    END IF;
+END$$;
+DO $$ BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'version') THEN
+        CREATE TYPE version AS (
+            name TEXT,
+            protocol INTEGER
+        );
+    END IF;
+END$$;
+DO $$ BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'players') THEN
+        CREATE TYPE players AS (
+            max INTEGER,
+            online INTEGER,
+            sample player[]
+        );
+    END IF;
+END$$;
+
+-- Create servers table
+CREATE TABLE IF NOT EXISTS servers (
+    id SERIAL PRIMARY KEY,
+    ip TEXT NOT NULL UNIQUE,
+    description TEXT,
+    raw_description JSONB,
+    players players,
+    version version,
+    favicon TEXT,
+    enforces_secure_chat BOOLEAN,
+    extra JSONB,
+    last_pinged TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Deduplicated favicons, servers point at them by content hash
+CREATE TABLE IF NOT EXISTS favicons (
+    hash TEXT PRIMARY KEY,
+    png BYTEA NOT NULL,
+    phash BIGINT NOT NULL,
+    refs INTEGER NOT NULL DEFAULT 0,
+    first_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS favicon_hash TEXT;
+
+-- Description rendered with colours and formatting
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS description_html TEXT;
+
+-- Login-state probe results (opt-in)
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS login_state TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS disconnect_reason TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS login_checked TIMESTAMPTZ;
+
+-- Forge / NeoForge mod list summary
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS mod_loader TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS mods_truncated BOOLEAN;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS mod_channels JSONB;
+
+-- Server software fingerprint
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS software TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS software_proxy BOOLEAN;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS software_confidence REAL;
+
+-- Normalized supported version range
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS version_min TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS version_max TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS protocol_min INTEGER;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS protocol_max INTEGER;
+
+-- Honeypot / fake server detection
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS response_hash TEXT;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS latency_ms INTEGER;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS suspicion REAL;
+ALTER TABLE servers ADD COLUMN IF NOT EXISTS suspicion_reasons TEXT[];
+
+-- Create server mods table
+CREATE TABLE IF NOT EXISTS server_mods (
+    id SERIAL PRIMARY KEY,
+    server_id INTEGER NOT NULL REFERENCES servers(id),
+    mod_id TEXT NOT NULL,
+    version TEXT,
+    channels JSONB,
+    UNIQUE (server_id, mod_id)
+);
+
+-- Create player list table
+CREATE TABLE IF NOT EXISTS player_list (
+    id SERIAL PRIMARY KEY,
+    name TEXT NOT NULL,
+    uuid TEXT NOT NULL,
+    cracked BOOLEAN NOT NULL,
+    UNIQUE (uuid, name)
+);
+
+-- Create player actions table
+CREATE TABLE IF NOT EXISTS player_actions (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES player_list(id),
+    server_id INTEGER NOT NULL REFERENCES servers(id),
+    action action_type NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create validator status table (for validator only)
+CREATE TABLE IF NOT EXISTS validator_status (
+    id SERIAL PRIMARY KEY,
+    ips_validated INTEGER NOT NULL,
+    ips_active INTEGER NOT NULL,
+    ips_validated_list TEXT[] NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Create scanner status table (for scanner only)
+CREATE TABLE IF NOT EXISTS status (
+    id SERIAL PRIMARY KEY,
+    ips_scanned INTEGER NOT NULL,
+    ips_active INTEGER NOT NULL,
+    ips_active_list TEXT[] NOT NULL,
+    timestamp TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Probe outcome counters per /24, port and outcome
+CREATE TABLE IF NOT EXISTS scan_results (
+    subnet TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    outcome TEXT NOT NULL,
+    count BIGINT NOT NULL DEFAULT 0,
+    last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (subnet, port, outcome)
+);
+
+-- Create index on player_list for faster lookups
+CREATE INDEX IF NOT EXISTS idx_player_list_name_uuid ON player_list (name, uuid);
+
+-- Create index on servers for "supports version X" range queries
+CREATE INDEX IF NOT EXISTS idx_servers_protocol_range ON servers (protocol_min, protocol_max);
+
+-- Create index on servers for response clustering
+CREATE INDEX IF NOT EXISTS idx_servers_response_hash ON servers (response_hash);
+
+-- Create index on server_mods for "which servers run mod X" lookups
+CREATE INDEX IF NOT EXISTS idx_server_mods_mod_id ON server_mods (mod_id);
+
+-- Create index on servers for "who uses favicon X" lookups
+CREATE INDEX IF NOT EXISTS idx_servers_favicon_hash ON servers (favicon_hash);
@@ -0,0 +1,33 @@
+-- One row per successful ping. Text fields are only filled in when they
+-- changed since the previous snapshot, `changed` lists which ones did.
+CREATE TABLE server_snapshots (
+    id BIGSERIAL PRIMARY KEY,
+    server_id INTEGER NOT NULL REFERENCES servers(id),
+    at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    online INTEGER,
+    max INTEGER,
+    latency_ms INTEGER,
+    changed TEXT[] NOT NULL DEFAULT '{}',
+    version_name TEXT,
+    protocol INTEGER,
+    software TEXT,
+    description_hash TEXT
+);
+CREATE INDEX idx_server_snapshots_server_at ON server_snapshots (server_id, at);
+CREATE INDEX idx_server_snapshots_at ON server_snapshots (at);
+
+-- Hourly and daily aggregates of server_snapshots, kept after the raw
+-- snapshots are pruned.
+CREATE TABLE server_rollups (
+    server_id INTEGER NOT NULL REFERENCES servers(id),
+    period TEXT NOT NULL,
+    bucket TIMESTAMPTZ NOT NULL,
+    samples INTEGER NOT NULL,
+    online_avg REAL,
+    online_min INTEGER,
+    online_max INTEGER,
+    max_players INTEGER,
+    latency_avg REAL,
+    PRIMARY KEY (server_id, period, bucket)
+);
+CREATE INDEX idx_server_rollups_period_bucket ON server_rollups (period, bucket);
@@ -0,0 +1,18 @@
+-- servers.ip stays the "1.2.3.4:25565" key, addr / port are the same address
+-- typed so it can be queried by subnet, e.g. addr << '1.2.0.0/16'.
+ALTER TABLE servers ADD COLUMN addr INET;
+ALTER TABLE servers ADD COLUMN port INTEGER;
+
+UPDATE servers
+SET addr = split_part(ip, ':', 1)::inet,
+    port = COALESCE(NULLIF(split_part(ip, ':', 2), ''), '25565')::integer
+-- The regex lets octets like 999 through, the cast would fail on them. CASE
+-- makes sure the octets are only checked once they are known to be digits.
+WHERE CASE WHEN ip ~ '^[0-9]{1,3}(\.[0-9]{1,3}){3}(:[0-9]{1,5})?$' THEN
+    (SELECT bool_and(octet::integer <= 255)
+        FROM unnest(string_to_array(split_part(ip, ':', 1), '.')) AS octet)
+    AND COALESCE(NULLIF(split_part(ip, ':', 2), ''), '0')::integer <= 65535
+ELSE FALSE END;
+
+CREATE INDEX idx_servers_addr ON servers USING gist (addr inet_ops);
+CREATE INDEX idx_servers_port ON servers (port);
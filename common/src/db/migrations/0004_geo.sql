@@ -0,0 +1,8 @@
+-- GeoIP / ASN enrichment from the configured local databases
+ALTER TABLE servers ADD COLUMN country TEXT;
+ALTER TABLE servers ADD COLUMN city TEXT;
+ALTER TABLE servers ADD COLUMN asn BIGINT;
+ALTER TABLE servers ADD COLUMN as_org TEXT;
+
+CREATE INDEX idx_servers_country ON servers (country);
+CREATE INDEX idx_servers_asn ON servers (asn);
@@ -0,0 +1,5 @@
+-- Reverse DNS (PTR) name of the server address, refreshed by the verifier
+ALTER TABLE servers ADD COLUMN rdns TEXT;
+ALTER TABLE servers ADD COLUMN rdns_checked TIMESTAMPTZ;
+
+CREATE INDEX idx_servers_rdns_checked ON servers (rdns_checked NULLS FIRST);
@@ -0,0 +1,57 @@
+-- Play sessions of a player on a server, paired from the sample diffs. Open
+-- sessions have no end yet, last_seen is the last sample they were in.
+-- observed is false when the end was inferred (server offline, player no
+-- longer sampled) instead of seen in a sample.
+CREATE TABLE player_sessions (
+    id BIGSERIAL PRIMARY KEY,
+    server_id INTEGER NOT NULL REFERENCES servers(id),
+    player_id INTEGER NOT NULL REFERENCES player_list(id),
+    started TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    ended TIMESTAMPTZ,
+    open BOOLEAN NOT NULL DEFAULT TRUE,
+    observed BOOLEAN NOT NULL DEFAULT TRUE
+);
+CREATE UNIQUE INDEX idx_player_sessions_open ON player_sessions (server_id, player_id) WHERE open;
+CREATE INDEX idx_player_sessions_player ON player_sessions (player_id, started);
+CREATE INDEX idx_player_sessions_server ON player_sessions (server_id, started);
+CREATE INDEX idx_player_sessions_last_seen ON player_sessions (last_seen) WHERE open;
+
+-- Pair the existing actions. The actions up to and including a LEFT (an
+-- island) are one session, from its first JOINED on, so repeated JOINEDs do
+-- not shorten it. An island without a LEFT is a session that is still open.
+INSERT INTO player_sessions (server_id, player_id, started, last_seen, ended, open)
+SELECT server_id, user_id, first_join, COALESCE(left_at, last_join), left_at, left_at IS NULL
+FROM (
+    SELECT server_id, user_id,
+        MIN(timestamp) FILTER (WHERE action = 'JOINED') AS first_join,
+        MAX(timestamp) FILTER (WHERE action = 'JOINED') AS last_join,
+        MAX(timestamp) FILTER (WHERE action = 'LEFT') AS left_at
+    FROM (
+        SELECT server_id, user_id, action, timestamp,
+            COUNT(*) FILTER (WHERE action = 'LEFT') OVER (
+                PARTITION BY server_id, user_id ORDER BY timestamp, id
+                ROWS UNBOUNDED PRECEDING
+            ) - CASE WHEN action = 'LEFT' THEN 1 ELSE 0 END AS island
+        FROM player_actions
+    ) a
+    GROUP BY server_id, user_id, island
+) s
+WHERE first_join IS NOT NULL;
+
+-- Total playtime per player and server, open sessions count up to last_seen.
+CREATE VIEW player_playtime AS
+SELECT
+    s.player_id,
+    p.name,
+    p.uuid,
+    s.server_id,
+    sv.ip,
+    COUNT(*) AS sessions,
+    SUM(COALESCE(s.ended, s.last_seen) - s.started) AS playtime,
+    MIN(s.started) AS first_seen,
+    MAX(COALESCE(s.ended, s.last_seen)) AS last_seen
+FROM player_sessions s
+JOIN player_list p ON p.id = s.player_id
+JOIN servers sv ON sv.id = s.server_id
+GROUP BY s.player_id, p.name, p.uuid, s.server_id, sv.ip;
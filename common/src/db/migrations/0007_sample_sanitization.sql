@@ -0,0 +1,15 @@
+-- Account type of sampled players, see common::sanitize
+ALTER TABLE player_list ADD COLUMN bedrock BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Sample entries that were not stored as players, with the reason, for review
+CREATE TABLE rejected_sample_entries (
+    server_id INTEGER NOT NULL REFERENCES servers(id),
+    name TEXT NOT NULL,
+    uuid TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    count INTEGER NOT NULL DEFAULT 1,
+    first_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_seen TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (server_id, name, uuid)
+);
+CREATE INDEX idx_rejected_sample_entries_reason ON rejected_sample_entries (reason);
@@ -0,0 +1,40 @@
+-- Players saved before sample sanitization have their UUID as the server sent
+-- it, upper case or without dashes. Normalize them like sanitize.rs does and
+-- merge players that end up with the same name and UUID into the lowest id.
+-- Entries that are not 32 hex digits are left as they are.
+CREATE TEMP TABLE player_merges ON COMMIT DROP AS
+SELECT id, MIN(id) OVER (PARTITION BY name, uuid) AS keep, uuid
+FROM (
+    SELECT id, name,
+        substr(h, 1, 8) || '-' || substr(h, 9, 4) || '-' || substr(h, 13, 4) || '-'
+            || substr(h, 17, 4) || '-' || substr(h, 21) AS uuid
+    FROM (SELECT id, name, lower(replace(uuid, '-', '')) AS h FROM player_list) p
+    WHERE h ~ '^[0-9a-f]{32}$'
+) normalized;
+
+-- A merged player has one open session per server at most, close the others.
+UPDATE player_sessions s SET open = FALSE, ended = s.last_seen, observed = FALSE
+FROM player_merges m
+WHERE s.open AND s.player_id = m.id AND m.id <> m.keep
+    AND EXISTS (
+        SELECT 1 FROM player_sessions o
+        JOIN player_merges om ON om.id = o.player_id
+        WHERE o.open AND o.server_id = s.server_id AND om.keep = m.keep
+            AND o.player_id < s.player_id
+    );
+
+UPDATE player_actions a SET user_id = m.keep
+FROM player_merges m
+WHERE a.user_id = m.id AND m.id <> m.keep;
+
+UPDATE player_sessions s SET player_id = m.keep
+FROM player_merges m
+WHERE s.player_id = m.id AND m.id <> m.keep;
+
+DELETE FROM player_list p
+USING player_merges m
+WHERE p.id = m.id AND m.id <> m.keep;
+
+UPDATE player_list p SET uuid = m.uuid
+FROM player_merges m
+WHERE p.id = m.id AND p.uuid <> m.uuid;
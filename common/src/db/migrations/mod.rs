@@ -0,0 +1,321 @@
+//! Numbered schema migrations. Each one runs once, in its own transaction, and
+//! is recorded in `schema_migrations` with the md5 of its SQL so an edited
+//! migration is caught instead of silently diverging between deployments.
+//!
+//! New migrations get the next number and a line in [`MIGRATIONS`]. Never edit
+//! one that has been released, add another.
+
+use std::{collections::HashMap, fmt};
+
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Migration {
+    pub fn checksum(&self) -> String {
+        format!("{:x}", md5::compute(self.sql))
+    }
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "baseline",
+        sql: include_str!("0001_baseline.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "server_history",
+        sql: include_str!("0002_server_history.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "typed_addresses",
+        sql: include_str!("0003_typed_addresses.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "geo",
+        sql: include_str!("0004_geo.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "rdns",
+        sql: include_str!("0005_rdns.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "player_sessions",
+        sql: include_str!("0006_player_sessions.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "sample_sanitization",
+        sql: include_str!("0007_sample_sanitization.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "normalize_player_uuids",
+        sql: include_str!("0008_normalize_player_uuids.sql"),
+    },
+];
+
+/// Arbitrary key for `pg_advisory_lock`, so the scanner and the verifier
+/// starting at the same time do not both apply the same migration.
+const LOCK_KEY: i64 = 0x6d63_7363_616e;
+
+#[derive(Debug)]
+pub enum MigrationError {
+    Db(tokio_postgres::Error),
+    /// An applied migration's SQL no longer matches what was recorded.
+    ChecksumMismatch {
+        version: i32,
+        name: String,
+    },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Db(e) => write!(f, "{}", e),
+            MigrationError::ChecksumMismatch { version, name } => write!(
+                f,
+                "migration {:04}_{} was changed after it was applied",
+                version, name
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<tokio_postgres::Error> for MigrationError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        MigrationError::Db(e)
+    }
+}
+
+/// A row of `schema_migrations`.
+pub struct Applied {
+    pub version: i32,
+    pub name: String,
+    pub checksum: String,
+}
+
+/// The migrations from `known` that still have to run, oldest first. Fails if
+/// an applied migration was edited. Versions in the database that this binary
+/// does not know (written by a newer build) are ignored.
+pub fn plan<'a>(
+    known: &'a [Migration],
+    applied: &[Applied],
+) -> Result<Vec<&'a Migration>, MigrationError> {
+    let applied: HashMap<i32, &Applied> = applied.iter().map(|a| (a.version, a)).collect();
+    let mut pending = Vec::new();
+    for migration in known {
+        match applied.get(&migration.version) {
+            Some(a) if a.checksum != migration.checksum() => {
+                return Err(MigrationError::ChecksumMismatch {
+                    version: a.version,
+                    name: a.name.clone(),
+                });
+            }
+            Some(_) => {}
+            None => pending.push(migration),
+        }
+    }
+    Ok(pending)
+}
+
+async fn applied(client: &tokio_postgres::Client) -> Result<Vec<Applied>, tokio_postgres::Error> {
+    client
+        .batch_execute(
+            r#"
+                CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    checksum TEXT NOT NULL,
+                    applied_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+                );
+            "#,
+        )
+        .await?;
+    let rows = client
+        .query(
+            "SELECT version, name, checksum FROM schema_migrations ORDER BY version",
+            &[],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| Applied {
+            version: row.get(0),
+            name: row.get(1),
+            checksum: row.get(2),
+        })
+        .collect())
+}
+
+async fn apply(
+    client: &tokio_postgres::Client,
+    migration: &Migration,
+) -> Result<(), tokio_postgres::Error> {
+    client.batch_execute("BEGIN").await?;
+    let result = async {
+        client.batch_execute(migration.sql).await?;
+        client
+            .execute(
+                "INSERT INTO schema_migrations (version, name, checksum) VALUES ($1, $2, $3)",
+                &[&migration.version, &migration.name, &migration.checksum()],
+            )
+            .await
+    }
+    .await;
+    match result {
+        Ok(_) => client.batch_execute("COMMIT").await,
+        Err(e) => {
+            let _ = client.batch_execute("ROLLBACK").await;
+            Err(e)
+        }
+    }
+}
+
+/// Apply every pending migration, returning the ones that ran (or, with
+/// `dry_run`, would run).
+pub async fn migrate(
+    client: &tokio_postgres::Client,
+    dry_run: bool,
+) -> Result<Vec<&'static Migration>, MigrationError> {
+    client
+        .execute("SELECT pg_advisory_lock($1)", &[&LOCK_KEY])
+        .await?;
+    let result = async {
+        let pending = plan(MIGRATIONS, &applied(client).await?)?;
+        if !dry_run {
+            for migration in &pending {
+                tracing::info!(
+                    "Applying migration {:04}_{}",
+                    migration.version,
+                    migration.name
+                );
+                apply(client, migration).await?;
+            }
+        }
+        Ok(pending)
+    }
+    .await;
+    let _ = client
+        .execute("SELECT pg_advisory_unlock($1)", &[&LOCK_KEY])
+        .await;
+    result
+}
+
+/// `<binary> migrate [status | --dry-run]`.
+pub async fn run_command(client: &tokio_postgres::Client, args: &[String]) {
+    let result = match args.first().map(String::as_str) {
+        None => migrate(client, false).await.map(|applied| {
+            if applied.is_empty() {
+                println!("schema is up to date");
+            }
+            for m in applied {
+                println!("applied {:04}_{}", m.version, m.name);
+            }
+        }),
+        Some("--dry-run") => migrate(client, true).await.map(|pending| {
+            if pending.is_empty() {
+                println!("schema is up to date");
+            }
+            for m in pending {
+                println!("-- {:04}_{}\n{}", m.version, m.name, m.sql);
+            }
+        }),
+        Some("status") => print_status(client).await,
+        Some(other) => {
+            eprintln!(
+                "unknown migrate argument {:?}, expected status or --dry-run",
+                other
+            );
+            return;
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("Migration failed: {}", e);
+    }
+}
+
+async fn print_status(client: &tokio_postgres::Client) -> Result<(), MigrationError> {
+    let applied = applied(client).await?;
+    let by_version: HashMap<i32, &Applied> = applied.iter().map(|a| (a.version, a)).collect();
+    for m in MIGRATIONS {
+        let state = match by_version.get(&m.version) {
+            Some(a) if a.checksum != m.checksum() => "changed",
+            Some(_) => "applied",
+            None => "pending",
+        };
+        println!("{:04}_{:<32} {}", m.version, m.name, state);
+    }
+    for a in &applied {
+        if !MIGRATIONS.iter().any(|m| m.version == a.version) {
+            println!("{:04}_{:<32} unknown (newer build?)", a.version, a.name);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn applied(m: &Migration) -> Applied {
+        Applied {
+            version: m.version,
+            name: m.name.to_string(),
+            checksum: m.checksum(),
+        }
+    }
+
+    #[test]
+    fn test_versions_ordered() {
+        for (i, m) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(m.version, i as i32 + 1, "{} is out of order", m.name);
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let known = [
+            Migration {
+                version: 1,
+                name: "one",
+                sql: "SELECT 1;",
+            },
+            Migration {
+                version: 2,
+                name: "two",
+                sql: "SELECT 2;",
+            },
+        ];
+        let pending = plan(&known, &[]).unwrap();
+        assert_eq!(pending.len(), 2);
+
+        let pending = plan(&known, &[applied(&known[0])]).unwrap();
+        assert_eq!(pending.iter().map(|m| m.version).collect::<Vec<_>>(), [2]);
+
+        // Applied by a newer build, not ours to judge.
+        let newer = Applied {
+            version: 3,
+            name: "three".into(),
+            checksum: String::new(),
+        };
+        let pending = plan(&known, &[applied(&known[0]), applied(&known[1]), newer]).unwrap();
+        assert!(pending.is_empty());
+
+        let mut edited = applied(&known[0]);
+        edited.checksum = "0".into();
+        assert!(matches!(
+            plan(&known, &[edited]),
+            Err(MigrationError::ChecksumMismatch { version: 1, .. })
+        ));
+    }
+}
@@ -0,0 +1,13 @@
+pub mod favicons;
+pub mod geo;
+pub mod history;
+pub mod init;
+pub mod migrations;
+pub mod presence;
+pub mod rdns;
+pub mod scan_results;
+pub mod servers;
+pub mod sessions;
+pub mod status;
+pub mod structs;
+pub mod suspicion;
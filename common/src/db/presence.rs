@@ -0,0 +1,354 @@
+//! Player presence from the status `sample`, shared by the scanner and the
+//! verifier. The sample is a random subset of at most 12 online players, so a
+//! player missing from one sample has not necessarily left: the last known set
+//! is the server's open sessions, and a player only counts as left once a
+//! complete sample misses them or they were not sampled for a grace window.
+//! Entries are checked by [`crate::sanitize`] first, rejected ones are kept in
+//! `rejected_sample_entries`.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, SystemTime},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    db::{
+        sessions::{OpenSession, close_sessions, open_sessions, touch_sessions},
+        structs::{ActionType, Player, Players},
+    },
+    sanitize::{PlayerKind, RejectReason, SamplePlayer, sanitize_player},
+};
+
+/// Presence settings, flattened into the scanner and verifier configs.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PresenceConfig {
+    /// How long a player may be missing from partial samples before a LEFT
+    /// is recorded. Grows with the share of players a sample leaves out.
+    #[serde(default = "default_presence_grace_secs")]
+    pub presence_grace_secs: u64,
+}
+
+fn default_presence_grace_secs() -> u64 {
+    600
+}
+
+impl Default for PresenceConfig {
+    fn default() -> Self {
+        PresenceConfig {
+            presence_grace_secs: default_presence_grace_secs(),
+        }
+    }
+}
+
+impl PresenceConfig {
+    pub fn left_grace(&self) -> Duration {
+        Duration::from_secs(self.presence_grace_secs)
+    }
+}
+
+/// Upper bound for how much [`grace_window`] stretches the configured grace.
+const MAX_GRACE_FACTOR: usize = 10;
+
+/// The grace window for one ping. A sample of 12 out of 100 players says
+/// little about any one of them, so the window grows with online / sampled.
+fn grace_window(left_grace: Duration, online: Option<i32>, sampled: usize) -> Duration {
+    let factor = match online {
+        Some(online) if sampled > 0 => usize::try_from(online).unwrap_or(0).div_ceil(sampled),
+        _ => 1,
+    };
+    left_grace * factor.clamp(1, MAX_GRACE_FACTOR) as u32
+}
+
+/// Whether the sample lists every online player, so missing ones are gone.
+fn is_complete(online: Option<i32>, sampled: usize) -> bool {
+    online.is_some_and(|online| usize::try_from(online).is_ok_and(|online| sampled >= online))
+}
+
+/// A sample sorted by [`sanitize_player`].
+#[derive(Default)]
+struct SortedSample<'a> {
+    accepted: Vec<SamplePlayer>,
+    rejected: HashMap<(&'a str, &'a str), RejectReason>,
+    anonymous: usize,
+}
+
+impl<'a> SortedSample<'a> {
+    fn new(sample: &'a [Player]) -> Self {
+        let mut sorted = SortedSample::default();
+        for entry in sample {
+            let (name, id) = (entry.name.as_deref(), entry.id.as_deref());
+            match sanitize_player(name, id) {
+                Ok(player) => sorted.accepted.push(player),
+                Err(RejectReason::Anonymous) => sorted.anonymous += 1,
+                Err(reason) => {
+                    sorted
+                        .rejected
+                        .insert((name.unwrap_or(""), id.unwrap_or("")), reason);
+                }
+            }
+        }
+        sorted
+            .accepted
+            .sort_unstable_by(|a, b| (&a.name, &a.uuid).cmp(&(&b.name, &b.uuid)));
+        sorted.accepted.dedup();
+        sorted
+    }
+
+    /// Entries that stand for an online player. Hidden players count, MOTD
+    /// lines and other fakes do not.
+    fn sampled(&self) -> usize {
+        self.accepted.len() + self.anonymous
+    }
+}
+
+/// Record JOINED for sampled players without an open session and LEFT for
+/// open sessions whose player is gone, and keep `player_sessions` in step.
+/// A player is gone when a complete sample misses them or when they were not
+/// sampled for the grace window (see [`PresenceConfig`]). `servers` pairs
+/// server ids with their players, the joins of all of them are written at
+/// once.
+pub async fn save_presence(
+    servers: &[(i32, Option<&Players>)],
+    left_grace: Duration,
+    client: &tokio_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    let mut diffs = Vec::with_capacity(servers.len());
+    let mut joins = Vec::new();
+    for &(server_id, players) in servers {
+        // No players object says nothing about who is online.
+        let Some(players) = players else {
+            continue;
+        };
+        let sample = SortedSample::new(players.sample.as_deref().unwrap_or_default());
+        save_rejected(server_id, &sample.rejected, client).await?;
+        let grace = grace_window(left_grace, players.online, sample.sampled());
+        let open = open_sessions(server_id, grace, client).await?;
+        let known: HashSet<(&str, &str)> = open
+            .iter()
+            .map(|s| (s.name.as_str(), s.uuid.as_str()))
+            .collect();
+        for player in &sample.accepted {
+            if !known.contains(&(player.name.as_str(), player.uuid.as_str())) {
+                joins.push((server_id, player.clone()));
+            }
+        }
+        let complete = is_complete(players.online, sample.sampled());
+        diffs.push((server_id, sample.accepted, open, complete));
+    }
+    save_joins(&joins, client).await?;
+
+    for (server_id, accepted, open, complete) in &diffs {
+        let present: Vec<(&str, &str)> = accepted
+            .iter()
+            .map(|p| (p.name.as_str(), p.uuid.as_str()))
+            .collect();
+        touch_sessions(*server_id, &present, client).await?;
+        let (left, left_at): (Vec<i32>, Vec<Option<SystemTime>>) =
+            departures(open, &present, *complete).into_iter().unzip();
+        if left.is_empty() {
+            continue;
+        }
+        client
+            .execute(
+                r#"
+                    INSERT INTO player_actions (user_id, server_id, action, timestamp)
+                    SELECT t.user_id, $2, $3, COALESCE(t.left_at, NOW())
+                    FROM UNNEST($1::int[], $4::timestamptz[]) AS t (user_id, left_at)
+                "#,
+                &[&left, server_id, &ActionType::Left, &left_at],
+            )
+            .await?;
+        close_sessions(*server_id, &left, *complete, client).await?;
+    }
+    Ok(())
+}
+
+/// The open sessions whose player is gone, with when they left: now if a
+/// complete sample misses them (`None`), else when they were last seen, as
+/// [`close_sessions`] ends the session.
+fn departures(
+    open: &[OpenSession],
+    present: &[(&str, &str)],
+    complete: bool,
+) -> Vec<(i32, Option<SystemTime>)> {
+    open.iter()
+        .filter(|s| !present.contains(&(s.name.as_str(), s.uuid.as_str())))
+        .filter(|s| complete || s.expired)
+        .map(|s| (s.player_id, (!complete).then_some(s.last_seen)))
+        .collect()
+}
+
+/// Count rejected sample entries per server, name and id.
+async fn save_rejected(
+    server_id: i32,
+    rejected: &HashMap<(&str, &str), RejectReason>,
+    client: &tokio_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    if rejected.is_empty() {
+        return Ok(());
+    }
+    let names: Vec<&str> = rejected.keys().map(|(name, _)| *name).collect();
+    let ids: Vec<&str> = rejected.keys().map(|(_, id)| *id).collect();
+    let reasons: Vec<&str> = rejected.values().map(RejectReason::as_str).collect();
+    client
+        .execute(
+            r#"
+                INSERT INTO rejected_sample_entries (server_id, name, uuid, reason)
+                SELECT $1, * FROM UNNEST($2::text[], $3::text[], $4::text[])
+                ON CONFLICT (server_id, name, uuid) DO UPDATE SET
+                    reason = EXCLUDED.reason,
+                    count = rejected_sample_entries.count + 1,
+                    last_seen = NOW()
+            "#,
+            &[&server_id, &names, &ids, &reasons],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Add the `(server_id, player)` pairs to `player_list` and record their
+/// JOINED, in one statement for a whole batch of servers.
+async fn save_joins(
+    joins: &[(i32, SamplePlayer)],
+    client: &tokio_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    if joins.is_empty() {
+        return Ok(());
+    }
+    let server_ids: Vec<i32> = joins.iter().map(|(id, _)| *id).collect();
+    let names: Vec<&str> = joins.iter().map(|(_, p)| p.name.as_str()).collect();
+    let uuids: Vec<&str> = joins.iter().map(|(_, p)| p.uuid.as_str()).collect();
+    let cracked: Vec<bool> = joins
+        .iter()
+        .map(|(_, p)| p.kind == PlayerKind::Offline)
+        .collect();
+    let bedrock: Vec<bool> = joins
+        .iter()
+        .map(|(_, p)| p.kind == PlayerKind::Bedrock)
+        .collect();
+    client
+        .execute(
+            r#"
+                WITH input AS (
+                    SELECT * FROM UNNEST($1::int[], $2::text[], $3::text[], $4::bool[], $5::bool[])
+                        AS t (server_id, name, uuid, cracked, bedrock)
+                ),
+                upserted AS (
+                    -- A player on several servers of the batch is upserted once,
+                    -- in name order so concurrent batches lock alike.
+                    INSERT INTO player_list (name, uuid, cracked, bedrock)
+                    SELECT DISTINCT ON (name, uuid) name, uuid, cracked, bedrock
+                    FROM input ORDER BY name, uuid
+                    ON CONFLICT (uuid, name) DO UPDATE SET
+                        cracked = EXCLUDED.cracked,
+                        bedrock = EXCLUDED.bedrock
+                    WHERE player_list.cracked IS DISTINCT FROM EXCLUDED.cracked
+                        OR player_list.bedrock IS DISTINCT FROM EXCLUDED.bedrock
+                    RETURNING id, name, uuid
+                )
+                INSERT INTO player_actions (user_id, server_id, action)
+                -- Unchanged players are not returned by the upsert.
+                SELECT COALESCE(u.id, p.id), i.server_id, $6
+                FROM input i
+                LEFT JOIN upserted u ON u.name = i.name AND u.uuid = i.uuid
+                LEFT JOIN player_list p ON p.name = i.name AND p.uuid = i.uuid
+            "#,
+            &[
+                &server_ids,
+                &names,
+                &uuids,
+                &cracked,
+                &bedrock,
+                &ActionType::Joined,
+            ],
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_grace_window() {
+        let grace = Duration::from_secs(600);
+        assert_eq!(grace_window(grace, Some(5), 5), grace);
+        assert_eq!(grace_window(grace, Some(30), 12), grace * 3);
+        assert_eq!(grace_window(grace, Some(5000), 12), grace * 10);
+        // Nothing sampled, nothing to scale by.
+        assert_eq!(grace_window(grace, Some(30), 0), grace);
+        assert_eq!(grace_window(grace, None, 12), grace);
+    }
+
+    #[test]
+    fn test_complete_sample() {
+        assert!(is_complete(Some(1), 1));
+        assert!(is_complete(Some(0), 0));
+        assert!(!is_complete(Some(2), 1));
+        assert!(!is_complete(None, 1));
+    }
+
+    #[test]
+    fn test_departures() {
+        let last_seen = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000);
+        let session = |player_id, name: &str, expired| OpenSession {
+            player_id,
+            name: name.into(),
+            uuid: format!("uuid-{}", name),
+            last_seen,
+            expired,
+        };
+        let open = [
+            session(1, "here", true),
+            session(2, "expired", true),
+            session(3, "unsampled", false),
+        ];
+        let present = [("here", "uuid-here")];
+        // A partial sample only gives up on expired players, who left when
+        // they were last seen.
+        assert_eq!(
+            departures(&open, &present, false),
+            vec![(2, Some(last_seen))]
+        );
+        // A complete sample shows everyone missing gone now.
+        assert_eq!(
+            departures(&open, &present, true),
+            vec![(2, None), (3, None)]
+        );
+    }
+
+    #[test]
+    fn test_fake_sample() {
+        let entry = |name: &str, id: &str| Player {
+            name: Some(name.into()),
+            id: Some(id.into()),
+        };
+        let nil = "00000000-0000-0000-0000-000000000000";
+        // A MOTD in the sample, three lines for three "players".
+        let motd = [
+            entry("§6Welcome to", nil),
+            entry("Play now!", nil),
+            entry("§7play.example.net", "not-a-uuid"),
+        ];
+        let sample = SortedSample::new(&motd);
+        assert_eq!(sample.sampled(), 0);
+        assert_eq!(sample.rejected.len(), 3);
+        assert!(!is_complete(Some(3), sample.sampled()));
+        let grace = Duration::from_secs(600);
+        assert_eq!(grace_window(grace, Some(3), sample.sampled()), grace);
+
+        // Hidden players stand for real ones.
+        let mixed = [
+            entry("Notch", "069a79f4-44e9-4726-a5be-fca90e38aaf5"),
+            entry("Anonymous Player", nil),
+            entry("Vote for us!", nil),
+        ];
+        let sample = SortedSample::new(&mixed);
+        assert_eq!(sample.sampled(), 2);
+        assert!(is_complete(Some(2), sample.sampled()));
+        assert!(!is_complete(Some(3), sample.sampled()));
+    }
+}
@@ -0,0 +1,51 @@
+use std::{net::IpAddr, time::Duration};
+
+/// Up to `limit` servers whose PTR record was never looked up or not within
+/// `refresh`, never checked ones first.
+pub async fn servers_due_for_rdns(
+    client: &tokio_postgres::Client,
+    refresh: Duration,
+    limit: i64,
+) -> Result<Vec<(i32, IpAddr)>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            r#"
+                SELECT id, addr FROM servers
+                WHERE addr IS NOT NULL
+                    AND (rdns_checked IS NULL
+                        OR rdns_checked < NOW() - make_interval(secs => $1))
+                ORDER BY rdns_checked NULLS FIRST
+                LIMIT $2
+            "#,
+            &[&refresh.as_secs_f64(), &limit],
+        )
+        .await?;
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+/// Store PTR lookup results by server id. The outer `None` means the lookup
+/// failed (timeout, server error), the stored name is kept then. Either way
+/// the server is not due again before the next refresh.
+pub async fn save_rdns(
+    client: &tokio_postgres::Client,
+    results: &[(i32, Option<Option<String>>)],
+) -> Result<u64, tokio_postgres::Error> {
+    let ids: Vec<i32> = results.iter().map(|(id, _)| *id).collect();
+    let resolved: Vec<bool> = results.iter().map(|(_, r)| r.is_some()).collect();
+    let names: Vec<Option<&str>> = results
+        .iter()
+        .map(|(_, r)| r.as_ref().and_then(|name| name.as_deref()))
+        .collect();
+    client
+        .execute(
+            r#"
+                UPDATE servers s SET
+                    rdns = CASE WHEN t.resolved THEN t.rdns ELSE s.rdns END,
+                    rdns_checked = NOW()
+                FROM UNNEST($1::int[], $2::bool[], $3::text[]) AS t (id, resolved, rdns)
+                WHERE s.id = t.id
+            "#,
+            &[&ids, &resolved, &names],
+        )
+        .await
+}
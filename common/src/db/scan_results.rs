@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+
+use crate::probe::outcome::OutcomeKey;
+
+/// Add drained probe outcome counters to the `scan_results` table. One
+/// statement, so a failed write adds nothing and the counts can be put back.
+pub async fn save_scan_results(
+    counts: &HashMap<OutcomeKey, i64>,
+    client: &tokio_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    if counts.is_empty() {
+        return Ok(());
+    }
+    let subnets: Vec<String> = counts
+        .keys()
+        .map(|(subnet, _, _)| format!("{}/24", subnet))
+        .collect();
+    let ports: Vec<i32> = counts.keys().map(|(_, port, _)| i32::from(*port)).collect();
+    let outcomes: Vec<&str> = counts.keys().map(|(_, _, o)| o.as_str()).collect();
+    let totals: Vec<i64> = counts.values().copied().collect();
+    client
+        .execute(
+            r#"
+                INSERT INTO scan_results (subnet, port, outcome, count)
+                SELECT * FROM UNNEST($1::text[], $2::int[], $3::text[], $4::bigint[])
+                ON CONFLICT (subnet, port, outcome)
+                DO UPDATE SET count = scan_results.count + EXCLUDED.count, last_seen = NOW()
+            "#,
+            &[&subnets, &ports, &outcomes, &totals],
+        )
+        .await?;
+    Ok(())
+}
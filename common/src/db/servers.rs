@@ -0,0 +1,267 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use serde_json::Value;
+use tokio_postgres::error::SqlState;
+
+use crate::{
+    db::{
+        history::{Previous, save_snapshots},
+        presence::save_presence,
+        structs::{Players, Version},
+    },
+    geo::GeoInfo,
+    versions::VersionRange,
+};
+
+/// The columns of `servers` written on every successful ping.
+pub struct ServerRow<'a> {
+    /// `ip:port`, the key of the table.
+    pub addr: &'a str,
+    pub description: Option<&'a str>,
+    pub description_html: Option<&'a str>,
+    pub raw_description: Option<&'a Value>,
+    pub players: Option<&'a Players>,
+    pub version: Option<&'a Version>,
+    pub version_range: &'a VersionRange,
+    /// Only favicons that could not be decoded, see [`crate::db::favicons`].
+    pub favicon: Option<&'a str>,
+    pub enforces_secure_chat: Option<bool>,
+    pub extra: &'a Value,
+    /// Software name, proxy flag and confidence. `None` keeps what is stored.
+    pub software: Option<(&'a str, bool, f32)>,
+    /// How long the status request took, for the snapshot.
+    pub latency: Duration,
+    /// GeoIP / ASN lookup. Fields that are `None` keep what is stored.
+    pub geo: Option<&'a GeoInfo>,
+}
+
+/// What the upsert of one server returned.
+#[derive(Debug)]
+pub struct UpsertedServer {
+    pub id: i32,
+    pub inserted: bool,
+}
+
+/// Tries of the whole transaction when Postgres aborts it to break a deadlock.
+const DEADLOCK_ATTEMPTS: u32 = 3;
+
+/// Insert or update `rows` keyed by address, record player joins and leaves
+/// (see [`save_presence`] for `left_grace`) and add a snapshot, all in one
+/// transaction. Returns the upserted servers by address.
+pub async fn save_servers(
+    rows: &[ServerRow<'_>],
+    left_grace: Duration,
+    client: &mut tokio_postgres::Client,
+) -> Result<HashMap<String, UpsertedServer>, tokio_postgres::Error> {
+    let mut attempt = 1;
+    loop {
+        match save_servers_once(rows, left_grace, client).await {
+            Err(e)
+                if attempt < DEADLOCK_ATTEMPTS
+                    && e.code() == Some(&SqlState::T_R_DEADLOCK_DETECTED) =>
+            {
+                tracing::debug!("Deadlock saving {} servers, retrying", rows.len());
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+async fn save_servers_once(
+    rows: &[ServerRow<'_>],
+    left_grace: Duration,
+    client: &mut tokio_postgres::Client,
+) -> Result<HashMap<String, UpsertedServer>, tokio_postgres::Error> {
+    // Rolled back when dropped, also when the caller gives up on the future,
+    // so a pooled connection never goes back holding the row locks.
+    let transaction = client.transaction().await?;
+    let servers = upsert(rows, left_grace, transaction.client()).await?;
+    transaction.commit().await?;
+    Ok(servers)
+}
+
+async fn upsert(
+    rows: &[ServerRow<'_>],
+    left_grace: Duration,
+    client: &tokio_postgres::Client,
+) -> Result<HashMap<String, UpsertedServer>, tokio_postgres::Error> {
+    // Lock and insert in address order, so concurrent batches of the scanner
+    // and the verifier do not deadlock on the servers rows. Sessions are still
+    // written server by server, two batches sharing players can deadlock on
+    // player_sessions and are retried by save_servers.
+    let mut rows: Vec<&ServerRow> = rows.iter().collect();
+    rows.sort_by_key(|row| row.addr);
+    rows.dedup_by_key(|row| row.addr);
+
+    let addrs: Vec<&str> = rows.iter().map(|row| row.addr).collect();
+    let sockets: Vec<Option<SocketAddr>> = addrs.iter().map(|a| a.parse().ok()).collect();
+    let previous: HashMap<String, Previous> = client
+        .query(
+            r#"
+                SELECT ip, version, software, md5(description)
+                FROM servers WHERE ip = ANY($1) ORDER BY ip FOR UPDATE
+            "#,
+            &[&addrs],
+        )
+        .await?
+        .iter()
+        .map(|row| {
+            (
+                row.get(0),
+                Previous {
+                    version: row.get(1),
+                    software: row.get(2),
+                    description_hash: row.get(3),
+                },
+            )
+        })
+        .collect();
+
+    let upserted = client
+        .query(
+            r#"
+                WITH input AS (
+                    -- UNNEST would expand the players and version composites
+                    -- into their fields, index the arrays instead.
+                    SELECT
+                        ($1::text[])[i] AS ip,
+                        ($2::text[])[i] AS description,
+                        ($3::text[])[i] AS description_html,
+                        ($4::jsonb[])[i] AS raw_description,
+                        ($5::players[])[i] AS players,
+                        ($6::version[])[i] AS version,
+                        ($7::text[])[i] AS version_min,
+                        ($8::text[])[i] AS version_max,
+                        ($9::int[])[i] AS protocol_min,
+                        ($10::int[])[i] AS protocol_max,
+                        ($11::text[])[i] AS favicon,
+                        ($12::bool[])[i] AS enforces_secure_chat,
+                        ($13::jsonb[])[i] AS extra,
+                        ($14::text[])[i] AS software,
+                        ($15::bool[])[i] AS software_proxy,
+                        ($16::real[])[i] AS software_confidence,
+                        ($17::inet[])[i] AS addr,
+                        ($18::int[])[i] AS port,
+                        ($19::text[])[i] AS country,
+                        ($20::text[])[i] AS city,
+                        ($21::bigint[])[i] AS asn,
+                        ($22::text[])[i] AS as_org
+                    FROM generate_subscripts($1::text[], 1) AS i
+                    ORDER BY i
+                )
+                INSERT INTO servers (
+                    ip, description, description_html, raw_description, players,
+                    version, version_min, version_max, protocol_min, protocol_max,
+                    favicon, enforces_secure_chat, extra, software, software_proxy,
+                    software_confidence, addr, port, country, city, asn, as_org, last_pinged
+                )
+                SELECT *, NOW() FROM input
+                ON CONFLICT (ip) DO UPDATE SET
+                    description = EXCLUDED.description,
+                    description_html = EXCLUDED.description_html,
+                    raw_description = EXCLUDED.raw_description,
+                    players = EXCLUDED.players,
+                    version = EXCLUDED.version,
+                    version_min = EXCLUDED.version_min,
+                    version_max = EXCLUDED.version_max,
+                    protocol_min = EXCLUDED.protocol_min,
+                    protocol_max = EXCLUDED.protocol_max,
+                    favicon = EXCLUDED.favicon,
+                    enforces_secure_chat = EXCLUDED.enforces_secure_chat,
+                    extra = EXCLUDED.extra,
+                    software = COALESCE(EXCLUDED.software, servers.software),
+                    software_proxy = COALESCE(EXCLUDED.software_proxy, servers.software_proxy),
+                    software_confidence = COALESCE(EXCLUDED.software_confidence, servers.software_confidence),
+                    addr = EXCLUDED.addr,
+                    port = EXCLUDED.port,
+                    country = COALESCE(EXCLUDED.country, servers.country),
+                    city = COALESCE(EXCLUDED.city, servers.city),
+                    asn = COALESCE(EXCLUDED.asn, servers.asn),
+                    as_org = COALESCE(EXCLUDED.as_org, servers.as_org),
+                    last_pinged = NOW()
+                RETURNING id, ip, xmax = 0 AS inserted
+            "#,
+            &[
+                &addrs,
+                &rows.iter().map(|r| r.description).collect::<Vec<_>>(),
+                &rows.iter().map(|r| r.description_html).collect::<Vec<_>>(),
+                &rows.iter().map(|r| r.raw_description).collect::<Vec<_>>(),
+                &rows.iter().map(|r| r.players).collect::<Vec<_>>(),
+                &rows.iter().map(|r| r.version).collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.version_range.min.as_deref())
+                    .collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.version_range.max.as_deref())
+                    .collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.version_range.min_protocol)
+                    .collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.version_range.max_protocol)
+                    .collect::<Vec<_>>(),
+                &rows.iter().map(|r| r.favicon).collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.enforces_secure_chat)
+                    .collect::<Vec<_>>(),
+                &rows.iter().map(|r| r.extra).collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.software.map(|s| s.0))
+                    .collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.software.map(|s| s.1))
+                    .collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.software.map(|s| s.2))
+                    .collect::<Vec<_>>(),
+                &sockets.iter().map(|s| s.map(|s| s.ip())).collect::<Vec<_>>(),
+                &sockets
+                    .iter()
+                    .map(|s| s.map(|s| i32::from(s.port())))
+                    .collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.geo.and_then(|g| g.country.as_deref()))
+                    .collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.geo.and_then(|g| g.city.as_deref()))
+                    .collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.geo.and_then(|g| g.asn).map(i64::from))
+                    .collect::<Vec<_>>(),
+                &rows
+                    .iter()
+                    .map(|r| r.geo.and_then(|g| g.as_org.as_deref()))
+                    .collect::<Vec<_>>(),
+            ],
+        )
+        .await?;
+
+    let mut servers = HashMap::with_capacity(upserted.len());
+    for row in &upserted {
+        let addr: String = row.get("ip");
+        let server = UpsertedServer {
+            id: row.get("id"),
+            inserted: row.get("inserted"),
+        };
+        servers.insert(addr, server);
+    }
+    let presence: Vec<(i32, Option<&Players>)> = rows
+        .iter()
+        .filter_map(|row| Some((servers.get(row.addr)?.id, row.players)))
+        .collect();
+    save_presence(&presence, left_grace, client).await?;
+    save_snapshots(&rows, &servers, &previous, client).await?;
+    Ok(servers)
+}
@@ -0,0 +1,140 @@
+use std::time::{Duration, SystemTime};
+
+use crate::db::structs::ActionType;
+
+/// Extend the open session of every sampled player, or open one. Players
+/// that are not in `player_list` are skipped.
+pub async fn touch_sessions(
+    server_id: i32,
+    players: &[(&str, &str)],
+    client: &tokio_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    if players.is_empty() {
+        return Ok(());
+    }
+    let (names, ids): (Vec<&str>, Vec<&str>) = players.iter().copied().unzip();
+    client
+        .execute(
+            r#"
+                INSERT INTO player_sessions (server_id, player_id)
+                SELECT $1, p.id
+                FROM player_list p
+                JOIN UNNEST($2::text[], $3::text[]) AS t (name, uuid)
+                    ON p.name = t.name AND p.uuid = t.uuid
+                ON CONFLICT (server_id, player_id) WHERE open
+                    DO UPDATE SET last_seen = NOW()
+            "#,
+            &[&server_id, &names, &ids],
+        )
+        .await?;
+    Ok(())
+}
+
+/// An open session as the presence diff sees it.
+pub struct OpenSession {
+    pub player_id: i32,
+    pub name: String,
+    pub uuid: String,
+    pub last_seen: SystemTime,
+    /// The player was not sampled within the grace window.
+    pub expired: bool,
+}
+
+/// The open sessions of a server, the players it is thought to have online.
+pub async fn open_sessions(
+    server_id: i32,
+    grace: Duration,
+    client: &tokio_postgres::Client,
+) -> Result<Vec<OpenSession>, tokio_postgres::Error> {
+    let rows = client
+        .query(
+            r#"
+                SELECT s.player_id, p.name, p.uuid, s.last_seen,
+                    s.last_seen < NOW() - make_interval(secs => $2) AS expired
+                FROM player_sessions s
+                JOIN player_list p ON p.id = s.player_id
+                WHERE s.open AND s.server_id = $1
+            "#,
+            &[&server_id, &grace.as_secs_f64()],
+        )
+        .await?;
+    Ok(rows
+        .iter()
+        .map(|row| OpenSession {
+            player_id: row.get(0),
+            name: row.get(1),
+            uuid: row.get(2),
+            last_seen: row.get(3),
+            expired: row.get(4),
+        })
+        .collect())
+}
+
+/// Close the open sessions of `player_ids` on a server. `observed` sessions
+/// end now because a complete sample showed the player gone, the others when
+/// the player was last seen.
+pub async fn close_sessions(
+    server_id: i32,
+    player_ids: &[i32],
+    observed: bool,
+    client: &tokio_postgres::Client,
+) -> Result<(), tokio_postgres::Error> {
+    client
+        .execute(
+            r#"
+                UPDATE player_sessions SET
+                    open = FALSE,
+                    ended = CASE WHEN $3 THEN NOW() ELSE last_seen END,
+                    observed = $3
+                WHERE open AND server_id = $1 AND player_id = ANY($2)
+            "#,
+            &[&server_id, &player_ids, &observed],
+        )
+        .await?;
+    Ok(())
+}
+
+/// Close every open session on a server that stopped answering. They end when
+/// the player was last seen, which is also when their LEFT is recorded.
+pub async fn close_offline_sessions(
+    addr: &str,
+    client: &tokio_postgres::Client,
+) -> Result<u64, tokio_postgres::Error> {
+    client
+        .execute(
+            r#"
+                WITH closed AS (
+                    UPDATE player_sessions SET open = FALSE, ended = last_seen, observed = FALSE
+                    WHERE open AND server_id = (SELECT id FROM servers WHERE ip = $1)
+                    RETURNING server_id, player_id, last_seen
+                )
+                INSERT INTO player_actions (user_id, server_id, action, timestamp)
+                SELECT player_id, server_id, $2, last_seen FROM closed
+            "#,
+            &[&addr, &ActionType::Left],
+        )
+        .await
+}
+
+/// Close open sessions whose player was not sampled for `timeout`, e.g.
+/// because the server stopped sending a sample. They end when the player was
+/// last seen, which is also when their LEFT is recorded.
+pub async fn close_stale_sessions(
+    client: &tokio_postgres::Client,
+    timeout: Duration,
+) -> Result<u64, tokio_postgres::Error> {
+    client
+        .execute(
+            r#"
+                WITH closed AS (
+                    UPDATE player_sessions SET open = FALSE, ended = last_seen, observed = FALSE
+                    WHERE open AND last_seen < NOW() - make_interval(secs => $1)
+                    RETURNING server_id, player_id, last_seen
+                )
+                INSERT INTO player_actions (user_id, server_id, action, timestamp)
+                SELECT player_id, server_id, $2, last_seen FROM closed
+            "#,
+            &[&timeout.as_secs_f64(), &ActionType::Left],
+        )
+        .await
+}
@@ -0,0 +1,131 @@
+//! The parts of a status response that end up in `servers`, parsed the same
+//! way for the scanner and the verifier. What only the scanner looks at (mods,
+//! fingerprint) it takes from the cleaned JSON itself.
+
+use std::time::Duration;
+
+use serde_json::Value;
+
+use crate::{
+    chat::{description_html, parse_description},
+    db::{
+        servers::ServerRow,
+        structs::{Players, Version, parse_players, parse_version},
+    },
+    favicon::{Favicon, split_favicon},
+    geo::GeoInfo,
+    suspicion::{Suspicion, response_hash, score_response},
+    versions::{VersionRange, parse_version_range},
+};
+
+/// Keys with a column of their own, left out of `extra`.
+const STORED_KEYS: &[&str] = &[
+    "description",
+    "enforcesSecureChat",
+    "favicon",
+    "players",
+    "version",
+];
+
+/// Parse a status response after removing NUL characters, which Postgres does
+/// not store in text or JSON. Returns the cleaned string with the JSON.
+pub fn clean_status_json(raw: &str) -> Result<(String, Value), serde_json::Error> {
+    let json_str = raw.replace("\\u0000", "").replace('\u{0000}', "");
+    let json = serde_json::from_str(&json_str)?;
+    Ok((json_str, json))
+}
+
+pub struct ParsedStatus {
+    pub description: Option<String>,
+    pub description_html: Option<String>,
+    pub raw_description: Option<Value>,
+    pub enforces_secure_chat: Option<bool>,
+    /// Only favicons that could not be decoded, valid ones are in `icon`.
+    pub favicon: Option<String>,
+    pub icon: Option<Favicon>,
+    pub players: Option<Players>,
+    pub version: Option<Version>,
+    pub version_range: VersionRange,
+    pub suspicion: Suspicion,
+    pub response_hash: Option<String>,
+    /// Everything without a column of its own.
+    pub extra: Value,
+}
+
+impl ParsedStatus {
+    /// `json_str` and `json` as returned by [`clean_status_json`].
+    pub fn new(json_str: &str, json: &Value, latency: Duration) -> Self {
+        let description = json.get("description").cloned();
+        let (icon, favicon) = split_favicon(json.get("favicon").and_then(|v| v.as_str()));
+        let version = json.get("version").map(parse_version);
+        let version_range = version
+            .as_ref()
+            .map(|v| parse_version_range(v.name.as_deref(), v.protocol))
+            .unwrap_or_default();
+        let mut extra = json.clone();
+        if let Some(obj) = extra.as_object_mut() {
+            for key in STORED_KEYS {
+                obj.remove(*key);
+            }
+        }
+        ParsedStatus {
+            description: description.as_ref().map(parse_description),
+            description_html: description.as_ref().map(description_html),
+            raw_description: description,
+            enforces_secure_chat: json.get("enforcesSecureChat").and_then(|v| v.as_bool()),
+            favicon,
+            icon,
+            players: json.get("players").map(parse_players),
+            version,
+            version_range,
+            suspicion: score_response(json, latency),
+            response_hash: response_hash(json_str, json),
+            extra,
+        }
+    }
+
+    /// The `servers` row of this response. `software` `None` keeps the stored
+    /// fingerprint.
+    pub fn row<'a>(
+        &'a self,
+        addr: &'a str,
+        latency: Duration,
+        software: Option<(&'a str, bool, f32)>,
+        geo: Option<&'a GeoInfo>,
+    ) -> ServerRow<'a> {
+        ServerRow {
+            addr,
+            description: self.description.as_deref(),
+            description_html: self.description_html.as_deref(),
+            raw_description: self.raw_description.as_ref(),
+            players: self.players.as_ref(),
+            version: self.version.as_ref(),
+            version_range: &self.version_range,
+            favicon: self.favicon.as_deref(),
+            enforces_secure_chat: self.enforces_secure_chat,
+            extra: &self.extra,
+            software,
+            latency,
+            geo,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parsed_status() {
+        let raw = r#"{"description":{"text":"A\u0000 server"},"version":{"name":"Paper 1.21.4","protocol":769},"players":{"max":20,"online":1},"favicon":"not a data uri","forgeData":{}}"#;
+        let (json_str, json) = clean_status_json(raw).unwrap();
+        assert!(!json_str.contains("\\u0000"));
+        let status = ParsedStatus::new(&json_str, &json, Duration::from_millis(50));
+        assert_eq!(status.description.as_deref(), Some("A server"));
+        assert_eq!(status.favicon.as_deref(), Some("not a data uri"));
+        assert!(status.icon.is_none());
+        assert_eq!(status.players.as_ref().and_then(|p| p.online), Some(1));
+        assert_eq!(status.extra, serde_json::json!({"forgeData": {}}));
+        assert!(clean_status_json("{").is_err());
+    }
+}
@@ -48,38 +48,14 @@ pub fn parse_version(value: &Value) -> Version {
     serde_json::from_value(value.clone()).unwrap_or_default()
 }
 
-pub fn parse_description(value: &Value) -> String {
-    if let Some(s) = value.as_str() {
-        return s.to_string();
-    }
-
-    fn extract_text(val: &Value) -> String {
-        if let Some(s) = val.get("text").and_then(|v| v.as_str()) {
-            let mut out = s.to_string();
-            if let Some(extra) = val.get("extra").and_then(|v| v.as_array()) {
-                for e in extra {
-                    out.push_str(&extract_text(e));
-                }
-            }
-            return out;
-        }
-
-        if let Some(arr) = val.as_array() {
-            return arr.iter().map(extract_text).collect();
-        }
-        String::new()
-    }
-    extract_text(value)
-}
-
 pub fn extract_players(players: Option<Players>) -> Vec<(String, String)> {
     let mut result = Vec::new();
-    if let Some(players) = players {
-        if let Some(sample) = players.sample {
-            for player in sample {
-                if let (Some(name), Some(id)) = (player.name, player.id) {
-                    result.push((name, id));
-                }
+    if let Some(players) = players
+        && let Some(sample) = players.sample
+    {
+        for player in sample {
+            if let (Some(name), Some(id)) = (player.name, player.id) {
+                result.push((name, id));
             }
         }
     }
@@ -0,0 +1,51 @@
+use std::time::Duration;
+
+use crate::suspicion::{SHARED_RESPONSE, Suspicion, weight};
+
+/// Store the per-response suspicion of a server. Resets [`SHARED_RESPONSE`],
+/// [`flag_shared_responses`] adds it back on its next run.
+pub async fn save_suspicion(
+    addr: &str,
+    response_hash: Option<&str>,
+    latency: Duration,
+    suspicion: &Suspicion,
+    client: &tokio_postgres::Client,
+) {
+    let latency_ms = latency.as_millis().min(i32::MAX as u128) as i32;
+    let reasons: Vec<&str> = suspicion.reasons.clone();
+    if let Err(e) = client
+        .execute(
+            "UPDATE servers SET response_hash = $2, latency_ms = $3, suspicion = $4, suspicion_reasons = $5 WHERE ip = $1;",
+            &[&addr, &response_hash, &latency_ms, &suspicion.score, &reasons],
+        )
+        .await
+    {
+        tracing::error!("Error saving suspicion for {}: {}", addr, e);
+    }
+}
+
+/// Flag every server whose exact response is shared by at least `min_servers`
+/// addresses. Returns the number of newly flagged servers.
+pub async fn flag_shared_responses(
+    client: &tokio_postgres::Client,
+    min_servers: i64,
+) -> Result<u64, tokio_postgres::Error> {
+    client
+        .execute(
+            r#"
+                UPDATE servers s
+                SET suspicion = LEAST(1.0, COALESCE(s.suspicion, 0) + $2),
+                    suspicion_reasons = array_append(COALESCE(s.suspicion_reasons, '{}'), $3)
+                FROM (
+                    SELECT response_hash FROM servers
+                    WHERE response_hash IS NOT NULL
+                    GROUP BY response_hash
+                    HAVING COUNT(*) >= $1
+                ) shared
+                WHERE s.response_hash = shared.response_hash
+                  AND NOT ($3 = ANY(COALESCE(s.suspicion_reasons, '{}')))
+            "#,
+            &[&min_servers, &weight(SHARED_RESPONSE), &SHARED_RESPONSE],
+        )
+        .await
+}
@@ -0,0 +1,266 @@
+//! Decoding and fingerprinting of the `favicon` data URI servers send.
+
+use std::{fmt, io::Cursor};
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+
+/// Vanilla only accepts 64x64 PNGs.
+pub const FAVICON_SIZE: u32 = 64;
+const DATA_URI_PREFIX: &str = "data:image/png;base64,";
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Favicon {
+    pub png: Vec<u8>,
+    /// md5 of the PNG bytes, the `favicons` table key.
+    pub hash: String,
+    /// 64-bit DCT perceptual hash, near-duplicates differ in a few bits.
+    pub phash: i64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum FaviconError {
+    NotDataUri,
+    InvalidBase64,
+    NotPng,
+    InvalidPng(String),
+    WrongSize { width: u32, height: u32 },
+}
+
+impl fmt::Display for FaviconError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaviconError::NotDataUri => write!(f, "not a PNG data URI"),
+            FaviconError::InvalidBase64 => write!(f, "invalid base64"),
+            FaviconError::NotPng => write!(f, "not a PNG"),
+            FaviconError::InvalidPng(e) => write!(f, "invalid PNG: {}", e),
+            FaviconError::WrongSize { width, height } => {
+                write!(f, "favicon is {}x{}, not 64x64", width, height)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FaviconError {}
+
+/// Decode and validate a favicon data URI.
+pub fn decode_favicon(data_uri: &str) -> Result<Favicon, FaviconError> {
+    let data = data_uri
+        .trim()
+        .strip_prefix(DATA_URI_PREFIX)
+        .ok_or(FaviconError::NotDataUri)?;
+    // Some servers wrap the base64 like a MIME body.
+    let data: String = data.chars().filter(|c| !c.is_ascii_whitespace()).collect();
+    let png = STANDARD
+        .decode(data)
+        .map_err(|_| FaviconError::InvalidBase64)?;
+    if !png.starts_with(PNG_SIGNATURE) {
+        return Err(FaviconError::NotPng);
+    }
+    let gray = grayscale(&png)?;
+    Ok(Favicon {
+        hash: format!("{:x}", md5::compute(&png)),
+        phash: phash(&gray),
+        png,
+    })
+}
+
+/// Where the `favicon` field of a response is stored: a valid favicon goes to
+/// the `favicons` table, anything else is kept raw in `servers.favicon`.
+pub fn split_favicon(value: Option<&str>) -> (Option<Favicon>, Option<String>) {
+    let Some(raw) = value else {
+        return (None, None);
+    };
+    match decode_favicon(raw) {
+        Ok(icon) => (Some(icon), None),
+        Err(_) => (None, Some(raw.to_string())),
+    }
+}
+
+/// Decode the PNG into 64x64 luma values, transparent pixels count as black.
+fn grayscale(png: &[u8]) -> Result<Vec<f64>, FaviconError> {
+    let invalid = |e: png::DecodingError| FaviconError::InvalidPng(e.to_string());
+    let mut decoder = png::Decoder::new(Cursor::new(png));
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(invalid)?;
+    // Checked before inflating any pixel data.
+    let (width, height) = (reader.info().width, reader.info().height);
+    if width != FAVICON_SIZE || height != FAVICON_SIZE {
+        return Err(FaviconError::WrongSize { width, height });
+    }
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(invalid)?;
+    let channels = info.color_type.samples();
+    let gray = buf[..info.buffer_size()]
+        .chunks(channels)
+        .map(|px| {
+            let (luma, alpha) = match px {
+                [g] => (*g as f64, 255.0),
+                [g, a] => (*g as f64, *a as f64),
+                [r, g, b] => (luma(*r, *g, *b), 255.0),
+                [r, g, b, a, ..] => (luma(*r, *g, *b), *a as f64),
+                [] => (0.0, 0.0),
+            };
+            luma * alpha / 255.0
+        })
+        .collect();
+    Ok(gray)
+}
+
+fn luma(r: u8, g: u8, b: u8) -> f64 {
+    0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64
+}
+
+/// pHash: shrink to 32x32, take the 8x8 lowest DCT frequencies (without the DC
+/// term) and set a bit for every coefficient above their median.
+fn phash(gray: &[f64]) -> i64 {
+    const N: usize = 32;
+    let size = FAVICON_SIZE as usize;
+    let scale = size / N;
+    let mut small = [[0.0f64; N]; N];
+    for (y, row) in small.iter_mut().enumerate() {
+        for (x, value) in row.iter_mut().enumerate() {
+            let mut sum = 0.0;
+            for dy in 0..scale {
+                for dx in 0..scale {
+                    sum += gray[(y * scale + dy) * size + x * scale + dx];
+                }
+            }
+            *value = sum / (scale * scale) as f64;
+        }
+    }
+    let cos: Vec<[f64; N]> = (0..8)
+        .map(|u| {
+            let mut row = [0.0; N];
+            for (x, c) in row.iter_mut().enumerate() {
+                *c = (std::f64::consts::PI * u as f64 * (2 * x + 1) as f64 / (2 * N) as f64).cos();
+            }
+            row
+        })
+        .collect();
+    let mut coefficients = Vec::with_capacity(64);
+    for cos_v in &cos {
+        for cos_u in &cos {
+            let mut sum = 0.0;
+            for (y, row) in small.iter().enumerate() {
+                for (x, value) in row.iter().enumerate() {
+                    sum += value * cos_u[x] * cos_v[y];
+                }
+            }
+            coefficients.push(sum);
+        }
+    }
+    let mut sorted: Vec<f64> = coefficients[1..].to_vec();
+    sorted.sort_by(f64::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    coefficients
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold(
+            0u64,
+            |hash, (i, c)| {
+                if *c > median { hash | 1 << i } else { hash }
+            },
+        ) as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 4]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut encoder = png::Encoder::new(&mut out, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().unwrap();
+        let data: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| pixel(x, y))
+            .collect();
+        writer.write_image_data(&data).unwrap();
+        writer.finish().unwrap();
+        out
+    }
+
+    fn uri(png: &[u8]) -> String {
+        format!("{}{}", DATA_URI_PREFIX, STANDARD.encode(png))
+    }
+
+    /// A bright disc on a icon, with enough structure for a stable pHash.
+    fn icon(x: u32, y: u32) -> [u8; 4] {
+        let (dx, dy) = (x as i32 - 24, y as i32 - 40);
+        if dx * dx + dy * dy < 200 {
+            [250, 220, 40, 255]
+        } else {
+            [(x * 4) as u8, (y * 4) as u8, 128, 255]
+        }
+    }
+
+    #[test]
+    fn test_decode() {
+        let png = encode(64, 64, icon);
+        let favicon = decode_favicon(&uri(&png)).unwrap();
+        assert_eq!(favicon.png, png);
+        assert_eq!(favicon.hash, format!("{:x}", md5::compute(&png)));
+
+        // Line-wrapped base64 decodes to the same favicon.
+        let wrapped = uri(&png)
+            .as_bytes()
+            .chunks(76)
+            .map(|c| std::str::from_utf8(c).unwrap())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(decode_favicon(&wrapped).unwrap(), favicon);
+        assert_eq!(split_favicon(Some(&wrapped)), (Some(favicon), None));
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert_eq!(decode_favicon("hello"), Err(FaviconError::NotDataUri));
+        assert_eq!(split_favicon(Some("hello")), (None, Some("hello".into())));
+        assert_eq!(split_favicon(None), (None, None));
+        assert_eq!(
+            decode_favicon("data:image/png;base64,!!!"),
+            Err(FaviconError::InvalidBase64)
+        );
+        assert_eq!(decode_favicon(&uri(b"GIF89a")), Err(FaviconError::NotPng));
+        assert_eq!(
+            decode_favicon(&uri(&encode(32, 32, icon))),
+            Err(FaviconError::WrongSize {
+                width: 32,
+                height: 32
+            })
+        );
+        let mut truncated = encode(64, 64, icon);
+        truncated.truncate(60);
+        assert!(matches!(
+            decode_favicon(&uri(&truncated)),
+            Err(FaviconError::InvalidPng(_))
+        ));
+    }
+
+    #[test]
+    fn test_phash() {
+        let distance = |a: i64, b: i64| (a ^ b).count_ones();
+        let base = decode_favicon(&uri(&encode(64, 64, icon))).unwrap();
+        // A few changed pixels: a different file, nearly the same hash.
+        let touched = decode_favicon(&uri(&encode(64, 64, |x, y| {
+            if x < 3 && y < 3 {
+                [255, 255, 255, 255]
+            } else {
+                icon(x, y)
+            }
+        })))
+        .unwrap();
+        let other = decode_favicon(&uri(&encode(64, 64, |x, y| {
+            let v = if (x / 8 + y / 8) % 2 == 0 { 255 } else { 0 };
+            [v, v, v, 255]
+        })))
+        .unwrap();
+        assert_ne!(base.hash, touched.hash);
+        assert!(distance(base.phash, touched.phash) <= 4);
+        assert!(distance(base.phash, other.phash) > 16);
+    }
+}
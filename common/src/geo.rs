@@ -0,0 +1,233 @@
+//! Offline GeoIP and ASN lookups from local MaxMind-format (mmdb) databases
+//! or an iptoasn.com TSV dump. Everything is loaded into memory at startup.
+
+use std::{
+    fmt, fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+};
+
+use maxminddb::{MaxMindDBError, Reader, geoip2};
+use serde::Deserialize;
+
+/// GeoIP settings, shared by the scanner and verifier configs. Every source is
+/// optional, without any lookups return nothing.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct GeoConfig {
+    /// GeoLite2 / GeoIP2 City (or Country) mmdb, for country and city.
+    #[serde(default)]
+    pub geoip_city_db: Option<PathBuf>,
+    /// GeoLite2 / GeoIP2 ASN mmdb, for ASN and AS organization.
+    #[serde(default)]
+    pub geoip_asn_db: Option<PathBuf>,
+    /// iptoasn.com `ip2asn-v4.tsv` or `ip2asn-combined.tsv`, for ASN and AS
+    /// organization, and country when there is no City database.
+    #[serde(default)]
+    pub iptoasn_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GeoInfo {
+    /// ISO 3166-1 alpha-2.
+    pub country: Option<String>,
+    /// English name.
+    pub city: Option<String>,
+    pub asn: Option<u32>,
+    pub as_org: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum GeoError {
+    Io(PathBuf, io::Error),
+    Mmdb(PathBuf, MaxMindDBError),
+    /// A line of the iptoasn file that is not `start end asn country org`.
+    InvalidLine(PathBuf, usize),
+}
+
+impl fmt::Display for GeoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GeoError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            GeoError::Mmdb(path, e) => write!(f, "{}: {}", path.display(), e),
+            GeoError::InvalidLine(path, line) => {
+                write!(f, "{}:{}: not an iptoasn line", path.display(), line)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GeoError {}
+
+struct AsnRange {
+    start: u128,
+    end: u128,
+    asn: u32,
+    country: Option<String>,
+    org: Option<String>,
+}
+
+/// Sorted, non-overlapping ranges from an iptoasn dump.
+#[derive(Default)]
+struct AsnTable {
+    ranges: Vec<AsnRange>,
+}
+
+/// IPv4 addresses are looked up as IPv4-mapped IPv6, so both fit in one table.
+fn key(ip: IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(ip) => u128::from(ip.to_ipv6_mapped()),
+        IpAddr::V6(ip) => u128::from(ip),
+    }
+}
+
+impl AsnTable {
+    fn parse(data: &str) -> Result<AsnTable, usize> {
+        let mut ranges = Vec::new();
+        for (i, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = line.splitn(5, '\t');
+            let mut next = || fields.next().map(str::trim);
+            let (Some(start), Some(end), Some(asn), country, org) =
+                (next(), next(), next(), next(), next())
+            else {
+                return Err(i + 1);
+            };
+            let (Ok(start), Ok(end), Ok(asn)) = (
+                start.parse::<IpAddr>(),
+                end.parse::<IpAddr>(),
+                asn.parse::<u32>(),
+            ) else {
+                return Err(i + 1);
+            };
+            // AS 0 marks address space that is not routed.
+            if asn == 0 {
+                continue;
+            }
+            let known = |s: Option<&str>| {
+                s.filter(|s| !s.is_empty() && *s != "None" && *s != "Unknown")
+                    .map(str::to_string)
+            };
+            ranges.push(AsnRange {
+                start: key(start),
+                end: key(end),
+                asn,
+                country: known(country),
+                org: known(org),
+            });
+        }
+        ranges.sort_by_key(|r| r.start);
+        Ok(AsnTable { ranges })
+    }
+
+    fn lookup(&self, ip: IpAddr) -> Option<&AsnRange> {
+        let ip = key(ip);
+        let i = self.ranges.partition_point(|r| r.start <= ip);
+        self.ranges[..i].last().filter(|r| r.end >= ip)
+    }
+}
+
+#[derive(Default)]
+pub struct Geo {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+    iptoasn: Option<AsnTable>,
+}
+
+fn open_mmdb(path: &Path) -> Result<Reader<Vec<u8>>, GeoError> {
+    Reader::open_readfile(path).map_err(|e| GeoError::Mmdb(path.to_path_buf(), e))
+}
+
+pub fn load_geo(config: &GeoConfig) -> Result<Geo, GeoError> {
+    let iptoasn = match &config.iptoasn_file {
+        Some(path) => {
+            let data = fs::read_to_string(path).map_err(|e| GeoError::Io(path.clone(), e))?;
+            Some(AsnTable::parse(&data).map_err(|line| GeoError::InvalidLine(path.clone(), line))?)
+        }
+        None => None,
+    };
+    Ok(Geo {
+        city: config.geoip_city_db.as_deref().map(open_mmdb).transpose()?,
+        asn: config.geoip_asn_db.as_deref().map(open_mmdb).transpose()?,
+        iptoasn,
+    })
+}
+
+impl Geo {
+    pub fn is_enabled(&self) -> bool {
+        self.city.is_some() || self.asn.is_some() || self.iptoasn.is_some()
+    }
+
+    /// Everything the configured databases know about `ip`. The mmdb files win
+    /// over the iptoasn dump where both have a field.
+    pub fn lookup(&self, ip: IpAddr) -> GeoInfo {
+        let mut info = GeoInfo::default();
+        if let Some(reader) = &self.city
+            && let Ok(city) = reader.lookup::<geoip2::City>(ip)
+        {
+            info.country = city.country.and_then(|c| c.iso_code).map(str::to_string);
+            info.city = city
+                .city
+                .and_then(|c| c.names)
+                .and_then(|names| names.get("en").map(|n| n.to_string()));
+        }
+        if let Some(reader) = &self.asn
+            && let Ok(asn) = reader.lookup::<geoip2::Asn>(ip)
+        {
+            info.asn = asn.autonomous_system_number;
+            info.as_org = asn.autonomous_system_organization.map(str::to_string);
+        }
+        if let Some(range) = self.iptoasn.as_ref().and_then(|t| t.lookup(ip)) {
+            info.asn = info.asn.or(Some(range.asn));
+            info.as_org = info.as_org.or_else(|| range.org.clone());
+            info.country = info.country.or_else(|| range.country.clone());
+        }
+        info
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iptoasn() {
+        let table = AsnTable::parse(
+            "1.0.4.0\t1.0.7.255\t38803\tAU\tWPL-AS-AP Wirefreebroadband Pty Ltd\n\
+             1.0.0.0\t1.0.0.255\t13335\tUS\tCLOUDFLARENET\n\
+             1.0.1.0\t1.0.3.255\t0\tNone\tNot routed\n\
+             2606:4700::\t2606:4700:ffff:ffff:ffff:ffff:ffff:ffff\t13335\tUS\tCLOUDFLARENET\n",
+        )
+        .unwrap();
+        let geo = Geo {
+            iptoasn: Some(table),
+            ..Default::default()
+        };
+        assert!(geo.is_enabled());
+
+        let info = geo.lookup("1.0.0.1".parse().unwrap());
+        assert_eq!(info.asn, Some(13335));
+        assert_eq!(info.as_org.as_deref(), Some("CLOUDFLARENET"));
+        assert_eq!(info.country.as_deref(), Some("US"));
+        assert_eq!(info.city, None);
+
+        assert_eq!(geo.lookup("1.0.5.9".parse().unwrap()).asn, Some(38803));
+        assert_eq!(
+            geo.lookup("2606:4700::1111".parse().unwrap()).asn,
+            Some(13335)
+        );
+        // Not routed, between ranges and past the last one.
+        assert_eq!(geo.lookup("1.0.2.1".parse().unwrap()), GeoInfo::default());
+        assert_eq!(geo.lookup("9.9.9.9".parse().unwrap()), GeoInfo::default());
+        assert_eq!(geo.lookup("0.0.0.1".parse().unwrap()), GeoInfo::default());
+    }
+
+    #[test]
+    fn test_invalid_iptoasn() {
+        assert_eq!(
+            AsnTable::parse("1.0.0.0\t1.0.0.255\t13335\tUS\tX\nnonsense\n").err(),
+            Some(2)
+        );
+    }
+}
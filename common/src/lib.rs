@@ -0,0 +1,22 @@
+//! Code shared by the scanner, the verifier and the honeypot: the Minecraft
+//! protocol codec, the IP blacklist and (with the `db` feature) the database
+//! model. Probing, GeoIP, reverse DNS and favicon decoding have features of
+//! their own, see `Cargo.toml`.
+
+pub mod blacklist;
+pub mod chat;
+#[cfg(feature = "db")]
+pub mod db;
+#[cfg(feature = "favicon")]
+pub mod favicon;
+#[cfg(feature = "geo")]
+pub mod geo;
+pub mod packets;
+#[cfg(feature = "probe")]
+pub mod probe;
+#[cfg(feature = "rdns")]
+pub mod rdns;
+pub mod sanitize;
+pub mod suspicion;
+pub mod utils;
+pub mod versions;
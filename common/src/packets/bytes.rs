@@ -0,0 +1,63 @@
+use super::{
+    error::PacketError,
+    varint::{read_var_int, write_var_int},
+};
+
+/// Read a var int length prefixed byte array of at most `max_len` bytes.
+pub fn read_byte_array(
+    data: &[u8],
+    index: &mut usize,
+    max_len: usize,
+) -> Result<Vec<u8>, PacketError> {
+    let mut offset = *index;
+    let length = read_var_int(data, Some(&mut offset))?;
+    let length = usize::try_from(length).map_err(|_| PacketError::NegativeLength(length.into()))?;
+    if length > max_len {
+        return Err(PacketError::FrameTooLarge {
+            len: length,
+            max: max_len,
+        });
+    }
+    let end_pos = offset
+        .checked_add(length)
+        .ok_or(PacketError::UnexpectedEof)?;
+    let bytes = data
+        .get(offset..end_pos)
+        .ok_or(PacketError::UnexpectedEof)?;
+    *index = end_pos;
+    Ok(bytes.to_vec())
+}
+
+pub fn write_byte_array(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    write_var_int(buffer, &(bytes.len() as i32));
+    buffer.extend_from_slice(bytes);
+}
+
+/// Read a boolean sent as one byte, anything but 0 is true.
+pub fn read_bool(data: &[u8], index: &mut usize) -> Result<bool, PacketError> {
+    let byte = *data.get(*index).ok_or(PacketError::UnexpectedEof)?;
+    *index += 1;
+    Ok(byte != 0)
+}
+
+pub fn write_bool(buffer: &mut Vec<u8>, value: bool) {
+    buffer.push(u8::from(value));
+}
+
+/// Read a UUID sent as two big endian longs.
+pub fn read_uuid(data: &[u8], index: &mut usize) -> Result<u128, PacketError> {
+    const SIZE: usize = 16;
+
+    let bytes = data
+        .get(*index..index.saturating_add(SIZE))
+        .ok_or(PacketError::UnexpectedEof)?;
+    let mut value = [0u8; SIZE];
+    value.copy_from_slice(bytes);
+    *index += SIZE;
+
+    Ok(u128::from_be_bytes(value))
+}
+
+pub fn write_uuid(buffer: &mut Vec<u8>, uuid: u128) {
+    buffer.extend_from_slice(&uuid.to_be_bytes());
+}
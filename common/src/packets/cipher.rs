@@ -0,0 +1,72 @@
+use aes::{
+    Aes128,
+    cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit, inout::InOutBuf},
+};
+
+/// Stream cipher applied to every byte on the wire once encryption is enabled.
+///
+/// Encryption and decryption keep separate state, so one instance can serve
+/// both directions of a connection.
+pub trait Cipher: Send {
+    fn encrypt(&mut self, data: &mut [u8]);
+    fn decrypt(&mut self, data: &mut [u8]);
+}
+
+/// AES-128 in CFB8 mode, the vanilla cipher. The shared secret is both key and IV.
+pub struct Aes128Cfb8 {
+    encryptor: cfb8::Encryptor<Aes128>,
+    decryptor: cfb8::Decryptor<Aes128>,
+}
+
+impl Aes128Cfb8 {
+    pub fn new(shared_secret: &[u8; 16]) -> Self {
+        Aes128Cfb8 {
+            encryptor: cfb8::Encryptor::new(shared_secret.into(), shared_secret.into()),
+            decryptor: cfb8::Decryptor::new(shared_secret.into(), shared_secret.into()),
+        }
+    }
+}
+
+impl Cipher for Aes128Cfb8 {
+    fn encrypt(&mut self, data: &mut [u8]) {
+        let (blocks, _) = InOutBuf::from(data).into_chunks();
+        self.encryptor.encrypt_blocks_inout_mut(blocks);
+    }
+
+    fn decrypt(&mut self, data: &mut [u8]) {
+        let (blocks, _) = InOutBuf::from(data).into_chunks();
+        self.decryptor.decrypt_blocks_inout_mut(blocks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
+    #[test]
+    fn test_known_ciphertext() {
+        let mut data = *b"minecraft";
+        Aes128Cfb8::new(&SECRET).encrypt(&mut data);
+        assert_eq!(data, [103, 161, 53, 250, 102, 221, 10, 211, 83]);
+    }
+
+    #[test]
+    fn test_stream_state_carries_over() {
+        let mut whole = *b"minecraft";
+        Aes128Cfb8::new(&SECRET).encrypt(&mut whole);
+
+        let mut cipher = Aes128Cfb8::new(&SECRET);
+        let mut split = *b"minecraft";
+        let (head, tail) = split.split_at_mut(4);
+        cipher.encrypt(head);
+        cipher.encrypt(tail);
+        assert_eq!(split, whole);
+
+        let mut cipher = Aes128Cfb8::new(&SECRET);
+        cipher.decrypt(&mut split[..2]);
+        cipher.decrypt(&mut split[2..]);
+        assert_eq!(&split, b"minecraft");
+    }
+}
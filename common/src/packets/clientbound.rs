@@ -0,0 +1,199 @@
+//! Packets sent by the server, read by the scanner and verifier and written by the honeypot.
+
+use super::{
+    Packet,
+    bytes::{read_byte_array, read_uuid, write_byte_array, write_uuid},
+    error::PacketError,
+    frame::Limits,
+    i64::{read_i64, write_i64},
+    string::{read_string_max, write_string},
+    varint::{read_var_int, write_var_int},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusResponse {
+    pub json: String,
+}
+
+impl Packet for StatusResponse {
+    const ID: i32 = 0x00;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        write_string(buf, &self.json)
+    }
+
+    fn read_body(data: &[u8], index: &mut usize, limits: &Limits) -> Result<Self, PacketError> {
+        Ok(StatusResponse {
+            json: read_string_max(data, index, limits.max_string_len)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PongResponse {
+    pub payload: i64,
+}
+
+impl Packet for PongResponse {
+    const ID: i32 = 0x01;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        write_i64(buf, self.payload);
+        Ok(())
+    }
+
+    fn read_body(data: &[u8], index: &mut usize, _limits: &Limits) -> Result<Self, PacketError> {
+        Ok(PongResponse {
+            payload: read_i64(data, index)?,
+        })
+    }
+}
+
+/// Disconnect during login, `reason` is a JSON chat component.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginDisconnect {
+    pub reason: String,
+}
+
+impl Packet for LoginDisconnect {
+    const ID: i32 = 0x00;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        write_string(buf, &self.reason)
+    }
+
+    fn read_body(data: &[u8], index: &mut usize, limits: &Limits) -> Result<Self, PacketError> {
+        Ok(LoginDisconnect {
+            reason: read_string_max(data, index, limits.max_string_len)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncryptionRequest {
+    pub server_id: String,
+    pub public_key: Vec<u8>,
+    pub verify_token: Vec<u8>,
+}
+
+impl Packet for EncryptionRequest {
+    const ID: i32 = 0x01;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        write_string(buf, &self.server_id)?;
+        write_byte_array(buf, &self.public_key);
+        write_byte_array(buf, &self.verify_token);
+        Ok(())
+    }
+
+    fn read_body(data: &[u8], index: &mut usize, limits: &Limits) -> Result<Self, PacketError> {
+        Ok(EncryptionRequest {
+            server_id: read_string_max(data, index, limits.max_string_len)?,
+            public_key: read_byte_array(data, index, limits.max_frame_size)?,
+            verify_token: read_byte_array(data, index, limits.max_frame_size)?,
+        })
+    }
+}
+
+/// Login Success in the 1.18 layout; newer fields (properties) are ignored on read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginSuccess {
+    pub uuid: u128,
+    pub username: String,
+}
+
+impl Packet for LoginSuccess {
+    const ID: i32 = 0x02;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        write_uuid(buf, self.uuid);
+        write_string(buf, &self.username)
+    }
+
+    fn read_body(data: &[u8], index: &mut usize, limits: &Limits) -> Result<Self, PacketError> {
+        Ok(LoginSuccess {
+            uuid: read_uuid(data, index)?,
+            username: read_string_max(data, index, limits.max_string_len)?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetCompression {
+    pub threshold: i32,
+}
+
+impl Packet for SetCompression {
+    const ID: i32 = 0x03;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        write_var_int(buf, &self.threshold);
+        Ok(())
+    }
+
+    fn read_body(data: &[u8], index: &mut usize, _limits: &Limits) -> Result<Self, PacketError> {
+        Ok(SetCompression {
+            threshold: read_var_int(data, Some(index))?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip<P: Packet + PartialEq + std::fmt::Debug>(packet: P) {
+        let encoded = packet.encode().unwrap();
+        let mut index = 0;
+        read_var_int(&encoded, Some(&mut index)).unwrap();
+        let decoded = P::decode(&encoded[index..], &Limits::default()).unwrap();
+        assert_eq!(decoded, packet);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip(StatusResponse {
+            json: r#"{"description":"hi"}"#.to_string(),
+        });
+        roundtrip(PongResponse { payload: 1234 });
+        roundtrip(LoginDisconnect {
+            reason: r#"{"text":"bye"}"#.to_string(),
+        });
+        roundtrip(EncryptionRequest {
+            server_id: String::new(),
+            public_key: vec![1, 2, 3],
+            verify_token: vec![4, 5, 6, 7],
+        });
+        roundtrip(LoginSuccess {
+            uuid: 7,
+            username: "kybe".to_string(),
+        });
+        roundtrip(SetCompression { threshold: 256 });
+    }
+
+    #[test]
+    fn test_status_response_limit() {
+        let encoded = StatusResponse {
+            json: "x".repeat(100),
+        }
+        .encode()
+        .unwrap();
+        let limits = Limits {
+            max_frame_size: 1024,
+            max_string_len: 50,
+        };
+        assert!(matches!(
+            StatusResponse::decode(&encoded[1..], &limits),
+            Err(PacketError::StringTooLong { len: 100, max: 50 })
+        ));
+    }
+
+    #[test]
+    fn test_truncated_body() {
+        let encoded = PongResponse { payload: 1 }.encode().unwrap();
+        assert!(matches!(
+            PongResponse::decode(&encoded[1..5], &Limits::default()),
+            Err(PacketError::UnexpectedEof)
+        ));
+    }
+}
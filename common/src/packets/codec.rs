@@ -0,0 +1,349 @@
+use std::io::{Read, Write};
+
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    time::{Instant, timeout_at},
+};
+
+use super::{
+    Packet,
+    cipher::Cipher,
+    error::PacketError,
+    frame::Limits,
+    varint::{read_var_int, write_var_int},
+};
+
+/// Framing state of one connection: optional zlib compression (after Set
+/// Compression) and an optional cipher (after the encryption handshake).
+///
+/// The codec only works on byte buffers, so it can be driven by any transport
+/// and tested against recorded fixtures.
+pub struct PacketCodec {
+    limits: Limits,
+    compression_threshold: Option<usize>,
+    cipher: Option<Box<dyn Cipher>>,
+    /// Received bytes, already decrypted, that do not form a whole frame yet.
+    buffer: Vec<u8>,
+}
+
+impl PacketCodec {
+    pub fn new(limits: Limits) -> Self {
+        PacketCodec {
+            limits,
+            compression_threshold: None,
+            cipher: None,
+            buffer: Vec::new(),
+        }
+    }
+
+    /// Apply a Set Compression threshold, a negative value disables compression.
+    pub fn set_compression(&mut self, threshold: i32) {
+        self.compression_threshold = usize::try_from(threshold).ok();
+    }
+
+    /// Encrypt everything written and decrypt everything fed from now on.
+    pub fn enable_encryption(&mut self, cipher: Box<dyn Cipher>) {
+        self.cipher = Some(cipher);
+    }
+
+    pub fn compression_threshold(&self) -> Option<usize> {
+        self.compression_threshold
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Bytes fed that do not form a whole frame yet.
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Encode a packet into the bytes to write to the socket.
+    pub fn encode<P: Packet>(&mut self, packet: &P) -> Result<Vec<u8>, PacketError> {
+        let mut data = Vec::new();
+        write_var_int(&mut data, &P::ID);
+        packet.write_body(&mut data)?;
+        self.encode_raw(&data)
+    }
+
+    /// Frame, compress and encrypt a packet id followed by its body.
+    pub fn encode_raw(&mut self, data: &[u8]) -> Result<Vec<u8>, PacketError> {
+        let body = match self.compression_threshold {
+            None => data.to_vec(),
+            Some(threshold) => {
+                let mut body = Vec::new();
+                if data.len() >= threshold {
+                    write_var_int(&mut body, &(data.len() as i32));
+                    let mut encoder = ZlibEncoder::new(body, Compression::default());
+                    encoder.write_all(data)?;
+                    body = encoder.finish()?;
+                } else {
+                    write_var_int(&mut body, &0);
+                    body.extend_from_slice(data);
+                }
+                body
+            }
+        };
+        let mut out = Vec::with_capacity(body.len() + 5);
+        write_var_int(&mut out, &(body.len() as i32));
+        out.extend_from_slice(&body);
+        if let Some(cipher) = self.cipher.as_mut() {
+            cipher.encrypt(&mut out);
+        }
+        Ok(out)
+    }
+
+    /// Hand received bytes to the codec.
+    pub fn feed(&mut self, data: &[u8]) {
+        let start = self.buffer.len();
+        self.buffer.extend_from_slice(data);
+        if let Some(cipher) = self.cipher.as_mut() {
+            cipher.decrypt(&mut self.buffer[start..]);
+        }
+    }
+
+    /// Take the next whole packet (packet id and data) out of the fed bytes,
+    /// `None` if more bytes are needed.
+    pub fn next_packet(&mut self) -> Result<Option<Vec<u8>>, PacketError> {
+        let mut index = 0;
+        let len = match read_var_int(&self.buffer, Some(&mut index)) {
+            Ok(len) => len,
+            Err(PacketError::UnexpectedEof) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let len = usize::try_from(len).map_err(|_| PacketError::NegativeLength(len.into()))?;
+        if len == 0 {
+            return Err(PacketError::UnexpectedEof);
+        }
+        if len > self.limits.max_frame_size {
+            return Err(PacketError::FrameTooLarge {
+                len,
+                max: self.limits.max_frame_size,
+            });
+        }
+        if self.buffer.len() - index < len {
+            return Ok(None);
+        }
+        let frame: Vec<u8> = self.buffer.drain(..index + len).skip(index).collect();
+        match self.compression_threshold {
+            None => Ok(Some(frame)),
+            Some(_) => self.decompress(&frame).map(Some),
+        }
+    }
+
+    fn decompress(&self, frame: &[u8]) -> Result<Vec<u8>, PacketError> {
+        let mut index = 0;
+        let data_len = read_var_int(frame, Some(&mut index))?;
+        let data_len =
+            usize::try_from(data_len).map_err(|_| PacketError::NegativeLength(data_len.into()))?;
+        if data_len == 0 {
+            return Ok(frame[index..].to_vec());
+        }
+        if data_len > self.limits.max_frame_size {
+            return Err(PacketError::FrameTooLarge {
+                len: data_len,
+                max: self.limits.max_frame_size,
+            });
+        }
+        // Read one byte past the declared size so a lying peer is detected
+        // without inflating the rest of a zip bomb.
+        let mut data = Vec::with_capacity(data_len.min(64 * 1024));
+        ZlibDecoder::new(&frame[index..])
+            .take(data_len as u64 + 1)
+            .read_to_end(&mut data)?;
+        if data.len() != data_len {
+            return Err(PacketError::DecompressedSizeMismatch {
+                declared: data_len,
+                actual: data.len(),
+            });
+        }
+        Ok(data)
+    }
+}
+
+/// Read from `stream` until the codec yields a whole packet, failing with
+/// [`PacketError::Timeout`] once `deadline` passes.
+pub async fn read_packet_until<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    codec: &mut PacketCodec,
+    deadline: Instant,
+) -> Result<Vec<u8>, PacketError> {
+    let mut chunk = [0u8; 4096];
+    loop {
+        if let Some(packet) = codec.next_packet()? {
+            return Ok(packet);
+        }
+        let n = timeout_at(deadline, stream.read(&mut chunk))
+            .await
+            .map_err(|_| PacketError::Timeout)??;
+        if n == 0 {
+            return Err(PacketError::UnexpectedEof);
+        }
+        codec.feed(&chunk[..n]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::{
+        cipher::Aes128Cfb8,
+        clientbound::{SetCompression, StatusResponse},
+    };
+
+    const SECRET: [u8; 16] = [7; 16];
+
+    fn decode_all(codec: &mut PacketCodec, bytes: &[u8]) -> Vec<Vec<u8>> {
+        codec.feed(bytes);
+        let mut packets = Vec::new();
+        while let Some(packet) = codec.next_packet().unwrap() {
+            packets.push(packet);
+        }
+        packets
+    }
+
+    #[test]
+    fn test_plain_matches_packet_encode() {
+        let packet = SetCompression { threshold: 256 };
+        let mut codec = PacketCodec::new(Limits::default());
+        assert_eq!(codec.encode(&packet).unwrap(), packet.encode().unwrap());
+    }
+
+    #[test]
+    fn test_compression_threshold() {
+        let mut codec = PacketCodec::new(Limits::default());
+        codec.set_compression(64);
+
+        let small = codec.encode_raw(&[0x01, 0x02]).unwrap();
+        assert_eq!(small, vec![3, 0, 0x01, 0x02]);
+
+        let large = StatusResponse {
+            json: "a".repeat(300),
+        };
+        let encoded = codec.encode(&large).unwrap();
+        assert!(encoded.len() < 100);
+
+        let packets = decode_all(&mut codec, &[small, encoded].concat());
+        assert_eq!(packets[0], vec![0x01, 0x02]);
+        assert_eq!(
+            StatusResponse::decode(&packets[1], &Limits::default()).unwrap(),
+            large
+        );
+    }
+
+    #[test]
+    fn test_zlib_fixture() {
+        // 300 times "a" compressed by python's zlib.compress.
+        let compressed = [120, 156, 75, 76, 28, 5, 196, 2, 0, 216, 168, 113, 173];
+        let mut frame = Vec::new();
+        write_var_int(&mut frame, &300);
+        frame.extend_from_slice(&compressed);
+        let mut bytes = Vec::new();
+        write_var_int(&mut bytes, &(frame.len() as i32));
+        bytes.extend_from_slice(&frame);
+
+        let mut codec = PacketCodec::new(Limits::default());
+        codec.set_compression(256);
+        assert_eq!(decode_all(&mut codec, &bytes), vec![vec![b'a'; 300]]);
+    }
+
+    #[test]
+    fn test_encrypted_roundtrip_byte_by_byte() {
+        let mut server = PacketCodec::new(Limits::default());
+        server.set_compression(16);
+        server.enable_encryption(Box::new(Aes128Cfb8::new(&SECRET)));
+        let mut client = PacketCodec::new(Limits::default());
+        client.set_compression(16);
+        client.enable_encryption(Box::new(Aes128Cfb8::new(&SECRET)));
+
+        let packet = StatusResponse {
+            json: "hello ".repeat(20),
+        };
+        let mut bytes = server.encode(&packet).unwrap();
+        bytes.extend(server.encode(&SetCompression { threshold: 1 }).unwrap());
+
+        let mut packets = Vec::new();
+        for byte in bytes {
+            packets.extend(decode_all(&mut client, &[byte]));
+        }
+        assert_eq!(packets.len(), 2);
+        assert_eq!(
+            StatusResponse::decode(&packets[0], &Limits::default()).unwrap(),
+            packet
+        );
+        assert_eq!(
+            SetCompression::decode(&packets[1], &Limits::default())
+                .unwrap()
+                .threshold,
+            1
+        );
+    }
+
+    #[test]
+    fn test_declared_size_is_enforced() {
+        let limits = Limits {
+            max_frame_size: 128,
+            max_string_len: 128,
+        };
+        let mut sender = PacketCodec::new(Limits::default());
+        sender.set_compression(0);
+        let bomb = sender.encode_raw(&vec![0u8; 4096]).unwrap();
+
+        let mut codec = PacketCodec::new(limits);
+        codec.set_compression(0);
+        codec.feed(&bomb);
+        assert!(matches!(
+            codec.next_packet(),
+            Err(PacketError::FrameTooLarge {
+                len: 4096,
+                max: 128
+            })
+        ));
+
+        // Claims 10 bytes, inflates to 4096.
+        let mut frame = Vec::new();
+        write_var_int(&mut frame, &10);
+        frame.extend_from_slice(&bomb[3..]);
+        let mut bytes = Vec::new();
+        write_var_int(&mut bytes, &(frame.len() as i32));
+        bytes.extend_from_slice(&frame);
+        let mut codec = PacketCodec::new(limits);
+        codec.set_compression(0);
+        codec.feed(&bytes);
+        assert!(matches!(
+            codec.next_packet(),
+            Err(PacketError::DecompressedSizeMismatch {
+                declared: 10,
+                actual: 11
+            })
+        ));
+    }
+
+    #[test]
+    fn test_garbage_compressed_data() {
+        let mut codec = PacketCodec::new(Limits::default());
+        codec.set_compression(0);
+        codec.feed(&[4, 10, 0xde, 0xad, 0xbe]);
+        assert!(codec.next_packet().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_packet_until() {
+        let mut codec = PacketCodec::new(Limits::default());
+        let bytes = codec.encode_raw(&[0x00, 0x2a]).unwrap();
+        let mut stream = &bytes[..];
+        let deadline = Instant::now() + std::time::Duration::from_secs(1);
+        assert_eq!(
+            read_packet_until(&mut stream, &mut codec, deadline)
+                .await
+                .unwrap(),
+            vec![0x00, 0x2a]
+        );
+        assert!(matches!(
+            read_packet_until(&mut stream, &mut codec, deadline).await,
+            Err(PacketError::UnexpectedEof)
+        ));
+    }
+}
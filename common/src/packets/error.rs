@@ -0,0 +1,84 @@
+use std::fmt;
+
+/// Everything that can go wrong while encoding or decoding packets.
+///
+/// Decoding never panics, a hostile peer can at most produce one of these.
+#[derive(Debug)]
+pub enum PacketError {
+    Io(std::io::Error),
+    /// The buffer ended in the middle of a value.
+    UnexpectedEof,
+    /// A var int used more bytes than its type allows.
+    VarIntTooBig,
+    /// A length prefix was negative.
+    NegativeLength(i64),
+    /// A frame announced more bytes than the configured maximum.
+    FrameTooLarge {
+        len: usize,
+        max: usize,
+    },
+    /// A string is longer than the configured (or protocol) maximum.
+    StringTooLong {
+        len: usize,
+        max: usize,
+    },
+    InvalidUtf8,
+    /// A frame carried a different packet than the one expected in this state.
+    UnexpectedPacketId {
+        expected: i32,
+        got: i32,
+    },
+    /// A compressed packet inflated to a different size than it declared.
+    DecompressedSizeMismatch {
+        declared: usize,
+        actual: usize,
+    },
+    /// The read deadline passed before the frame was complete.
+    Timeout,
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PacketError::Io(e) => write!(f, "io error: {}", e),
+            PacketError::UnexpectedEof => write!(f, "unexpected end of data"),
+            PacketError::VarIntTooBig => write!(f, "var_int is too big"),
+            PacketError::NegativeLength(len) => write!(f, "negative length: {}", len),
+            PacketError::FrameTooLarge { len, max } => {
+                write!(f, "frame of {} bytes exceeds maximum of {}", len, max)
+            }
+            PacketError::StringTooLong { len, max } => {
+                write!(f, "string of {} exceeds maximum of {}", len, max)
+            }
+            PacketError::InvalidUtf8 => write!(f, "string is not valid utf-8"),
+            PacketError::UnexpectedPacketId { expected, got } => {
+                write!(f, "expected packet id {:#04x}, got {:#04x}", expected, got)
+            }
+            PacketError::DecompressedSizeMismatch { declared, actual } => write!(
+                f,
+                "compressed packet declared {} bytes, inflated to {}",
+                declared, actual
+            ),
+            PacketError::Timeout => write!(f, "read deadline exceeded"),
+        }
+    }
+}
+
+impl std::error::Error for PacketError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            PacketError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for PacketError {
+    fn from(e: std::io::Error) -> Self {
+        if e.kind() == std::io::ErrorKind::UnexpectedEof {
+            PacketError::UnexpectedEof
+        } else {
+            PacketError::Io(e)
+        }
+    }
+}
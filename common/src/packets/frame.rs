@@ -0,0 +1,151 @@
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    time::{Instant, timeout_at},
+};
+
+use super::{error::PacketError, varint::read_var_int_from_stream};
+
+/// Largest frame the vanilla protocol can express with a 3 byte length prefix.
+pub const DEFAULT_MAX_FRAME_SIZE: usize = 2 * 1024 * 1024;
+
+/// Size limits applied to everything read from a remote peer.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub max_frame_size: usize,
+    /// Maximum UTF-8 byte length of a single string (e.g. the status JSON).
+    pub max_string_len: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_string_len: DEFAULT_MAX_FRAME_SIZE,
+        }
+    }
+}
+
+/// Read one length prefixed frame (packet id and data).
+///
+/// The declared length is checked against `limits` and the buffer only grows
+/// as bytes actually arrive, so a peer cannot make us allocate what it claims.
+pub async fn read_frame<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    limits: &Limits,
+) -> Result<Vec<u8>, PacketError> {
+    let len = read_var_int_from_stream(stream).await?;
+    let len = usize::try_from(len).map_err(|_| PacketError::NegativeLength(len.into()))?;
+    if len == 0 {
+        return Err(PacketError::UnexpectedEof);
+    }
+    if len > limits.max_frame_size {
+        return Err(PacketError::FrameTooLarge {
+            len,
+            max: limits.max_frame_size,
+        });
+    }
+    let mut buffer = Vec::with_capacity(len.min(64 * 1024));
+    (&mut *stream)
+        .take(len as u64)
+        .read_to_end(&mut buffer)
+        .await?;
+    if buffer.len() < len {
+        return Err(PacketError::UnexpectedEof);
+    }
+    Ok(buffer)
+}
+
+/// [`read_frame`], but fail with [`PacketError::Timeout`] once `deadline` passes.
+pub async fn read_frame_until<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    limits: &Limits,
+    deadline: Instant,
+) -> Result<Vec<u8>, PacketError> {
+    timeout_at(deadline, read_frame(stream, limits))
+        .await
+        .map_err(|_| PacketError::Timeout)?
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::packets::test_util::xorshift;
+
+    fn framed(body: &[u8]) -> Vec<u8> {
+        let mut out = vec![body.len() as u8];
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[tokio::test]
+    async fn test_read_frame() {
+        let data = framed(&[0x00, 1, 2, 3]);
+        let frame = read_frame(&mut data.as_slice(), &Limits::default())
+            .await
+            .unwrap();
+        assert_eq!(frame, vec![0x00, 1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_frame_limits() {
+        let limits = Limits {
+            max_frame_size: 3,
+            max_string_len: 3,
+        };
+        let data = framed(&[0; 4]);
+        assert!(matches!(
+            read_frame(&mut data.as_slice(), &limits).await,
+            Err(PacketError::FrameTooLarge { len: 4, max: 3 })
+        ));
+
+        let claims_huge = [0xFF, 0xFF, 0xFF, 0xFF, 0x07];
+        assert!(matches!(
+            read_frame(&mut claims_huge.as_slice(), &Limits::default()).await,
+            Err(PacketError::FrameTooLarge { .. })
+        ));
+
+        let negative = [0xFF, 0xFF, 0xFF, 0xFF, 0x0F];
+        assert!(matches!(
+            read_frame(&mut negative.as_slice(), &Limits::default()).await,
+            Err(PacketError::NegativeLength(-1))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_truncated_frame() {
+        let mut data = framed(&[0; 10]);
+        data.truncate(6);
+        assert!(matches!(
+            read_frame(&mut data.as_slice(), &Limits::default()).await,
+            Err(PacketError::UnexpectedEof)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let (mut client, _server) = tokio::io::duplex(64);
+        let deadline = Instant::now() + Duration::from_millis(20);
+        assert!(matches!(
+            read_frame_until(&mut client, &Limits::default(), deadline).await,
+            Err(PacketError::Timeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_frame_fuzz_never_panics() {
+        let limits = Limits {
+            max_frame_size: 64,
+            max_string_len: 64,
+        };
+        let mut state = 0xA076_1D64_78BD_642F;
+        for _ in 0..2_000 {
+            let len = (xorshift(&mut state) % 80) as usize;
+            let data: Vec<u8> = (0..len).map(|_| xorshift(&mut state) as u8).collect();
+            if let Ok(frame) = read_frame(&mut data.as_slice(), &limits).await {
+                assert!(!frame.is_empty() && frame.len() <= limits.max_frame_size);
+            }
+        }
+    }
+}
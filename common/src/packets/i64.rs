@@ -0,0 +1,18 @@
+use super::error::PacketError;
+
+pub fn read_i64(data: &[u8], index: &mut usize) -> Result<i64, PacketError> {
+    const SIZE: usize = 8;
+
+    let bytes = data
+        .get(*index..index.saturating_add(SIZE))
+        .ok_or(PacketError::UnexpectedEof)?;
+    let mut value = [0u8; SIZE];
+    value.copy_from_slice(bytes);
+    *index += SIZE;
+
+    Ok(i64::from_be_bytes(value))
+}
+
+pub fn write_i64(buffer: &mut Vec<u8>, number: i64) {
+    buffer.extend_from_slice(&number.to_be_bytes());
+}
@@ -0,0 +1,154 @@
+use serde_json::Value;
+
+use crate::{
+    chat::parse_description,
+    packets::{
+        Packet,
+        clientbound::{EncryptionRequest, LoginDisconnect, LoginSuccess, SetCompression},
+        error::PacketError,
+        frame::Limits,
+        varint::read_var_int,
+    },
+};
+
+/// Classification of the first packet a server sends after Login Start.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginState {
+    /// Encryption Request, the server authenticates players with Mojang.
+    OnlineMode,
+    /// Login Success without encryption, the server runs in offline mode.
+    OfflineMode,
+    /// Set Compression before any encryption, which only offline servers do.
+    Compression(i32),
+    /// Disconnect with the rendered reason (whitelist, outdated client, ban, ...).
+    Disconnect(String),
+    /// Any other packet id, e.g. a Login Plugin Request sent by a proxy.
+    Unknown(i32),
+}
+
+impl LoginState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LoginState::OnlineMode => "online",
+            LoginState::OfflineMode => "offline",
+            LoginState::Compression(_) => "compression",
+            LoginState::Disconnect(_) => "disconnect",
+            LoginState::Unknown(_) => "unknown",
+        }
+    }
+
+    pub fn disconnect_reason(&self) -> Option<&str> {
+        match self {
+            LoginState::Disconnect(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// Classify a login-state packet (without its length prefix).
+pub fn classify_login_reply(packet: &[u8], limits: &Limits) -> Result<LoginState, PacketError> {
+    let mut index = 0;
+    let packet_id = read_var_int(packet, Some(&mut index))?;
+    match packet_id {
+        LoginDisconnect::ID => {
+            let disconnect = LoginDisconnect::read_body(packet, &mut index, limits)?;
+            Ok(LoginState::Disconnect(render_reason(&disconnect.reason)))
+        }
+        EncryptionRequest::ID => Ok(LoginState::OnlineMode),
+        LoginSuccess::ID => Ok(LoginState::OfflineMode),
+        SetCompression::ID => {
+            let compression = SetCompression::read_body(packet, &mut index, limits)?;
+            Ok(LoginState::Compression(compression.threshold))
+        }
+        id => Ok(LoginState::Unknown(id)),
+    }
+}
+
+/// Disconnect reasons are chat components, but some servers send plain text
+/// (which may still carry legacy § codes).
+fn render_reason(reason: &str) -> String {
+    let value =
+        serde_json::from_str::<Value>(reason).unwrap_or_else(|_| Value::String(reason.to_string()));
+    parse_description(&value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::{string::write_string, varint::write_var_int};
+
+    fn packet(id: i32, body: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut data = Vec::new();
+        write_var_int(&mut data, &id);
+        body(&mut data);
+        data
+    }
+
+    #[test]
+    fn test_classify_encryption_and_success() {
+        assert_eq!(
+            classify_login_reply(&packet(0x01, |_| {}), &Limits::default()).unwrap(),
+            LoginState::OnlineMode
+        );
+        assert_eq!(
+            classify_login_reply(&packet(0x02, |_| {}), &Limits::default()).unwrap(),
+            LoginState::OfflineMode
+        );
+    }
+
+    #[test]
+    fn test_classify_compression() {
+        let data = packet(0x03, |buf| write_var_int(buf, &256));
+        assert_eq!(
+            classify_login_reply(&data, &Limits::default()).unwrap(),
+            LoginState::Compression(256)
+        );
+    }
+
+    #[test]
+    fn test_classify_disconnect_reason() {
+        let data = packet(0x00, |buf| {
+            write_string(
+                buf,
+                r#"{"text":"You are not ","extra":[{"text":"whitelisted"}]}"#,
+            )
+            .unwrap()
+        });
+        assert_eq!(
+            classify_login_reply(&data, &Limits::default()).unwrap(),
+            LoginState::Disconnect("You are not whitelisted".to_string())
+        );
+
+        let data = packet(0x00, |buf| write_string(buf, "§cOutdated client!").unwrap());
+        assert_eq!(
+            classify_login_reply(&data, &Limits::default())
+                .unwrap()
+                .disconnect_reason(),
+            Some("Outdated client!")
+        );
+    }
+
+    #[test]
+    fn test_classify_translated_disconnect() {
+        let data = packet(0x00, |buf| {
+            write_string(
+                buf,
+                r#"{"translate":"multiplayer.disconnect.not_whitelisted"}"#,
+            )
+            .unwrap()
+        });
+        assert_eq!(
+            classify_login_reply(&data, &Limits::default())
+                .unwrap()
+                .disconnect_reason(),
+            Some("You are not white-listed on this server!")
+        );
+    }
+
+    #[test]
+    fn test_classify_truncated_disconnect() {
+        let mut data = packet(0x00, |buf| write_string(buf, "banned").unwrap());
+        data.truncate(4);
+        assert!(classify_login_reply(&data, &Limits::default()).is_err());
+    }
+}
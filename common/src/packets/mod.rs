@@ -0,0 +1,70 @@
+use crate::packets::{
+    error::PacketError, frame::Limits, varint::read_var_int, varint::write_var_int,
+};
+
+pub mod bytes;
+pub mod cipher;
+pub mod clientbound;
+pub mod codec;
+pub mod error;
+pub mod frame;
+pub mod i64;
+pub mod login;
+pub mod serverbound;
+pub mod string;
+pub mod u16;
+pub mod varint;
+
+/// Protocol version we speak in status handshakes (1.18 / 1.18.1), old enough
+/// that every modern server and proxy still answers. The login probe uses the
+/// version the server reports instead.
+pub const PROTOCOL_VERSION: i32 = 757;
+
+/// A packet with a fixed id in its connection state.
+///
+/// Every packet can be written and read, so the same types serve the probing
+/// side (scanner, verifier) and the serving side (honeypot).
+pub trait Packet: Sized {
+    const ID: i32;
+
+    /// Write the fields after the packet id.
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError>;
+
+    /// Read the fields after the packet id.
+    fn read_body(data: &[u8], index: &mut usize, limits: &Limits) -> Result<Self, PacketError>;
+
+    /// Encode into a length prefixed frame, ready to be written to the socket.
+    fn encode(&self) -> Result<Vec<u8>, PacketError> {
+        let mut inner = Vec::new();
+        write_var_int(&mut inner, &Self::ID);
+        self.write_body(&mut inner)?;
+        let mut outer = Vec::with_capacity(inner.len() + 5);
+        write_var_int(&mut outer, &(inner.len() as i32));
+        outer.extend_from_slice(&inner);
+        Ok(outer)
+    }
+
+    /// Decode a frame (packet id and data, without the length prefix).
+    fn decode(frame: &[u8], limits: &Limits) -> Result<Self, PacketError> {
+        let mut index = 0;
+        let id = read_var_int(frame, Some(&mut index))?;
+        if id != Self::ID {
+            return Err(PacketError::UnexpectedPacketId {
+                expected: Self::ID,
+                got: id,
+            });
+        }
+        Self::read_body(frame, &mut index, limits)
+    }
+}
+
+#[cfg(test)]
+mod test_util {
+    /// Small deterministic PRNG so the fuzz tests need no extra dependency.
+    pub fn xorshift(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+}
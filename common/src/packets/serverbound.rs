@@ -0,0 +1,283 @@
+//! Packets sent by the client (scanner, verifier) and read by the honeypot.
+
+use super::{
+    PROTOCOL_VERSION, Packet,
+    bytes::{read_bool, read_byte_array, read_uuid, write_bool, write_uuid},
+    error::PacketError,
+    frame::Limits,
+    i64::{read_i64, write_i64},
+    string::{read_string_max, write_string},
+    u16::{read_u16, write_u16},
+    varint::{read_var_int, write_var_int},
+};
+use crate::utils::name_to_uuid;
+
+pub const NEXT_STATE_STATUS: i32 = 1;
+pub const NEXT_STATE_LOGIN: i32 = 2;
+
+/// Handshake hostnames are at most 255 characters.
+const MAX_SERVER_ADDRESS_LEN: usize = 255;
+const MAX_USERNAME_LEN: usize = 16;
+
+/// Protocol versions that changed the Login Start layout.
+/// 1.19: a "has signature data" flag after the name.
+const LOGIN_SIGNATURE_VERSION: i32 = 759;
+/// 1.19.1: an optional UUID after the signature data.
+const LOGIN_UUID_VERSION: i32 = 760;
+/// 1.19.3: the signature data is gone again.
+const LOGIN_UNSIGNED_VERSION: i32 = 761;
+/// 1.20.2: the UUID is always sent.
+const LOGIN_REQUIRED_UUID_VERSION: i32 = 764;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Handshake {
+    pub protocol_version: i32,
+    pub server_address: String,
+    pub server_port: u16,
+    pub next_state: i32,
+}
+
+impl Packet for Handshake {
+    const ID: i32 = 0x00;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        write_var_int(buf, &self.protocol_version);
+        write_string(buf, &self.server_address)?;
+        write_u16(buf, self.server_port);
+        write_var_int(buf, &self.next_state);
+        Ok(())
+    }
+
+    fn read_body(data: &[u8], index: &mut usize, limits: &Limits) -> Result<Self, PacketError> {
+        Ok(Handshake {
+            protocol_version: read_var_int(data, Some(index))?,
+            server_address: read_string_max(
+                data,
+                index,
+                limits.max_string_len.min(MAX_SERVER_ADDRESS_LEN),
+            )?,
+            server_port: read_u16(data, Some(index))?,
+            next_state: read_var_int(data, Some(index))?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StatusRequest;
+
+impl Packet for StatusRequest {
+    const ID: i32 = 0x00;
+
+    fn write_body(&self, _buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        Ok(())
+    }
+
+    fn read_body(_data: &[u8], _index: &mut usize, _limits: &Limits) -> Result<Self, PacketError> {
+        Ok(StatusRequest)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PingRequest {
+    pub payload: i64,
+}
+
+impl Packet for PingRequest {
+    const ID: i32 = 0x01;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        write_i64(buf, self.payload);
+        Ok(())
+    }
+
+    fn read_body(data: &[u8], index: &mut usize, _limits: &Limits) -> Result<Self, PacketError> {
+        Ok(PingRequest {
+            payload: read_i64(data, index)?,
+        })
+    }
+}
+
+/// Login Start. `protocol_version` is not sent, it picks the layout: the name
+/// alone before 1.19, then signature data (never sent by us), an optional
+/// UUID from 1.19.1 and a required one from 1.20.2.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoginStart {
+    pub protocol_version: i32,
+    pub name: String,
+    /// Left out before 1.19.1, the nil UUID from 1.20.2 when `None`.
+    pub uuid: Option<u128>,
+}
+
+impl LoginStart {
+    /// Login Start as an offline-mode client of `protocol_version` sends it,
+    /// with the UUID derived from the name.
+    pub fn offline(protocol_version: i32, name: &str) -> LoginStart {
+        let uuid = u128::from_str_radix(&name_to_uuid(name).replace('-', ""), 16).ok();
+        LoginStart {
+            protocol_version,
+            name: name.to_string(),
+            uuid,
+        }
+    }
+
+    /// Read the fields after the packet id in the layout of
+    /// `protocol_version`, as announced in the handshake.
+    pub fn read_versioned(
+        data: &[u8],
+        index: &mut usize,
+        limits: &Limits,
+        protocol_version: i32,
+    ) -> Result<Self, PacketError> {
+        let name = read_string_max(data, index, limits.max_string_len.min(MAX_USERNAME_LEN * 4))?;
+        if (LOGIN_SIGNATURE_VERSION..LOGIN_UNSIGNED_VERSION).contains(&protocol_version)
+            && read_bool(data, index)?
+        {
+            // Expiry, public key and its signature by Mojang.
+            read_i64(data, index)?;
+            read_byte_array(data, index, limits.max_string_len)?;
+            read_byte_array(data, index, limits.max_string_len)?;
+        }
+        let has_uuid = match protocol_version {
+            LOGIN_REQUIRED_UUID_VERSION.. => true,
+            LOGIN_UUID_VERSION.. => read_bool(data, index)?,
+            _ => false,
+        };
+        let uuid = if has_uuid {
+            Some(read_uuid(data, index)?)
+        } else {
+            None
+        };
+        Ok(LoginStart {
+            protocol_version,
+            name,
+            uuid,
+        })
+    }
+}
+
+impl Packet for LoginStart {
+    const ID: i32 = 0x00;
+
+    fn write_body(&self, buf: &mut Vec<u8>) -> Result<(), PacketError> {
+        write_string(buf, &self.name)?;
+        let version = self.protocol_version;
+        if (LOGIN_SIGNATURE_VERSION..LOGIN_UNSIGNED_VERSION).contains(&version) {
+            write_bool(buf, false);
+        }
+        if version >= LOGIN_REQUIRED_UUID_VERSION {
+            write_uuid(buf, self.uuid.unwrap_or(0));
+        } else if version >= LOGIN_UUID_VERSION {
+            write_bool(buf, self.uuid.is_some());
+            if let Some(uuid) = self.uuid {
+                write_uuid(buf, uuid);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the layout of [`PROTOCOL_VERSION`], use
+    /// [`LoginStart::read_versioned`] when the handshake is known.
+    fn read_body(data: &[u8], index: &mut usize, limits: &Limits) -> Result<Self, PacketError> {
+        Self::read_versioned(data, index, limits, PROTOCOL_VERSION)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip<P: Packet + PartialEq + std::fmt::Debug>(packet: P) {
+        let encoded = packet.encode().unwrap();
+        let mut index = 0;
+        let len = read_var_int(&encoded, Some(&mut index)).unwrap();
+        assert_eq!(len as usize, encoded.len() - index);
+        let decoded = P::decode(&encoded[index..], &Limits::default()).unwrap();
+        assert_eq!(decoded, packet);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        roundtrip(Handshake {
+            protocol_version: 757,
+            server_address: "127.0.0.1".to_string(),
+            server_port: 25565,
+            next_state: NEXT_STATE_STATUS,
+        });
+        roundtrip(StatusRequest);
+        roundtrip(PingRequest { payload: -42 });
+        roundtrip(LoginStart {
+            protocol_version: PROTOCOL_VERSION,
+            name: "kybe".to_string(),
+            uuid: None,
+        });
+    }
+
+    #[test]
+    fn test_login_start_layouts() {
+        let uuid = 0x0123_4567_89ab_cdef_0123_4567_89ab_cdef;
+        let name = [4, b'k', b'y', b'b', b'e'];
+        // (protocol, uuid, body after the name)
+        let cases: [(i32, Option<u128>, &[u8]); 7] = [
+            (757, Some(uuid), &[]),
+            (759, Some(uuid), &[0]),
+            (760, None, &[0, 0]),
+            (760, Some(uuid), &[0, 1]),
+            (761, None, &[0]),
+            (763, Some(uuid), &[1]),
+            (767, Some(uuid), &[]),
+        ];
+        for (protocol, id, tail) in cases {
+            let packet = LoginStart {
+                protocol_version: protocol,
+                name: "kybe".to_string(),
+                uuid: id,
+            };
+            let mut body = Vec::new();
+            packet.write_body(&mut body).unwrap();
+            let mut expected = name.to_vec();
+            expected.extend_from_slice(tail);
+            if protocol >= 760 && id.is_some() {
+                expected.extend_from_slice(&uuid.to_be_bytes());
+            }
+            assert_eq!(body, expected, "{}", protocol);
+
+            let decoded =
+                LoginStart::read_versioned(&body, &mut 0, &Limits::default(), protocol).unwrap();
+            // Versions without a UUID field drop it.
+            let sent = if protocol >= 760 { id } else { None };
+            assert_eq!(decoded.uuid, sent, "{}", protocol);
+        }
+    }
+
+    #[test]
+    fn test_offline_login_start() {
+        let packet = LoginStart::offline(767, "Steve");
+        let uuid = format!("{:032x}", packet.uuid.unwrap());
+        assert_eq!(uuid, name_to_uuid("Steve").replace('-', ""));
+    }
+
+    #[test]
+    fn test_handshake_layout() {
+        let encoded = Handshake {
+            protocol_version: 757,
+            server_address: "a".to_string(),
+            server_port: 25565,
+            next_state: NEXT_STATE_LOGIN,
+        }
+        .encode()
+        .unwrap();
+        assert_eq!(encoded, vec![8, 0x00, 0xF5, 0x05, 1, b'a', 0x63, 0xDD, 2]);
+    }
+
+    #[test]
+    fn test_wrong_packet_id() {
+        let encoded = PingRequest { payload: 1 }.encode().unwrap();
+        assert!(matches!(
+            Handshake::decode(&encoded[1..], &Limits::default()),
+            Err(PacketError::UnexpectedPacketId {
+                expected: 0x00,
+                got: 0x01
+            })
+        ));
+    }
+}
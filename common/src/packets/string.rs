@@ -0,0 +1,131 @@
+use super::{
+    error::PacketError,
+    varint::{read_var_int, write_var_int},
+};
+
+/// Maximum string length the protocol allows, in UTF-16 code units.
+pub const MAX_PROTOCOL_STRING_LEN: usize = 32767;
+
+/// Read a string, bounded only by the buffer it is read from.
+#[allow(unused)]
+pub fn read_string(data: &[u8], index: &mut usize) -> Result<String, PacketError> {
+    read_string_max(data, index, usize::MAX)
+}
+
+/// Read a string whose UTF-8 byte length must not exceed `max_len`.
+pub fn read_string_max(
+    data: &[u8],
+    index: &mut usize,
+    max_len: usize,
+) -> Result<String, PacketError> {
+    let mut offset = *index;
+    let length = read_var_int(data, Some(&mut offset))?;
+    let length = usize::try_from(length).map_err(|_| PacketError::NegativeLength(length.into()))?;
+    if length > max_len {
+        return Err(PacketError::StringTooLong {
+            len: length,
+            max: max_len,
+        });
+    }
+
+    let end_pos = offset
+        .checked_add(length)
+        .ok_or(PacketError::UnexpectedEof)?;
+    let str_bytes = data
+        .get(offset..end_pos)
+        .ok_or(PacketError::UnexpectedEof)?;
+
+    let string = std::str::from_utf8(str_bytes)
+        .map(ToString::to_string)
+        .map_err(|_| PacketError::InvalidUtf8)?;
+    *index = end_pos;
+    Ok(string)
+}
+
+pub fn write_string(buffer: &mut Vec<u8>, string: &str) -> Result<(), PacketError> {
+    let utf16_len = string.chars().map(|c| c.len_utf16()).sum::<usize>();
+
+    if utf16_len > MAX_PROTOCOL_STRING_LEN {
+        return Err(PacketError::StringTooLong {
+            len: utf16_len,
+            max: MAX_PROTOCOL_STRING_LEN,
+        });
+    }
+
+    write_var_int(buffer, &(string.len() as i32));
+    buffer.extend_from_slice(string.as_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::test_util::xorshift;
+
+    #[test]
+    fn test_string_roundtrip() {
+        for s in ["", "localhost", "§aÜnïcödé ☃ 🎮"] {
+            let mut buffer = Vec::new();
+            write_string(&mut buffer, s).unwrap();
+            let mut index = 0;
+            assert_eq!(read_string(&buffer, &mut index).unwrap(), s);
+            assert_eq!(index, buffer.len());
+        }
+    }
+
+    #[test]
+    fn test_write_too_long() {
+        let mut buffer = Vec::new();
+        let long = "a".repeat(MAX_PROTOCOL_STRING_LEN + 1);
+        assert!(matches!(
+            write_string(&mut buffer, &long),
+            Err(PacketError::StringTooLong { .. })
+        ));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_read_limits() {
+        let mut buffer = Vec::new();
+        write_string(&mut buffer, "hello").unwrap();
+        let mut index = 0;
+        assert!(matches!(
+            read_string_max(&buffer, &mut index, 4),
+            Err(PacketError::StringTooLong { len: 5, max: 4 })
+        ));
+        assert_eq!(index, 0);
+
+        let mut index = 0;
+        assert!(matches!(
+            read_string(&buffer[..4], &mut index),
+            Err(PacketError::UnexpectedEof)
+        ));
+
+        let mut negative = Vec::new();
+        write_var_int(&mut negative, &-5);
+        assert!(matches!(
+            read_string(&negative, &mut 0),
+            Err(PacketError::NegativeLength(-5))
+        ));
+
+        let mut huge = Vec::new();
+        write_var_int(&mut huge, &i32::MAX);
+        assert!(matches!(
+            read_string(&huge, &mut 0),
+            Err(PacketError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_string_fuzz_never_panics() {
+        let mut state = 0xD1B5_4A32_D192_ED03;
+        for _ in 0..10_000 {
+            let len = (xorshift(&mut state) % 16) as usize;
+            let data: Vec<u8> = (0..len).map(|_| xorshift(&mut state) as u8).collect();
+            let mut index = 0;
+            if read_string(&data, &mut index).is_ok() {
+                assert!(index <= data.len());
+            }
+        }
+    }
+}
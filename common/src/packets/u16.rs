@@ -0,0 +1,23 @@
+use super::error::PacketError;
+
+#[allow(unused)]
+pub fn read_u16(data: &[u8], index: Option<&mut usize>) -> Result<u16, PacketError> {
+    let current = index.as_ref().map(|v| **v).unwrap_or(0);
+    const SIZE: usize = 2;
+
+    let bytes = data
+        .get(current..current.saturating_add(SIZE))
+        .ok_or(PacketError::UnexpectedEof)?;
+    let value = u16::from_be_bytes([bytes[0], bytes[1]]);
+
+    if let Some(idx) = index {
+        *idx = current + SIZE;
+    }
+
+    Ok(value)
+}
+
+#[allow(unused)]
+pub fn write_u16(buffer: &mut Vec<u8>, number: u16) {
+    buffer.extend_from_slice(&number.to_be_bytes());
+}
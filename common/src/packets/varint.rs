@@ -0,0 +1,231 @@
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use super::error::PacketError;
+
+const SEGMENT_BITS: u8 = 0x7F;
+const CONTINUE_BIT: u8 = 0x80;
+
+#[allow(unused)]
+pub fn read_var_int_long(var_int: &[u8], offset: Option<&mut usize>) -> Result<i64, PacketError> {
+    read_var_int_generic(var_int, offset, 64)
+}
+
+pub fn read_var_int(var_int: &[u8], offset: Option<&mut usize>) -> Result<i32, PacketError> {
+    read_var_int_generic(var_int, offset, 32).map(|v| v as i32)
+}
+
+/// Decode a var int, leaving `offset` untouched on error.
+fn read_var_int_generic(
+    var_int: &[u8],
+    offset: Option<&mut usize>,
+    max_bits: u32,
+) -> Result<i64, PacketError> {
+    let mut value = 0i64;
+    let mut position = 0u32;
+    let mut current_offset = offset.as_ref().map_or(0, |ptr| **ptr);
+
+    loop {
+        let byte = *var_int
+            .get(current_offset)
+            .ok_or(PacketError::UnexpectedEof)?;
+        current_offset += 1;
+        value |= i64::from(byte & SEGMENT_BITS) << position;
+
+        if byte & CONTINUE_BIT == 0 {
+            break;
+        }
+
+        position += 7;
+        if position >= max_bits {
+            return Err(PacketError::VarIntTooBig);
+        }
+    }
+
+    if let Some(ptr) = offset {
+        *ptr = current_offset;
+    }
+
+    Ok(value)
+}
+
+#[allow(unused)]
+pub fn write_var_long(result: &mut Vec<u8>, value: i64) {
+    write_var_int_generic(result, value as u64);
+}
+
+pub fn write_var_int(result: &mut Vec<u8>, value: &i32) {
+    write_var_int_generic(result, *value as u32 as u64);
+}
+
+fn write_var_int_generic(result: &mut Vec<u8>, mut value: u64) {
+    loop {
+        if value <= SEGMENT_BITS as u64 {
+            result.push(value as u8);
+            return;
+        }
+        result.push(((value as u8) & SEGMENT_BITS) | CONTINUE_BIT);
+        value >>= 7;
+    }
+}
+
+/// Read a var int from a stream, giving up after the 5 bytes an `i32` can take.
+pub async fn read_var_int_from_stream<R: AsyncRead + Unpin>(
+    stream: &mut R,
+) -> Result<i32, PacketError> {
+    let mut value = 0u32;
+
+    for num_read in 0..5 {
+        let byte = stream.read_u8().await?;
+        value |= u32::from(byte & SEGMENT_BITS) << (7 * num_read);
+
+        if byte & CONTINUE_BIT == 0 {
+            return Ok(value as i32);
+        }
+    }
+
+    Err(PacketError::VarIntTooBig)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packets::test_util::xorshift;
+
+    fn encode_varint(value: i32) -> Vec<u8> {
+        let mut vec = Vec::new();
+        write_var_int(&mut vec, &value);
+        vec
+    }
+
+    fn encode_varlong(value: i64) -> Vec<u8> {
+        let mut vec = Vec::new();
+        write_var_long(&mut vec, value);
+        vec
+    }
+
+    fn decode_varint(bytes: &[u8]) -> i32 {
+        read_var_int(bytes, None).unwrap()
+    }
+
+    fn decode_varlong(bytes: &[u8]) -> i64 {
+        read_var_int_long(bytes, None).unwrap()
+    }
+
+    #[test]
+    fn test_varint_roundtrip() {
+        let values = [
+            0,
+            1,
+            2,
+            127,
+            128,
+            255,
+            25565,
+            2097151,
+            2147483647,
+            -1,
+            -2147483648,
+        ];
+
+        for &val in &values {
+            let encoded = encode_varint(val);
+            let decoded = decode_varint(&encoded);
+            assert_eq!(val, decoded, "Failed on value {val}");
+        }
+    }
+
+    #[test]
+    fn test_varlong_roundtrip() {
+        let values = [
+            0,
+            1,
+            2,
+            127,
+            128,
+            255,
+            2147483647,
+            9223372036854775807,
+            -1,
+            -2147483648,
+            -9223372036854775808,
+        ];
+
+        for &val in &values {
+            let encoded = encode_varlong(val);
+            let decoded = decode_varlong(&encoded);
+            assert_eq!(val, decoded, "Failed on value {val}");
+        }
+    }
+
+    #[test]
+    fn test_varint_random_roundtrip() {
+        let mut state = 0x2545_F491_4F6C_DD1D;
+        for _ in 0..10_000 {
+            let val = xorshift(&mut state) as i32;
+            let encoded = encode_varint(val);
+            let mut offset = 0;
+            assert_eq!(read_var_int(&encoded, Some(&mut offset)).unwrap(), val);
+            assert_eq!(offset, encoded.len());
+        }
+    }
+
+    #[test]
+    fn test_varint_too_big() {
+        let data = [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
+        let mut offset = 0;
+        assert!(matches!(
+            read_var_int(&data, Some(&mut offset)),
+            Err(PacketError::VarIntTooBig)
+        ));
+        assert_eq!(offset, 0);
+        assert!(matches!(
+            read_var_int_long(&[0xFF; 11], None),
+            Err(PacketError::VarIntTooBig)
+        ));
+    }
+
+    #[test]
+    fn test_varint_truncated() {
+        assert!(matches!(
+            read_var_int(&[], None),
+            Err(PacketError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            read_var_int(&[0x80, 0x80], None),
+            Err(PacketError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_varint_fuzz_never_panics() {
+        let mut state = 0x9E37_79B9_7F4A_7C15;
+        for _ in 0..10_000 {
+            let len = (xorshift(&mut state) % 12) as usize;
+            let data: Vec<u8> = (0..len).map(|_| xorshift(&mut state) as u8).collect();
+            let mut offset = 0;
+            if read_var_int(&data, Some(&mut offset)).is_ok() {
+                assert!(offset <= 5 && offset <= data.len());
+            }
+            let _ = read_var_int_long(&data, None);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stream_varint_is_bounded() {
+        let mut data: &[u8] = &[0xFF; 64];
+        assert!(matches!(
+            read_var_int_from_stream(&mut data).await,
+            Err(PacketError::VarIntTooBig)
+        ));
+        assert_eq!(data.len(), 59);
+
+        let mut data: &[u8] = &[0xDD, 0xC7, 0x01];
+        assert_eq!(read_var_int_from_stream(&mut data).await.unwrap(), 25565);
+
+        let mut data: &[u8] = &[0x80];
+        assert!(matches!(
+            read_var_int_from_stream(&mut data).await,
+            Err(PacketError::UnexpectedEof)
+        ));
+    }
+}
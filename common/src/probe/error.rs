@@ -0,0 +1,97 @@
+use std::fmt;
+
+use crate::packets::error::PacketError;
+
+/// Why a status probe did not produce a usable response.
+#[derive(Debug)]
+pub enum StatusError {
+    /// Sending the handshake or status request failed.
+    Send(PacketError),
+    /// Nothing arrived before the read deadline.
+    Timeout,
+    /// The connection failed or was closed before a response started.
+    Read(PacketError),
+    /// The response announced more bytes than the configured maximum.
+    TooLarge { len: usize, max: usize },
+    /// The first packet was not a status response.
+    WrongPacketId(i32),
+    /// The frame or the JSON string in it ended before its declared length,
+    /// `body` is what did arrive.
+    Truncated {
+        declared: usize,
+        received: usize,
+        body: String,
+    },
+    /// A complete response whose body is not JSON.
+    NotJson { body: String },
+    /// The peer answered with a text banner (SSH, HTTP, SMTP, ...) instead of a packet.
+    NotMinecraft { banner: String },
+    /// Anything else no real server sends (bad var ints, invalid UTF-8).
+    Malformed(PacketError),
+}
+
+impl StatusError {
+    /// Short stable name, for logs and metrics.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            StatusError::Send(_) => "send_failed",
+            StatusError::Timeout => "timeout",
+            StatusError::Read(_) => "read_failed",
+            StatusError::TooLarge { .. } => "too_large",
+            StatusError::WrongPacketId(_) => "wrong_packet_id",
+            StatusError::Truncated { .. } => "truncated",
+            StatusError::NotJson { .. } => "not_json",
+            StatusError::NotMinecraft { .. } => "not_minecraft",
+            StatusError::Malformed(_) => "malformed",
+        }
+    }
+
+    /// The (partial) body the server sent, if it got that far.
+    pub fn body(&self) -> Option<&str> {
+        match self {
+            StatusError::Truncated { body, .. }
+            | StatusError::NotJson { body }
+            | StatusError::NotMinecraft { banner: body } => Some(body),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for StatusError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StatusError::Send(e) => write!(f, "send failed: {}", e),
+            StatusError::Timeout => write!(f, "read deadline exceeded"),
+            StatusError::Read(e) => write!(f, "read failed: {}", e),
+            StatusError::TooLarge { len, max } => {
+                write!(f, "response of {} bytes exceeds maximum of {}", len, max)
+            }
+            StatusError::WrongPacketId(id) => {
+                write!(f, "expected packet id 0x00, got {:#04x}", id)
+            }
+            StatusError::Truncated {
+                declared, received, ..
+            } => write!(f, "truncated response: {} of {} bytes", received, declared),
+            StatusError::NotJson { body } => {
+                write!(f, "response is not JSON ({} bytes)", body.len())
+            }
+            StatusError::Malformed(e) => write!(f, "malformed response: {}", e),
+            StatusError::NotMinecraft { banner } => {
+                write!(
+                    f,
+                    "not a minecraft server: {:?}",
+                    banner.lines().next().unwrap_or_default()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for StatusError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StatusError::Send(e) | StatusError::Read(e) | StatusError::Malformed(e) => Some(e),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,514 @@
+//! Status and login probes, generic over the transport so they run the same
+//! against a TCP socket and an in-memory peer.
+
+use std::time::Duration;
+
+use serde::de::IgnoredAny;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    time::{Instant, timeout_at},
+};
+
+use crate::packets::{
+    PROTOCOL_VERSION, Packet,
+    clientbound::StatusResponse,
+    error::PacketError,
+    frame::{Limits, read_frame_until},
+    login::{LoginState, classify_login_reply},
+    serverbound::{Handshake, LoginStart, NEXT_STATE_LOGIN, NEXT_STATE_STATUS, StatusRequest},
+    varint::{read_var_int, read_var_int_from_stream},
+};
+
+pub use error::StatusError;
+
+pub mod error;
+pub mod outcome;
+pub mod retry;
+#[cfg(test)]
+mod scripted;
+pub mod transcript;
+
+async fn write_until<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    data: &[u8],
+    deadline: Instant,
+) -> Result<(), PacketError> {
+    timeout_at(deadline, stream.write_all(data))
+        .await
+        .map_err(|_| PacketError::Timeout)?
+        .map_err(PacketError::from)
+}
+
+/// A status response that arrived in full and is valid JSON.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusReply {
+    pub json: String,
+    /// Time from sending the request to having the whole response.
+    pub latency: Duration,
+}
+
+impl StatusReply {
+    /// The protocol version to log in with: the one the server reports, or
+    /// [`PROTOCOL_VERSION`] when it reports none. Some proxies send -1.
+    pub fn login_protocol(&self) -> i32 {
+        serde_json::from_str::<serde_json::Value>(&self.json.replace('\u{0000}', ""))
+            .ok()
+            .and_then(|json| json["version"]["protocol"].as_i64())
+            .and_then(|protocol| i32::try_from(protocol).ok())
+            .filter(|&protocol| protocol > 0)
+            .unwrap_or(PROTOCOL_VERSION)
+    }
+}
+
+/// Send a handshake and a status request and return the status JSON.
+///
+/// The response may arrive in any number of segments, `timeout_duration` bounds
+/// the whole exchange. Bodies that arrive but are cut short or are not JSON
+/// come back as [`StatusError::Truncated`] / [`StatusError::NotJson`].
+pub async fn try_handshake_and_status<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    limits: &Limits,
+    timeout_duration: Duration,
+) -> Result<StatusReply, StatusError> {
+    let deadline = Instant::now() + timeout_duration;
+    let mut request = Handshake {
+        protocol_version: PROTOCOL_VERSION,
+        server_address: host.to_string(),
+        server_port: port,
+        next_state: NEXT_STATE_STATUS,
+    }
+    .encode()
+    .map_err(StatusError::Send)?;
+    request.extend(StatusRequest.encode().map_err(StatusError::Send)?);
+    write_until(stream, &request, deadline)
+        .await
+        .map_err(StatusError::Send)?;
+    let sent = Instant::now();
+    let frame = read_status_frame(stream, limits, deadline).await?;
+    let latency = sent.elapsed();
+    let json = decode_status_frame(&frame, limits)
+        .map_err(|e| not_minecraft(frame.len(), &frame).unwrap_or(e))?;
+    // save_json drops NUL characters before parsing, so tolerate them here too.
+    if serde_json::from_str::<IgnoredAny>(&json.replace('\u{0000}', "")).is_err() {
+        return Err(StatusError::NotJson { body: json });
+    }
+    Ok(StatusReply { json, latency })
+}
+
+/// Read one frame, keeping whatever arrived if the peer stops halfway.
+async fn read_status_frame<R: AsyncRead + Unpin>(
+    stream: &mut R,
+    limits: &Limits,
+    deadline: Instant,
+) -> Result<Vec<u8>, StatusError> {
+    let len = match timeout_at(deadline, read_var_int_from_stream(stream)).await {
+        Err(_) => return Err(StatusError::Timeout),
+        Ok(Err(e @ PacketError::VarIntTooBig)) => return Err(StatusError::Malformed(e)),
+        Ok(Err(e)) => return Err(StatusError::Read(e)),
+        Ok(Ok(len)) => len,
+    };
+    let len = usize::try_from(len)
+        .map_err(|_| StatusError::Malformed(PacketError::NegativeLength(len.into())))?;
+    if len == 0 {
+        return Err(StatusError::Malformed(PacketError::UnexpectedEof));
+    }
+    if len > limits.max_frame_size {
+        return Err(StatusError::TooLarge {
+            len,
+            max: limits.max_frame_size,
+        });
+    }
+
+    let mut frame = Vec::with_capacity(len.min(64 * 1024));
+    let mut chunk = [0u8; 4096];
+    while frame.len() < len {
+        let want = (len - frame.len()).min(chunk.len());
+        let n = match timeout_at(deadline, stream.read(&mut chunk[..want])).await {
+            Ok(Ok(n)) => n,
+            Err(_) if frame.is_empty() => return Err(StatusError::Timeout),
+            Ok(Err(e)) if frame.is_empty() => return Err(StatusError::Read(e.into())),
+            Err(_) | Ok(Err(_)) => 0,
+        };
+        if n == 0 {
+            return Err(not_minecraft(len, &frame).unwrap_or_else(|| truncated_frame(&frame, len)));
+        }
+        frame.extend_from_slice(&chunk[..n]);
+    }
+    Ok(frame)
+}
+
+/// Text protocols greet with printable ASCII, which reads as a one byte length
+/// followed by a frame of more text. A status response always starts its frame
+/// with packet id 0x00, so it never matches.
+fn not_minecraft(len: usize, frame: &[u8]) -> Option<StatusError> {
+    let head = &frame[..frame.len().min(16)];
+    let printable = |b: &u8| b.is_ascii_graphic() || matches!(b, b' ' | b'\r' | b'\n' | b'\t');
+    if head.len() < 3 || !head.iter().all(printable) {
+        return None;
+    }
+    // The length byte was printable too, put it back in front of the banner.
+    let mut banner = vec![len as u8];
+    banner.extend_from_slice(frame);
+    Some(StatusError::NotMinecraft {
+        banner: String::from_utf8_lossy(&banner).into_owned(),
+    })
+}
+
+/// [`StatusError::Truncated`] for a frame that stopped after `frame.len()` of `declared` bytes.
+fn truncated_frame(frame: &[u8], declared: usize) -> StatusError {
+    let mut index = 0;
+    if read_var_int(frame, Some(&mut index)).is_ok() {
+        let _ = read_var_int(frame, Some(&mut index));
+    }
+    StatusError::Truncated {
+        declared,
+        received: frame.len(),
+        body: String::from_utf8_lossy(&frame[index..]).into_owned(),
+    }
+}
+
+fn decode_status_frame(frame: &[u8], limits: &Limits) -> Result<String, StatusError> {
+    let mut index = 0;
+    let id = read_var_int(frame, Some(&mut index)).map_err(StatusError::Malformed)?;
+    if id != StatusResponse::ID {
+        return Err(StatusError::WrongPacketId(id));
+    }
+    match StatusResponse::read_body(frame, &mut index, limits) {
+        Ok(response) => Ok(response.json),
+        Err(PacketError::StringTooLong { len, max }) => Err(StatusError::TooLarge { len, max }),
+        // The string claims more bytes than the frame holds.
+        Err(PacketError::UnexpectedEof) => {
+            let declared = read_var_int(frame, Some(&mut index)).map_err(StatusError::Malformed)?;
+            let body = &frame[index..];
+            Err(StatusError::Truncated {
+                declared: declared.max(0) as usize,
+                received: body.len(),
+                body: String::from_utf8_lossy(body).into_owned(),
+            })
+        }
+        Err(e) => Err(StatusError::Malformed(e)),
+    }
+}
+
+/// Send a handshake in login state and Login Start, then classify the first reply.
+/// `protocol_version` should be the server's own (see
+/// [`StatusReply::login_protocol`]), older versions are often refused before
+/// the server says whether it is in online mode. The caller drops the
+/// connection right after, so the server never sees a join.
+pub async fn try_login<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    protocol_version: i32,
+    username: &str,
+    limits: &Limits,
+    timeout_duration: Duration,
+) -> Result<LoginState, String> {
+    let deadline = Instant::now() + timeout_duration;
+    let handshake = Handshake {
+        protocol_version,
+        server_address: host.to_string(),
+        server_port: port,
+        next_state: NEXT_STATE_LOGIN,
+    }
+    .encode()
+    .map_err(|e| format!("handshake failed: {}", e))?;
+    write_until(stream, &handshake, deadline)
+        .await
+        .map_err(|e| format!("handshake failed: {}", e))?;
+    let login_start = LoginStart::offline(protocol_version, username)
+        .encode()
+        .map_err(|e| format!("login start failed: {}", e))?;
+    write_until(stream, &login_start, deadline)
+        .await
+        .map_err(|e| format!("login start failed: {}", e))?;
+    let buffer = read_frame_until(stream, limits, deadline)
+        .await
+        .map_err(|e| format!("read failed: {}", e))?;
+    classify_login_reply(&buffer, limits).map_err(|e| format!("invalid login reply: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{scripted::ScriptedPeer, *};
+    use crate::packets::{
+        clientbound::{EncryptionRequest, LoginDisconnect, PongResponse},
+        varint::write_var_int,
+    };
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    const JSON: &str = r#"{"description":"hi","players":{"max":20,"online":1}}"#;
+
+    fn status_response() -> Vec<u8> {
+        StatusResponse {
+            json: JSON.to_string(),
+        }
+        .encode()
+        .unwrap()
+    }
+
+    async fn status(peer: &mut ScriptedPeer) -> Result<String, StatusError> {
+        try_handshake_and_status(peer, "127.0.0.1", 25565, &Limits::default(), TIMEOUT)
+            .await
+            .map(|reply| reply.json)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_status() {
+        let mut peer = ScriptedPeer::new().await_written(1).send(status_response());
+        assert_eq!(status(&mut peer).await.unwrap(), JSON);
+
+        let mut expected = Handshake {
+            protocol_version: PROTOCOL_VERSION,
+            server_address: "127.0.0.1".to_string(),
+            server_port: 25565,
+            next_state: NEXT_STATE_STATUS,
+        }
+        .encode()
+        .unwrap();
+        expected.extend(StatusRequest.encode().unwrap());
+        assert_eq!(peer.written, expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_split_reads() {
+        let response = status_response();
+        let mut peer = ScriptedPeer::new()
+            .send(&response[..1])
+            .delay(Duration::from_millis(200))
+            .send(&response[1..10])
+            .send(&response[10..20])
+            .delay(Duration::from_millis(200))
+            .send(&response[20..]);
+        assert_eq!(status(&mut peer).await.unwrap(), JSON);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency() {
+        let response = status_response();
+        let mut peer = ScriptedPeer::new()
+            .delay(Duration::from_millis(30))
+            .send(&response[..5])
+            .delay(Duration::from_millis(20))
+            .send(&response[5..]);
+        let reply = try_handshake_and_status(&mut peer, "a", 1, &Limits::default(), TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(reply.latency, Duration::from_millis(50));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_many_segments() {
+        let json = format!(r#"{{"description":"{}"}}"#, "x".repeat(30_000));
+        let response = StatusResponse { json: json.clone() }.encode().unwrap();
+        let mut peer = ScriptedPeer::new();
+        for segment in response.chunks(1460) {
+            peer = peer.delay(Duration::from_millis(1)).send(segment);
+        }
+        assert_eq!(status(&mut peer).await.unwrap(), json);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_writer() {
+        let response = status_response();
+        let mut peer = ScriptedPeer::new().drip(&response, Duration::from_millis(50));
+        assert_eq!(status(&mut peer).await.unwrap(), JSON);
+
+        // Each byte is within any per-read timeout, the whole frame is not.
+        let mut peer = ScriptedPeer::new().drip(&response, Duration::from_millis(100));
+        let err = status(&mut peer).await.unwrap_err();
+        assert!(
+            matches!(err, StatusError::Truncated { received, .. } if received > 0),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_silent_server() {
+        let mut peer = ScriptedPeer::new();
+        let err = status(&mut peer).await.unwrap_err();
+        assert!(matches!(err, StatusError::Timeout), "{:?}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_truncated_frame() {
+        let response = status_response();
+        let mut peer = ScriptedPeer::new()
+            .send(&response[..response.len() / 2])
+            .close();
+        let err = status(&mut peer).await.unwrap_err();
+        assert_eq!(err.kind(), "truncated");
+        assert!(JSON.starts_with(err.body().unwrap()), "{:?}", err);
+
+        // Length prefix cut in the middle.
+        let mut peer = ScriptedPeer::new().send(vec![0x80]).close();
+        let err = status(&mut peer).await.unwrap_err();
+        assert!(matches!(err, StatusError::Read(_)), "{:?}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_string_longer_than_frame() {
+        // The frame is complete, but the string inside claims 200 bytes.
+        let mut frame = vec![0x00];
+        write_var_int(&mut frame, &200);
+        frame.extend_from_slice(br#"{"description":"#);
+        let mut response = Vec::new();
+        write_var_int(&mut response, &(frame.len() as i32));
+        response.extend(frame);
+
+        let mut peer = ScriptedPeer::new().send(response);
+        match status(&mut peer).await.unwrap_err() {
+            StatusError::Truncated {
+                declared,
+                received,
+                body,
+            } => {
+                assert_eq!((declared, received), (200, 15));
+                assert_eq!(body, r#"{"description":"#);
+            }
+            err => panic!("{:?}", err),
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_not_json() {
+        let response = StatusResponse {
+            json: "SSH-2.0-OpenSSH_9.6".to_string(),
+        }
+        .encode()
+        .unwrap();
+        let mut peer = ScriptedPeer::new().send(response);
+        let err = status(&mut peer).await.unwrap_err();
+        assert_eq!(err.body(), Some("SSH-2.0-OpenSSH_9.6"));
+        assert_eq!(err.kind(), "not_json");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_text_banner() {
+        // SSH greets first and then waits for us.
+        let mut peer = ScriptedPeer::new().send(&b"SSH-2.0-OpenSSH_9.6\r\n"[..]);
+        let err = status(&mut peer).await.unwrap_err();
+        assert_eq!(err.kind(), "not_minecraft");
+        assert_eq!(err.body(), Some("SSH-2.0-OpenSSH_9.6\r\n"));
+
+        // HTTP answers our handshake with an error and closes.
+        let mut peer = ScriptedPeer::new()
+            .await_written(1)
+            .send(&b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n"[..])
+            .close();
+        let err = status(&mut peer).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            r#"not a minecraft server: "HTTP/1.1 400 Bad Request""#
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wrong_packet_id() {
+        let pong = PongResponse { payload: 1 }.encode().unwrap();
+        let mut peer = ScriptedPeer::new().send(pong);
+        let err = status(&mut peer).await.unwrap_err();
+        assert!(matches!(err, StatusError::WrongPacketId(0x01)), "{:?}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_server_closes_early() {
+        let mut peer = ScriptedPeer::new().close();
+        let err = status(&mut peer).await.unwrap_err();
+        assert!(matches!(err, StatusError::Read(_)), "{:?}", err);
+
+        let mut peer = ScriptedPeer::new().await_written(1).reset();
+        let err = status(&mut peer).await.unwrap_err();
+        assert!(err.to_string().contains("reset"), "{}", err);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_oversized() {
+        let limits = Limits {
+            max_frame_size: 1024,
+            max_string_len: 32,
+        };
+        // Claims 2 MiB, but only the prefix is ever sent.
+        let mut peer = ScriptedPeer::new().send(vec![0x80, 0x80, 0x80, 0x01]);
+        let err = try_handshake_and_status(&mut peer, "a", 1, &limits, TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(
+                err,
+                StatusError::TooLarge {
+                    len: 2097152,
+                    max: 1024
+                }
+            ),
+            "{:?}",
+            err
+        );
+
+        // Fits the frame limit, but the JSON is over the string limit.
+        let mut peer = ScriptedPeer::new().send(status_response());
+        let err = try_handshake_and_status(&mut peer, "a", 1, &limits, TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, StatusError::TooLarge { max: 32, .. }),
+            "{:?}",
+            err
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_login() {
+        let encryption = EncryptionRequest {
+            server_id: String::new(),
+            public_key: vec![0; 162],
+            verify_token: vec![1, 2, 3, 4],
+        }
+        .encode()
+        .unwrap();
+        let mut peer = ScriptedPeer::new().await_written(1).send(encryption);
+        let state = try_login(&mut peer, "a", 1, 767, "kybe", &Limits::default(), TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(state, LoginState::OnlineMode);
+
+        // Handshake and Login Start in the server's own version.
+        let mut expected = Handshake {
+            protocol_version: 767,
+            server_address: "a".to_string(),
+            server_port: 1,
+            next_state: NEXT_STATE_LOGIN,
+        }
+        .encode()
+        .unwrap();
+        expected.extend(LoginStart::offline(767, "kybe").encode().unwrap());
+        assert_eq!(peer.written, expected);
+
+        let disconnect = LoginDisconnect {
+            reason: r#"{"text":"You are not whitelisted"}"#.to_string(),
+        }
+        .encode()
+        .unwrap();
+        let mut peer = ScriptedPeer::new().drip(&disconnect, Duration::from_millis(1));
+        let state = try_login(&mut peer, "a", 1, 757, "kybe", &Limits::default(), TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(state.disconnect_reason(), Some("You are not whitelisted"));
+    }
+
+    #[test]
+    fn test_login_protocol() {
+        let reply = |json: &str| StatusReply {
+            json: json.to_string(),
+            latency: Duration::ZERO,
+        };
+        let version = r#"{"version":{"name":"1.21","protocol":767}}"#;
+        assert_eq!(reply(version).login_protocol(), 767);
+        let proxy = r#"{"version":{"name":"Velocity","protocol":-1}}"#;
+        assert_eq!(reply(proxy).login_protocol(), PROTOCOL_VERSION);
+        assert_eq!(reply(JSON).login_protocol(), PROTOCOL_VERSION);
+    }
+}
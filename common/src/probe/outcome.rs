@@ -0,0 +1,209 @@
+//! Classification of every probe, successful or not, so false negatives and
+//! timeouts can be measured per /24 and per port.
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    sync::Mutex,
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::{net::TcpStream, time::timeout};
+
+use super::StatusError;
+use crate::packets::error::PacketError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeOutcome {
+    Success,
+    /// The port is closed.
+    Refused,
+    /// No answer to the SYN, usually filtered or nothing there.
+    ConnectTimeout,
+    /// Any other connect error (unreachable host or network).
+    ConnectFailed,
+    /// Connected, but nothing arrived in time.
+    ReadTimeout,
+    /// The peer reset or closed the connection before answering.
+    Reset,
+    /// Another protocol answered (SSH, HTTP, ...).
+    NotMinecraft,
+    /// Bytes arrived, but not a usable status packet.
+    Malformed,
+    /// A complete status packet whose body is not JSON.
+    InvalidJson,
+}
+
+impl ProbeOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProbeOutcome::Success => "success",
+            ProbeOutcome::Refused => "refused",
+            ProbeOutcome::ConnectTimeout => "connect_timeout",
+            ProbeOutcome::ConnectFailed => "connect_failed",
+            ProbeOutcome::ReadTimeout => "read_timeout",
+            ProbeOutcome::Reset => "reset",
+            ProbeOutcome::NotMinecraft => "not_minecraft",
+            ProbeOutcome::Malformed => "malformed",
+            ProbeOutcome::InvalidJson => "invalid_json",
+        }
+    }
+
+    /// Outcome of a failed `TcpStream::connect`.
+    pub fn from_connect_error(e: &io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::ConnectionRefused => ProbeOutcome::Refused,
+            io::ErrorKind::TimedOut => ProbeOutcome::ConnectTimeout,
+            _ => ProbeOutcome::ConnectFailed,
+        }
+    }
+
+    /// Outcome of a status probe that got past connecting.
+    pub fn from_status<T>(result: &Result<T, StatusError>) -> Self {
+        let e = match result {
+            Ok(_) => return ProbeOutcome::Success,
+            Err(e) => e,
+        };
+        match e {
+            StatusError::Timeout | StatusError::Send(PacketError::Timeout) => {
+                ProbeOutcome::ReadTimeout
+            }
+            StatusError::Send(_) | StatusError::Read(_) => ProbeOutcome::Reset,
+            StatusError::NotMinecraft { .. } => ProbeOutcome::NotMinecraft,
+            StatusError::NotJson { .. } => ProbeOutcome::InvalidJson,
+            StatusError::TooLarge { .. }
+            | StatusError::WrongPacketId(_)
+            | StatusError::Truncated { .. }
+            | StatusError::Malformed(_) => ProbeOutcome::Malformed,
+        }
+    }
+}
+
+/// `(/24 network, port, outcome)`.
+pub type OutcomeKey = (Ipv4Addr, u16, ProbeOutcome);
+
+/// Probe outcome counters, shared by all workers and drained periodically
+/// into the `scan_results` table. They count probes, not attempts: a probe
+/// that is retried is only recorded with the outcome of its last attempt.
+#[derive(Debug, Default)]
+pub struct OutcomeCounter {
+    counts: Mutex<HashMap<OutcomeKey, i64>>,
+}
+
+impl OutcomeCounter {
+    pub fn record(&self, addr: SocketAddr, outcome: ProbeOutcome) {
+        let std::net::IpAddr::V4(ip) = addr.ip() else {
+            return;
+        };
+        let subnet = Ipv4Addr::from(u32::from(ip) & 0xFFFF_FF00);
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        *counts.entry((subnet, addr.port(), outcome)).or_default() += 1;
+    }
+
+    /// Record the outcome of a finished probe.
+    pub fn record_result<T>(&self, addr: SocketAddr, result: &Result<T, ProbeOutcome>) {
+        let outcome = match result {
+            Ok(_) => ProbeOutcome::Success,
+            Err(outcome) => *outcome,
+        };
+        self.record(addr, outcome);
+    }
+
+    /// Take all counts recorded since the last call.
+    pub fn take(&self) -> HashMap<OutcomeKey, i64> {
+        std::mem::take(&mut *self.counts.lock().unwrap_or_else(|e| e.into_inner()))
+    }
+
+    /// Put counts back, e.g. after a failed write.
+    pub fn merge(&self, other: HashMap<OutcomeKey, i64>) {
+        let mut counts = self.counts.lock().unwrap_or_else(|e| e.into_inner());
+        for (key, n) in other {
+            *counts.entry(key).or_default() += n;
+        }
+    }
+}
+
+/// Connect to `addr`. Failures are not recorded, the caller knows whether
+/// the probe is retried.
+pub async fn connect(
+    addr: SocketAddr,
+    timeout_duration: Duration,
+) -> Result<TcpStream, ProbeOutcome> {
+    match timeout(timeout_duration, TcpStream::connect(addr)).await {
+        Ok(Ok(stream)) => Ok(stream),
+        Ok(Err(e)) => Err(ProbeOutcome::from_connect_error(&e)),
+        Err(_) => Err(ProbeOutcome::ConnectTimeout),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let refused = io::Error::from(io::ErrorKind::ConnectionRefused);
+        assert_eq!(
+            ProbeOutcome::from_connect_error(&refused),
+            ProbeOutcome::Refused
+        );
+        let unreachable = io::Error::from(io::ErrorKind::HostUnreachable);
+        assert_eq!(
+            ProbeOutcome::from_connect_error(&unreachable),
+            ProbeOutcome::ConnectFailed
+        );
+
+        let cases = [
+            (StatusError::Timeout, ProbeOutcome::ReadTimeout),
+            (
+                StatusError::Read(PacketError::UnexpectedEof),
+                ProbeOutcome::Reset,
+            ),
+            (
+                StatusError::NotMinecraft {
+                    banner: "SSH-2.0".to_string(),
+                },
+                ProbeOutcome::NotMinecraft,
+            ),
+            (StatusError::WrongPacketId(1), ProbeOutcome::Malformed),
+            (
+                StatusError::NotJson {
+                    body: "x".to_string(),
+                },
+                ProbeOutcome::InvalidJson,
+            ),
+        ];
+        for (err, outcome) in cases {
+            assert_eq!(ProbeOutcome::from_status::<()>(&Err(err)), outcome);
+        }
+        assert_eq!(ProbeOutcome::from_status(&Ok(())), ProbeOutcome::Success);
+    }
+
+    #[test]
+    fn test_counter() {
+        let counter = OutcomeCounter::default();
+        let a: SocketAddr = "10.0.0.1:25565".parse().unwrap();
+        let b: SocketAddr = "10.0.0.200:25565".parse().unwrap();
+        let c: SocketAddr = "10.0.1.1:25565".parse().unwrap();
+        counter.record(a, ProbeOutcome::Refused);
+        counter.record(b, ProbeOutcome::Refused);
+        counter.record(c, ProbeOutcome::Refused);
+        counter.record(a, ProbeOutcome::Success);
+
+        let counts = counter.take();
+        let subnet = Ipv4Addr::new(10, 0, 0, 0);
+        assert_eq!(counts[&(subnet, 25565, ProbeOutcome::Refused)], 2);
+        assert_eq!(counts[&(subnet, 25565, ProbeOutcome::Success)], 1);
+        assert_eq!(counts.len(), 3);
+        assert!(counter.take().is_empty());
+
+        counter.merge(counts);
+        counter.record_result::<()>(a, &Err(ProbeOutcome::Refused));
+        assert_eq!(counter.take()[&(subnet, 25565, ProbeOutcome::Refused)], 3);
+        counter.record_result(a, &Ok(()));
+        assert_eq!(counter.take()[&(subnet, 25565, ProbeOutcome::Success)], 1);
+    }
+}
@@ -0,0 +1,349 @@
+//! Retries for transient probe failures. Retries wait in a delayed queue and
+//! run on their own workers, so backoff never blocks the main worker pool.
+
+use std::{
+    future::poll_fn,
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use serde::Deserialize;
+use tokio::{
+    sync::{Semaphore, mpsc},
+    time::Instant,
+};
+use tokio_util::time::DelayQueue;
+
+use super::outcome::ProbeOutcome;
+
+/// Retry settings, shared by the scanner and verifier configs.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RetryConfig {
+    /// Outcomes worth another try.
+    #[serde(default = "default_retry_outcomes")]
+    pub retry_outcomes: Vec<ProbeOutcome>,
+    /// Retries after the first probe, 0 turns retrying off.
+    #[serde(default = "default_retry_attempts")]
+    pub retry_attempts: u32,
+    /// Delay before the first retry, doubled for every further one.
+    #[serde(default = "default_retry_backoff_ms")]
+    pub retry_backoff_ms: u64,
+    #[serde(default = "default_retry_backoff_max_ms")]
+    pub retry_backoff_max_ms: u64,
+    /// Retries waiting for their backoff or a worker at once, further ones
+    /// are dropped.
+    #[serde(default = "default_retry_queue_size")]
+    pub retry_queue_size: usize,
+    /// Retries probed at the same time.
+    #[serde(default = "default_retry_workers")]
+    pub retry_workers: usize,
+}
+
+fn default_retry_outcomes() -> Vec<ProbeOutcome> {
+    vec![ProbeOutcome::ReadTimeout, ProbeOutcome::Reset]
+}
+
+fn default_retry_attempts() -> u32 {
+    2
+}
+
+fn default_retry_backoff_ms() -> u64 {
+    2_000
+}
+
+fn default_retry_backoff_max_ms() -> u64 {
+    60_000
+}
+
+fn default_retry_queue_size() -> usize {
+    100_000
+}
+
+fn default_retry_workers() -> usize {
+    64
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            retry_outcomes: default_retry_outcomes(),
+            retry_attempts: default_retry_attempts(),
+            retry_backoff_ms: default_retry_backoff_ms(),
+            retry_backoff_max_ms: default_retry_backoff_max_ms(),
+            retry_queue_size: default_retry_queue_size(),
+            retry_workers: default_retry_workers(),
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Delay before retry number `retry` (starting at 1) after `outcome`, `None`
+    /// if it should not be retried. Exponential backoff with equal jitter: half
+    /// of the delay is fixed, the other half random.
+    pub fn delay(&self, outcome: ProbeOutcome, retry: u32) -> Option<Duration> {
+        if retry == 0 || retry > self.retry_attempts || !self.retry_outcomes.contains(&outcome) {
+            return None;
+        }
+        let backoff = self
+            .retry_backoff_ms
+            .saturating_mul(1 << (retry - 1).min(20))
+            .min(self.retry_backoff_max_ms);
+        let half = backoff / 2;
+        Some(Duration::from_millis(
+            backoff - half + rand::random_range(0..=half),
+        ))
+    }
+}
+
+/// One probe of an address, `number` 0 is the first try.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attempt {
+    pub addr: SocketAddr,
+    pub number: u32,
+}
+
+impl Attempt {
+    pub fn first(addr: SocketAddr) -> Self {
+        Attempt { addr, number: 0 }
+    }
+}
+
+pub struct RetryQueue {
+    config: RetryConfig,
+    tx: mpsc::UnboundedSender<(Attempt, Instant)>,
+    pending: Arc<AtomicUsize>,
+}
+
+/// The receiving end of a [`RetryQueue`], see [`run_retries`].
+pub struct RetryReceiver {
+    rx: mpsc::UnboundedReceiver<(Attempt, Instant)>,
+    pending: Arc<AtomicUsize>,
+}
+
+impl RetryQueue {
+    /// Hand the receiver to [`run_retries`], which probes the attempts once
+    /// their backoff has passed.
+    pub fn new(config: RetryConfig) -> (Self, RetryReceiver) {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let pending = Arc::new(AtomicUsize::new(0));
+        let queue = RetryQueue {
+            config,
+            tx,
+            pending: Arc::clone(&pending),
+        };
+        (queue, RetryReceiver { rx, pending })
+    }
+
+    /// Queue another attempt if `outcome` is retryable and attempts are left.
+    /// Returns whether it was queued.
+    pub fn schedule(&self, attempt: Attempt, outcome: ProbeOutcome) -> bool {
+        let next = attempt.number + 1;
+        let Some(delay) = self.config.delay(outcome, next) else {
+            return false;
+        };
+        if self.pending.fetch_add(1, Ordering::Relaxed) >= self.config.retry_queue_size {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            tracing::debug!("Retry queue full, dropping {}", attempt.addr);
+            return false;
+        }
+        let retry = Attempt {
+            addr: attempt.addr,
+            number: next,
+        };
+        if self.tx.send((retry, Instant::now() + delay)).is_err() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+
+    /// Retries that have not started yet, waiting for their backoff or a
+    /// worker.
+    pub fn pending(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+}
+
+/// Run `probe` for every attempt once its backoff has passed, at most
+/// `workers` at once. Attempts count as pending until they start.
+pub async fn run_retries<F, Fut>(retries: RetryReceiver, workers: usize, probe: F)
+where
+    F: Fn(Attempt) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let RetryReceiver { mut rx, pending } = retries;
+    let semaphore = Arc::new(Semaphore::new(workers.max(1)));
+    let mut delayed = DelayQueue::new();
+    let mut open = true;
+    loop {
+        // Take a worker first, so due attempts wait in the queue for one.
+        let Ok(permit) = Arc::clone(&semaphore).acquire_owned().await else {
+            break;
+        };
+        let due = loop {
+            tokio::select! {
+                scheduled = rx.recv(), if open => match scheduled {
+                    Some((attempt, at)) => {
+                        delayed.insert_at(attempt, at);
+                    }
+                    None => open = false,
+                },
+                Some(expired) = poll_fn(|cx| delayed.poll_expired(cx)) => {
+                    break Some(expired.into_inner());
+                }
+                else => break None,
+            }
+        };
+        let Some(attempt) = due else {
+            break;
+        };
+        pending.fetch_sub(1, Ordering::Relaxed);
+        let future = probe(attempt);
+        tokio::spawn(async move {
+            future.await;
+            drop(permit);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr() -> SocketAddr {
+        "10.0.0.1:25565".parse().unwrap()
+    }
+
+    #[test]
+    fn test_delay() {
+        let config = RetryConfig::default();
+        for _ in 0..100 {
+            let first = config.delay(ProbeOutcome::Reset, 1).unwrap();
+            assert!((1000..=2000).contains(&first.as_millis()), "{:?}", first);
+            let second = config.delay(ProbeOutcome::ReadTimeout, 2).unwrap();
+            assert!((2000..=4000).contains(&second.as_millis()), "{:?}", second);
+        }
+        assert_eq!(config.delay(ProbeOutcome::Reset, 3), None);
+        assert_eq!(config.delay(ProbeOutcome::Refused, 1), None);
+
+        let capped = RetryConfig {
+            retry_attempts: 40,
+            ..RetryConfig::default()
+        };
+        assert!(capped.delay(ProbeOutcome::Reset, 40).unwrap() <= Duration::from_secs(60));
+    }
+
+    #[test]
+    fn test_config_names() {
+        let config: RetryConfig =
+            toml::from_str(r#"retry_outcomes = ["connect_timeout", "reset"]"#).unwrap();
+        assert_eq!(
+            config.retry_outcomes,
+            vec![ProbeOutcome::ConnectTimeout, ProbeOutcome::Reset]
+        );
+        assert_eq!(config.retry_attempts, 2);
+    }
+
+    /// Run the retries of `rx`, sending each attempt to the returned channel
+    /// once it starts and taking a second per probe.
+    fn record(rx: RetryReceiver, workers: usize) -> mpsc::UnboundedReceiver<(Attempt, Instant)> {
+        let (done_tx, done_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run_retries(rx, workers, move |attempt| {
+            let _ = done_tx.send((attempt, Instant::now()));
+            async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }));
+        done_rx
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_queue() {
+        let (queue, rx) = RetryQueue::new(RetryConfig {
+            retry_queue_size: 1,
+            ..RetryConfig::default()
+        });
+        let started = Instant::now();
+        let mut done = record(rx, 1);
+        assert!(queue.schedule(Attempt::first(addr()), ProbeOutcome::Reset));
+        assert!(!queue.schedule(Attempt::first(addr()), ProbeOutcome::Reset));
+        assert!(!queue.schedule(Attempt::first(addr()), ProbeOutcome::Success));
+        assert_eq!(queue.pending(), 1);
+
+        let (retry, at) = done.recv().await.unwrap();
+        assert_eq!(
+            retry,
+            Attempt {
+                addr: addr(),
+                number: 1
+            }
+        );
+        assert!(at - started >= Duration::from_secs(1), "{:?}", at - started);
+        assert_eq!(queue.pending(), 0);
+
+        assert!(queue.schedule(retry, ProbeOutcome::Reset));
+        let (last, _) = done.recv().await.unwrap();
+        assert_eq!(last.number, 2);
+        assert!(!queue.schedule(last, ProbeOutcome::Reset));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pending_until_started() {
+        let (queue, rx) = RetryQueue::new(RetryConfig {
+            retry_backoff_ms: 0,
+            ..RetryConfig::default()
+        });
+        let mut done = record(rx, 1);
+        for _ in 0..3 {
+            assert!(queue.schedule(Attempt::first(addr()), ProbeOutcome::Reset));
+        }
+        // One runs, the other two are due but wait for the worker.
+        let started = Instant::now();
+        done.recv().await.unwrap();
+        tokio::task::yield_now().await;
+        assert_eq!(queue.pending(), 2);
+        done.recv().await.unwrap();
+        assert_eq!(queue.pending(), 1);
+        let (_, at) = done.recv().await.unwrap();
+        assert_eq!(at - started, Duration::from_secs(2));
+        assert_eq!(queue.pending(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_run_retries() {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let (done_tx, mut done_rx) = mpsc::unbounded_channel();
+        let now = Instant::now();
+        for number in 1..=3 {
+            let attempt = Attempt {
+                addr: addr(),
+                number,
+            };
+            // Due in reverse order.
+            tx.send((attempt, now + Duration::from_secs(10 - u64::from(number))))
+                .unwrap();
+        }
+        drop(tx);
+        let retries = RetryReceiver {
+            rx,
+            pending: Arc::new(AtomicUsize::new(3)),
+        };
+        run_retries(retries, 1, move |attempt| {
+            let done_tx = done_tx.clone();
+            async move {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                let _ = done_tx.send(attempt.number);
+            }
+        })
+        .await;
+        let mut done = Vec::new();
+        while let Some(n) = done_rx.recv().await {
+            done.push(n);
+        }
+        assert_eq!(done, vec![3, 2, 1]);
+    }
+}
@@ -0,0 +1,150 @@
+//! In-memory peer that plays back a script, for testing probes without sockets.
+
+use std::{
+    collections::VecDeque,
+    io,
+    pin::Pin,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::{Sleep, sleep},
+};
+
+enum Step {
+    /// Bytes the peer sends, handed out in one read (or less if the buffer is small).
+    Send(Vec<u8>),
+    Delay(Duration),
+    /// Hold back further steps until the client has written this many bytes in total.
+    AwaitWritten(usize),
+    Close,
+    Reset,
+}
+
+/// A fake server. Reads play back the script in order; once the script runs
+/// out the peer stays silent, like a server that accepts and never answers.
+/// Everything the client writes is kept in [`ScriptedPeer::written`].
+pub struct ScriptedPeer {
+    steps: VecDeque<Step>,
+    pub written: Vec<u8>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    read_waker: Option<Waker>,
+}
+
+impl ScriptedPeer {
+    pub fn new() -> Self {
+        ScriptedPeer {
+            steps: VecDeque::new(),
+            written: Vec::new(),
+            sleep: None,
+            read_waker: None,
+        }
+    }
+
+    pub fn send(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.steps.push_back(Step::Send(data.into()));
+        self
+    }
+
+    /// Send `data` one byte per read, pausing `delay` before each byte.
+    pub fn drip(mut self, data: &[u8], delay: Duration) -> Self {
+        for byte in data {
+            self = self.delay(delay).send(vec![*byte]);
+        }
+        self
+    }
+
+    pub fn delay(mut self, delay: Duration) -> Self {
+        self.steps.push_back(Step::Delay(delay));
+        self
+    }
+
+    pub fn await_written(mut self, len: usize) -> Self {
+        self.steps.push_back(Step::AwaitWritten(len));
+        self
+    }
+
+    /// Close the connection, every further read returns EOF.
+    pub fn close(mut self) -> Self {
+        self.steps.push_back(Step::Close);
+        self
+    }
+
+    /// Fail the next read with a connection reset.
+    pub fn reset(mut self) -> Self {
+        self.steps.push_back(Step::Reset);
+        self
+    }
+}
+
+impl AsyncRead for ScriptedPeer {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        loop {
+            match this.steps.front_mut() {
+                None => {
+                    this.read_waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+                Some(Step::Send(data)) => {
+                    let n = data.len().min(buf.remaining());
+                    buf.put_slice(&data[..n]);
+                    data.drain(..n);
+                    if data.is_empty() {
+                        this.steps.pop_front();
+                    }
+                    return Poll::Ready(Ok(()));
+                }
+                Some(Step::Delay(delay)) => {
+                    let delay = *delay;
+                    let timer = this.sleep.get_or_insert_with(|| Box::pin(sleep(delay)));
+                    if timer.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                    this.sleep = None;
+                    this.steps.pop_front();
+                }
+                Some(Step::AwaitWritten(len)) => {
+                    if this.written.len() < *len {
+                        this.read_waker = Some(cx.waker().clone());
+                        return Poll::Pending;
+                    }
+                    this.steps.pop_front();
+                }
+                Some(Step::Close) => return Poll::Ready(Ok(())),
+                Some(Step::Reset) => {
+                    this.steps.pop_front();
+                    return Poll::Ready(Err(io::ErrorKind::ConnectionReset.into()));
+                }
+            }
+        }
+    }
+}
+
+impl AsyncWrite for ScriptedPeer {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        self.written.extend_from_slice(data);
+        if let Some(waker) = self.read_waker.take() {
+            waker.wake();
+        }
+        Poll::Ready(Ok(data.len()))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
@@ -0,0 +1,603 @@
+//! Opt-in recording of the raw bytes exchanged with a server, to debug
+//! servers that produce odd failures.
+
+use std::{
+    fmt::{self, Write as _},
+    io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    time::Instant,
+};
+
+use super::{StatusError, StatusReply, try_handshake_and_status};
+use crate::packets::{
+    PROTOCOL_VERSION, Packet,
+    bytes::{read_byte_array, write_byte_array},
+    clientbound::{
+        EncryptionRequest, LoginDisconnect, LoginSuccess, PongResponse, SetCompression,
+        StatusResponse,
+    },
+    codec::PacketCodec,
+    error::PacketError,
+    frame::Limits,
+    i64::{read_i64, write_i64},
+    serverbound::{Handshake, LoginStart, NEXT_STATE_LOGIN, PingRequest, StatusRequest},
+    string::{read_string_max, write_string},
+    varint::{read_var_int, read_var_int_long, write_var_int, write_var_long},
+};
+
+const MAGIC: &[u8; 4] = b"MCTR";
+const FORMAT_VERSION: i32 = 1;
+pub const FILE_EXTENSION: &str = "mctr";
+/// Transcripts are written by us, but never trust a file more than the network.
+const MAX_EVENT_SIZE: usize = 16 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum TranscriptError {
+    /// The file does not start with the transcript magic number.
+    NotTranscript,
+    /// Written in a format version this build cannot read.
+    UnsupportedVersion(i32),
+    /// The transcript is cut off or corrupt.
+    Packet(PacketError),
+}
+
+impl fmt::Display for TranscriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TranscriptError::NotTranscript => write!(f, "not a transcript"),
+            TranscriptError::UnsupportedVersion(version) => write!(
+                f,
+                "transcript format version {} is not supported, expected {}",
+                version, FORMAT_VERSION
+            ),
+            TranscriptError::Packet(e) => write!(f, "corrupt transcript: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TranscriptError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TranscriptError::Packet(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<PacketError> for TranscriptError {
+    fn from(e: PacketError) -> Self {
+        TranscriptError::Packet(e)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Event {
+    /// Time since the transcript started.
+    pub at: Duration,
+    pub direction: Direction,
+    /// Empty for a received EOF.
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transcript {
+    pub target: String,
+    /// Unix time in milliseconds.
+    pub started: i64,
+    /// Error of the probe, `None` if it succeeded.
+    pub outcome: Option<String>,
+    pub events: Vec<Event>,
+}
+
+impl Transcript {
+    pub fn encode(&self) -> Result<Vec<u8>, PacketError> {
+        let mut buf = MAGIC.to_vec();
+        write_var_int(&mut buf, &FORMAT_VERSION);
+        write_string(&mut buf, &self.target)?;
+        write_i64(&mut buf, self.started);
+        write_string(&mut buf, self.outcome.as_deref().unwrap_or_default())?;
+        write_var_int(&mut buf, &(self.events.len() as i32));
+        for event in &self.events {
+            buf.push(match event.direction {
+                Direction::Sent => 0,
+                Direction::Received => 1,
+            });
+            write_var_long(&mut buf, event.at.as_micros() as i64);
+            write_byte_array(&mut buf, &event.data);
+        }
+        Ok(buf)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self, TranscriptError> {
+        if !data.starts_with(MAGIC) {
+            return Err(TranscriptError::NotTranscript);
+        }
+        let mut index = MAGIC.len();
+        let version = read_var_int(data, Some(&mut index))?;
+        if version != FORMAT_VERSION {
+            return Err(TranscriptError::UnsupportedVersion(version));
+        }
+        let target = read_string_max(data, &mut index, 255)?;
+        let started = read_i64(data, &mut index)?;
+        let outcome = read_string_max(data, &mut index, MAX_EVENT_SIZE)?;
+        let count = read_var_int(data, Some(&mut index))?;
+        let mut events = Vec::new();
+        for _ in 0..count.max(0) {
+            let direction = match data.get(index) {
+                Some(0) => Direction::Sent,
+                Some(_) => Direction::Received,
+                None => return Err(PacketError::UnexpectedEof.into()),
+            };
+            index += 1;
+            let at = read_var_int_long(data, Some(&mut index))?;
+            events.push(Event {
+                at: Duration::from_micros(at.max(0) as u64),
+                direction,
+                data: read_byte_array(data, &mut index, MAX_EVENT_SIZE)?,
+            });
+        }
+        Ok(Transcript {
+            target,
+            started,
+            outcome: (!outcome.is_empty()).then_some(outcome),
+            events,
+        })
+    }
+}
+
+/// Wraps a transport and records every byte read and written.
+pub struct Recorder<S> {
+    inner: S,
+    started: Instant,
+    events: Vec<Event>,
+}
+
+impl<S> Recorder<S> {
+    pub fn new(inner: S) -> Self {
+        Recorder {
+            inner,
+            started: Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    pub fn into_events(self) -> Vec<Event> {
+        self.events
+    }
+
+    fn record(&mut self, direction: Direction, data: &[u8]) {
+        self.events.push(Event {
+            at: self.started.elapsed(),
+            direction,
+            data: data.to_vec(),
+        });
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Recorder<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let before = buf.filled().len();
+        let result = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = result {
+            self.record(Direction::Received, &buf.filled()[before..]);
+        }
+        result
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Recorder<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let result = Pin::new(&mut self.inner).poll_write(cx, data);
+        if let Poll::Ready(Ok(n)) = result {
+            self.record(Direction::Sent, &data[..n]);
+        }
+        result
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Capture settings, shared by the scanner and verifier configs.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct CaptureConfig {
+    /// Directory transcripts are written to, capturing is off without it.
+    #[serde(default)]
+    pub transcript_dir: Option<PathBuf>,
+    /// Addresses (`ip` or `ip:port`) whose probes are always recorded.
+    #[serde(default)]
+    pub transcript_targets: Vec<String>,
+    /// Record every probe that fails.
+    #[serde(default)]
+    pub transcript_failures: bool,
+}
+
+impl CaptureConfig {
+    fn is_target(&self, addr: &SocketAddr) -> bool {
+        let ip = addr.ip().to_string();
+        let full = addr.to_string();
+        self.transcript_targets
+            .iter()
+            .any(|t| *t == ip || *t == full)
+    }
+
+    fn wants(&self, addr: &SocketAddr) -> bool {
+        self.transcript_dir.is_some() && (self.transcript_failures || self.is_target(addr))
+    }
+}
+
+/// [`try_handshake_and_status`], recording the exchange when `capture` asks for it.
+pub async fn status_with_capture<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    capture: &CaptureConfig,
+    addr: SocketAddr,
+    host: &str,
+    limits: &Limits,
+    timeout_duration: Duration,
+) -> Result<StatusReply, StatusError> {
+    if !capture.wants(&addr) {
+        return try_handshake_and_status(stream, host, addr.port(), limits, timeout_duration).await;
+    }
+    let started = unix_millis();
+    let mut recorder = Recorder::new(stream);
+    let result =
+        try_handshake_and_status(&mut recorder, host, addr.port(), limits, timeout_duration).await;
+    if (result.is_err() && capture.transcript_failures) || capture.is_target(&addr) {
+        let transcript = Transcript {
+            target: addr.to_string(),
+            started,
+            outcome: result.as_ref().err().map(|e| e.to_string()),
+            events: recorder.into_events(),
+        };
+        if let Some(dir) = &capture.transcript_dir
+            && let Err(e) = save_transcript(dir, &transcript).await
+        {
+            tracing::error!("Failed to save transcript for {}: {}", addr, e);
+        }
+    }
+    result
+}
+
+fn unix_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Write a transcript to `<dir>/<ip>_<port>_<unix ms>.mctr`.
+pub async fn save_transcript(dir: &Path, transcript: &Transcript) -> io::Result<PathBuf> {
+    let data = transcript
+        .encode()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    tokio::fs::create_dir_all(dir).await?;
+    let name = format!(
+        "{}_{}.{}",
+        transcript.target.replace([':', '[', ']'], "_"),
+        transcript.started,
+        FILE_EXTENSION
+    );
+    let path = dir.join(name);
+    tokio::fs::write(&path, data).await?;
+    Ok(path)
+}
+
+/// `<binary> transcript <file>...`: print saved transcripts to stdout.
+pub async fn print_files(paths: &[String]) {
+    for path in paths {
+        match tokio::fs::read(path).await {
+            Ok(data) => match Transcript::decode(&data) {
+                Ok(transcript) => println!("{}:\n{}", path, render(&transcript)),
+                Err(e) => eprintln!("{}: {}", path, e),
+            },
+            Err(e) => eprintln!("{}: {}", path, e),
+        }
+    }
+}
+
+/// Pretty-print a transcript: a hex dump of every event, followed by the
+/// packets decoded from each direction.
+pub fn render(transcript: &Transcript) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "target {} started {} (unix ms), outcome: {}",
+        transcript.target,
+        transcript.started,
+        transcript.outcome.as_deref().unwrap_or("ok")
+    );
+    let mut decoder = Decoder::new();
+    for event in &transcript.events {
+        let arrow = match event.direction {
+            Direction::Sent => ">>",
+            Direction::Received => "<<",
+        };
+        let ms = event.at.as_secs_f64() * 1000.0;
+        if event.data.is_empty() {
+            let _ = writeln!(out, "+{:.3}ms {} closed", ms, arrow);
+            continue;
+        }
+        let _ = writeln!(out, "+{:.3}ms {} {} bytes", ms, arrow, event.data.len());
+        hex_dump(&mut out, &event.data);
+        for line in decoder.feed(event.direction, &event.data) {
+            let _ = writeln!(out, "    {}", line);
+        }
+    }
+    for line in decoder.leftovers() {
+        let _ = writeln!(out, "{}", line);
+    }
+    out
+}
+
+fn hex_dump(out: &mut String, data: &[u8]) {
+    for (row, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|b| {
+                if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            "  {:04x}  {:<47}  |{}|",
+            row * 16,
+            hex.join(" "),
+            ascii
+        );
+    }
+}
+
+/// Follows the connection state (handshake, then status or login) so packet
+/// ids can be named.
+struct Decoder {
+    sent: Option<PacketCodec>,
+    received: Option<PacketCodec>,
+    next_state: Option<i32>,
+    /// From the handshake, Login Start depends on it.
+    protocol_version: i32,
+}
+
+impl Decoder {
+    fn new() -> Self {
+        Decoder {
+            sent: Some(PacketCodec::new(Limits::default())),
+            received: Some(PacketCodec::new(Limits::default())),
+            next_state: None,
+            protocol_version: PROTOCOL_VERSION,
+        }
+    }
+
+    fn feed(&mut self, direction: Direction, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        let codec = match direction {
+            Direction::Sent => &mut self.sent,
+            Direction::Received => &mut self.received,
+        };
+        let Some(c) = codec.as_mut() else {
+            return lines;
+        };
+        c.feed(data);
+        let mut packets = Vec::new();
+        loop {
+            match c.next_packet() {
+                Ok(Some(packet)) => packets.push(packet),
+                Ok(None) => break,
+                Err(e) => {
+                    lines.push(format!("undecodable frame: {}", e));
+                    *codec = None;
+                    break;
+                }
+            }
+        }
+        for packet in packets {
+            lines.push(self.describe(direction, &packet));
+        }
+        lines
+    }
+
+    fn describe(&mut self, direction: Direction, packet: &[u8]) -> String {
+        let limits = Limits::default();
+        let mut index = 0;
+        let id = match read_var_int(packet, Some(&mut index)) {
+            Ok(id) => id,
+            Err(e) => return format!("packet without id: {}", e),
+        };
+        let login = self.next_state == Some(NEXT_STATE_LOGIN);
+        let decoded = match (direction, self.next_state, id) {
+            (Direction::Sent, None, Handshake::ID) => Handshake::decode(packet, &limits).map(|h| {
+                self.next_state = Some(h.next_state);
+                self.protocol_version = h.protocol_version;
+                format!("{:?}", h)
+            }),
+            (Direction::Sent, Some(_), LoginStart::ID) if login => {
+                LoginStart::read_versioned(packet, &mut index, &limits, self.protocol_version)
+                    .map(|p| format!("{:?}", p))
+            }
+            (Direction::Sent, Some(_), StatusRequest::ID) => {
+                StatusRequest::decode(packet, &limits).map(|p| format!("{:?}", p))
+            }
+            (Direction::Sent, Some(_), PingRequest::ID) => {
+                PingRequest::decode(packet, &limits).map(|p| format!("{:?}", p))
+            }
+            (Direction::Received, _, LoginDisconnect::ID) if login => {
+                LoginDisconnect::decode(packet, &limits).map(|p| format!("{:?}", p))
+            }
+            (Direction::Received, _, EncryptionRequest::ID) if login => {
+                EncryptionRequest::decode(packet, &limits).map(|p| format!("{:?}", p))
+            }
+            (Direction::Received, _, LoginSuccess::ID) if login => {
+                LoginSuccess::decode(packet, &limits).map(|p| format!("{:?}", p))
+            }
+            (Direction::Received, _, SetCompression::ID) if login => {
+                SetCompression::decode(packet, &limits).map(|p| {
+                    for codec in [&mut self.sent, &mut self.received].into_iter().flatten() {
+                        codec.set_compression(p.threshold);
+                    }
+                    format!("{:?}", p)
+                })
+            }
+            (Direction::Received, _, StatusResponse::ID) => {
+                StatusResponse::decode(packet, &limits).map(|p| format!("{:?}", p))
+            }
+            (Direction::Received, _, PongResponse::ID) => {
+                PongResponse::decode(packet, &limits).map(|p| format!("{:?}", p))
+            }
+            _ => return format!("packet {:#04x}, {} bytes", id, packet.len() - index),
+        };
+        decoded.unwrap_or_else(|e| format!("packet {:#04x}, invalid: {}", id, e))
+    }
+
+    fn leftovers(&self) -> Vec<String> {
+        [("sent", &self.sent), ("received", &self.received)]
+            .into_iter()
+            .filter_map(|(name, codec)| {
+                let pending = codec.as_ref()?.pending();
+                (pending > 0)
+                    .then(|| format!("{} {} bytes do not form a whole frame", pending, name))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::scripted::ScriptedPeer, *};
+
+    fn sample() -> Transcript {
+        Transcript {
+            target: "1.2.3.4:25565".to_string(),
+            started: 1_700_000_000_000,
+            outcome: Some("truncated response: 3 of 40 bytes".to_string()),
+            events: vec![
+                Event {
+                    at: Duration::from_micros(15),
+                    direction: Direction::Sent,
+                    data: vec![1, 2, 3],
+                },
+                Event {
+                    at: Duration::from_millis(40),
+                    direction: Direction::Received,
+                    data: Vec::new(),
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let transcript = sample();
+        let encoded = transcript.encode().unwrap();
+        assert_eq!(Transcript::decode(&encoded).unwrap(), transcript);
+        assert!(matches!(
+            Transcript::decode(&encoded[..encoded.len() - 1]),
+            Err(TranscriptError::Packet(PacketError::UnexpectedEof))
+        ));
+        assert!(matches!(
+            Transcript::decode(b"not a transcript"),
+            Err(TranscriptError::NotTranscript)
+        ));
+        let mut newer = MAGIC.to_vec();
+        write_var_int(&mut newer, &(FORMAT_VERSION + 1));
+        assert!(matches!(
+            Transcript::decode(&newer),
+            Err(TranscriptError::UnsupportedVersion(2))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_record_and_render() {
+        let response = StatusResponse {
+            json: r#"{"description":"hi"}"#.to_string(),
+        }
+        .encode()
+        .unwrap();
+        let peer = ScriptedPeer::new()
+            .delay(Duration::from_millis(5))
+            .send(&response[..4])
+            .send(&response[4..])
+            .close();
+        let mut recorder = Recorder::new(peer);
+        try_handshake_and_status(
+            &mut recorder,
+            "example.com",
+            25565,
+            &Limits::default(),
+            Duration::from_secs(1),
+        )
+        .await
+        .unwrap();
+        let transcript = Transcript {
+            target: "1.2.3.4:25565".to_string(),
+            started: 0,
+            outcome: None,
+            events: recorder.into_events(),
+        };
+        assert_eq!(transcript.events[0].direction, Direction::Sent);
+        assert_eq!(transcript.events[1].at, Duration::from_millis(5));
+
+        let text = render(&transcript);
+        assert!(text.contains("outcome: ok"), "{}", text);
+        assert!(
+            text.contains(r#"server_address: "example.com""#),
+            "{}",
+            text
+        );
+        assert!(text.contains("StatusRequest"), "{}", text);
+        assert!(
+            text.contains(r#"StatusResponse { json: "{\"description\":\"hi\"}" }"#),
+            "{}",
+            text
+        );
+        assert!(text.contains("+5.000ms << "), "{}", text);
+    }
+
+    #[test]
+    fn test_render_garbage() {
+        let transcript = Transcript {
+            target: "1.2.3.4:25565".to_string(),
+            started: 0,
+            outcome: Some("malformed response".to_string()),
+            events: vec![Event {
+                at: Duration::ZERO,
+                direction: Direction::Received,
+                data: b"SSH-2.0-OpenSSH\r\n".to_vec(),
+            }],
+        };
+        let text = render(&transcript);
+        assert!(text.contains("|SSH-2.0-OpenSSH.|"), "{}", text);
+        assert!(text.contains("do not form a whole frame"), "{}", text);
+    }
+}
@@ -0,0 +1,186 @@
+//! Reverse DNS (PTR) lookups of server addresses. Lookups go through one
+//! shared rate limit, so a large backlog of new servers does not flood the
+//! resolver.
+
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use hickory_resolver::{
+    TokioAsyncResolver,
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::{ResolveError, ResolveErrorKind},
+};
+use serde::Deserialize;
+use tokio::{
+    sync::Mutex,
+    time::{Interval, MissedTickBehavior, interval},
+};
+
+/// Reverse DNS settings, flattened into the verifier config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RdnsConfig {
+    /// Nameserver as `ip` or `ip:port`, the system resolver when unset.
+    #[serde(default)]
+    pub rdns_resolver: Option<String>,
+    /// PTR lookups per second, 0 turns reverse DNS off.
+    #[serde(default = "default_rdns_rate")]
+    pub rdns_rate: u32,
+    /// How long a stored PTR record is kept before it is looked up again.
+    #[serde(default = "default_rdns_refresh_hours")]
+    pub rdns_refresh_hours: u64,
+    #[serde(default = "default_rdns_timeout_ms")]
+    pub rdns_timeout_ms: u64,
+}
+
+fn default_rdns_rate() -> u32 {
+    10
+}
+
+fn default_rdns_refresh_hours() -> u64 {
+    7 * 24
+}
+
+fn default_rdns_timeout_ms() -> u64 {
+    2_000
+}
+
+impl Default for RdnsConfig {
+    fn default() -> Self {
+        RdnsConfig {
+            rdns_resolver: None,
+            rdns_rate: default_rdns_rate(),
+            rdns_refresh_hours: default_rdns_refresh_hours(),
+            rdns_timeout_ms: default_rdns_timeout_ms(),
+        }
+    }
+}
+
+impl RdnsConfig {
+    pub fn refresh(&self) -> Duration {
+        Duration::from_secs(self.rdns_refresh_hours * 3600)
+    }
+}
+
+#[derive(Debug)]
+pub enum RdnsError {
+    InvalidResolver(String),
+    System(ResolveError),
+}
+
+impl fmt::Display for RdnsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RdnsError::InvalidResolver(s) => write!(f, "invalid rdns_resolver {:?}", s),
+            RdnsError::System(e) => write!(f, "system resolver: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RdnsError {}
+
+/// `ip` or `ip:port`, port 53 by default.
+fn parse_nameserver(s: &str) -> Option<SocketAddr> {
+    s.parse::<SocketAddr>()
+        .ok()
+        .or_else(|| s.parse::<IpAddr>().ok().map(|ip| SocketAddr::new(ip, 53)))
+}
+
+/// Lets one caller through per `1 / per_second`. Waiting callers queue up,
+/// missed ticks are not made up for with a burst.
+pub struct RateLimiter {
+    interval: Mutex<Interval>,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32) -> RateLimiter {
+        let mut interval = interval(Duration::from_secs(1) / per_second.max(1));
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        RateLimiter {
+            interval: Mutex::new(interval),
+        }
+    }
+
+    pub async fn wait(&self) {
+        self.interval.lock().await.tick().await;
+    }
+}
+
+pub struct Rdns {
+    resolver: TokioAsyncResolver,
+    limiter: RateLimiter,
+}
+
+pub fn build_rdns(config: &RdnsConfig) -> Result<Rdns, RdnsError> {
+    let mut options = ResolverOpts::default();
+    options.timeout = Duration::from_millis(config.rdns_timeout_ms);
+    options.attempts = 2;
+    let resolver = match &config.rdns_resolver {
+        Some(s) => {
+            let addr = parse_nameserver(s).ok_or_else(|| RdnsError::InvalidResolver(s.clone()))?;
+            let servers = NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true);
+            TokioAsyncResolver::tokio(ResolverConfig::from_parts(None, vec![], servers), options)
+        }
+        None => {
+            let (system, _) =
+                hickory_resolver::system_conf::read_system_conf().map_err(RdnsError::System)?;
+            TokioAsyncResolver::tokio(system, options)
+        }
+    };
+    Ok(Rdns {
+        resolver,
+        limiter: RateLimiter::new(config.rdns_rate),
+    })
+}
+
+impl Rdns {
+    /// The PTR name of `ip`, lowercase without the trailing dot, `Ok(None)`
+    /// when there is none. Waits for the rate limit first.
+    pub async fn lookup(&self, ip: IpAddr) -> Result<Option<String>, ResolveError> {
+        self.limiter.wait().await;
+        match self.resolver.reverse_lookup(ip).await {
+            Ok(lookup) => Ok(lookup
+                .iter()
+                .next()
+                .map(|name| name.to_utf8().trim_end_matches('.').to_ascii_lowercase())),
+            Err(e) if matches!(e.kind(), ResolveErrorKind::NoRecordsFound { .. }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::Instant;
+
+    #[test]
+    fn test_parse_nameserver() {
+        assert_eq!(
+            parse_nameserver("127.0.0.1"),
+            Some("127.0.0.1:53".parse().unwrap())
+        );
+        assert_eq!(
+            parse_nameserver("127.0.0.1:5353"),
+            Some("127.0.0.1:5353".parse().unwrap())
+        );
+        assert_eq!(
+            parse_nameserver("[::1]:5353"),
+            Some("[::1]:5353".parse().unwrap())
+        );
+        assert_eq!(parse_nameserver("dns.example"), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter() {
+        let limiter = RateLimiter::new(4);
+        let started = Instant::now();
+        for _ in 0..9 {
+            limiter.wait().await;
+        }
+        // The first call goes through right away.
+        assert_eq!(started.elapsed(), Duration::from_secs(2));
+    }
+}
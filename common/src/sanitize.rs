@@ -0,0 +1,256 @@
+//! Validation of `players.sample` entries before they are stored as players.
+//!
+//! Servers put all kinds of things in the sample: MOTD lines, ads, hidden
+//! players. An entry is only kept when its UUID parses and its name follows
+//! the rules of its account type. Java names follow the Mojang rules, Bedrock
+//! players joining through Geyser / Floodgate have a Floodgate UUID (the XUID
+//! in the low 64 bits) and usually a `.` or `*` in front of their gamertag.
+
+use crate::utils::name_to_uuid;
+
+/// Username prefixes Floodgate setups commonly use for Bedrock players.
+pub const FLOODGATE_PREFIXES: &[char] = &['.', '*'];
+
+/// Name servers send for players who hide themselves, with the nil UUID.
+pub const ANONYMOUS_PLAYER: &str = "Anonymous Player";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayerKind {
+    /// Online-mode Java account.
+    Java,
+    /// Offline-mode (cracked) account, the UUID is derived from the name.
+    Offline,
+    /// Bedrock account joined through Geyser / Floodgate.
+    Bedrock,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SamplePlayer {
+    pub name: String,
+    /// Lowercase and with dashes.
+    pub uuid: String,
+    pub kind: PlayerKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// A hidden player, expected and not worth reviewing.
+    Anonymous,
+    MissingName,
+    MissingId,
+    /// Not 32 hex digits, with or without dashes.
+    InvalidUuid,
+    /// The nil UUID with a name other than [`ANONYMOUS_PLAYER`], usually a
+    /// MOTD line.
+    NilUuid,
+    /// `§` colour or formatting codes, only MOTD lines have them.
+    FormattingCodes,
+    /// Breaks the Mojang rules: 3 to 16 of `a-z`, `A-Z`, `0-9` and `_`.
+    InvalidJavaName,
+    /// Floodgate UUID, but not a plausible gamertag.
+    InvalidBedrockName,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectReason::Anonymous => "anonymous",
+            RejectReason::MissingName => "missing_name",
+            RejectReason::MissingId => "missing_id",
+            RejectReason::InvalidUuid => "invalid_uuid",
+            RejectReason::NilUuid => "nil_uuid",
+            RejectReason::FormattingCodes => "formatting_codes",
+            RejectReason::InvalidJavaName => "invalid_java_name",
+            RejectReason::InvalidBedrockName => "invalid_bedrock_name",
+        }
+    }
+}
+
+/// `uuid` lowercase and with dashes. Accepts the dashed form and 32 plain hex
+/// digits.
+pub fn normalize_uuid(uuid: &str) -> Option<String> {
+    let uuid = uuid.trim();
+    let hex: String = match uuid.len() {
+        32 => uuid.to_string(),
+        36 if [8, 13, 18, 23].iter().all(|&i| uuid.as_bytes()[i] == b'-') => uuid.replace('-', ""),
+        _ => return None,
+    };
+    if hex.len() != 32 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let hex = hex.to_ascii_lowercase();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// Floodgate UUIDs are the XUID with the upper 64 bits zero.
+fn is_floodgate_uuid(uuid: &str) -> bool {
+    uuid.starts_with("00000000-0000-0000-") && !uuid.ends_with("0000-000000000000")
+}
+
+fn is_java_name(name: &str) -> bool {
+    (3..=16).contains(&name.len()) && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Floodgate replaces spaces in gamertags with `_` and cuts the prefixed name
+/// to 16 characters.
+fn is_bedrock_name(name: &str) -> bool {
+    let gamertag = name.strip_prefix(FLOODGATE_PREFIXES).unwrap_or(name);
+    !gamertag.is_empty()
+        && name.len() <= 16
+        && gamertag
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Check one sample entry and normalize its UUID.
+pub fn sanitize_player(name: Option<&str>, id: Option<&str>) -> Result<SamplePlayer, RejectReason> {
+    let name = name.filter(|n| !n.trim().is_empty());
+    let id = id.filter(|id| !id.trim().is_empty());
+    let Some(name) = name else {
+        return Err(RejectReason::MissingName);
+    };
+    if name.contains('§') {
+        return Err(RejectReason::FormattingCodes);
+    }
+    let Some(id) = id else {
+        return Err(RejectReason::MissingId);
+    };
+    let uuid = normalize_uuid(id).ok_or(RejectReason::InvalidUuid)?;
+    if uuid.bytes().all(|b| b == b'0' || b == b'-') {
+        return Err(if name == ANONYMOUS_PLAYER {
+            RejectReason::Anonymous
+        } else {
+            RejectReason::NilUuid
+        });
+    }
+    let kind = if is_floodgate_uuid(&uuid) {
+        if !is_bedrock_name(name) {
+            return Err(RejectReason::InvalidBedrockName);
+        }
+        PlayerKind::Bedrock
+    } else {
+        if !is_java_name(name) {
+            return Err(RejectReason::InvalidJavaName);
+        }
+        if name_to_uuid(name) == uuid {
+            PlayerKind::Offline
+        } else {
+            PlayerKind::Java
+        }
+    };
+    Ok(SamplePlayer {
+        name: name.to_string(),
+        uuid,
+        kind,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_uuid() {
+        let dashed = "069a79f4-44e9-4726-a5be-fca90e38aaf5";
+        assert_eq!(normalize_uuid(dashed).as_deref(), Some(dashed));
+        assert_eq!(
+            normalize_uuid("069A79F444E94726A5BEFCA90E38AAF5").as_deref(),
+            Some(dashed)
+        );
+        assert_eq!(normalize_uuid("069a79f4-44e9-4726-a5be-fca90e38aaf"), None);
+        assert_eq!(normalize_uuid("069a79f444e9-4726-a5be-fca90e38aaf5-"), None);
+        assert_eq!(normalize_uuid("069a79f4-44e9-4726-a5be-fca90e38-af5"), None);
+        assert_eq!(normalize_uuid("g69a79f444e94726a5befca90e38aaf5"), None);
+    }
+
+    #[test]
+    fn test_java_players() {
+        let notch =
+            sanitize_player(Some("Notch"), Some("069a79f444e94726a5befca90e38aaf5")).unwrap();
+        assert_eq!(notch.uuid, "069a79f4-44e9-4726-a5be-fca90e38aaf5");
+        assert_eq!(notch.kind, PlayerKind::Java);
+
+        let offline = name_to_uuid("Steve");
+        assert_eq!(
+            sanitize_player(Some("Steve"), Some(&offline)).unwrap().kind,
+            PlayerKind::Offline
+        );
+        for name in [
+            "ab",
+            "seventeen_chars_x",
+            "play.example.net",
+            "a b c",
+            "Ünïcode",
+        ] {
+            assert_eq!(
+                sanitize_player(Some(name), Some(&offline)),
+                Err(RejectReason::InvalidJavaName),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_bedrock_players() {
+        let uuid = "00000000-0000-0000-0009-01f64f65c7c3";
+        // The prefix is set per server and may be empty.
+        for name in [".Steve_Bedrock", "*Steve", "Steve_Bedrock"] {
+            assert_eq!(
+                sanitize_player(Some(name), Some(uuid)).unwrap().kind,
+                PlayerKind::Bedrock,
+                "{}",
+                name
+            );
+        }
+        assert_eq!(
+            sanitize_player(Some(".Too_Long_Gamertag"), Some(uuid)),
+            Err(RejectReason::InvalidBedrockName)
+        );
+        assert_eq!(
+            sanitize_player(Some("."), Some(uuid)),
+            Err(RejectReason::InvalidBedrockName)
+        );
+        // A dot prefix alone does not make a Bedrock player.
+        assert_eq!(
+            sanitize_player(Some(".Steve"), Some("069a79f4-44e9-4726-a5be-fca90e38aaf5")),
+            Err(RejectReason::InvalidJavaName)
+        );
+    }
+
+    #[test]
+    fn test_rejected_entries() {
+        let nil = "00000000-0000-0000-0000-000000000000";
+        assert_eq!(
+            sanitize_player(Some("Anonymous Player"), Some(nil)),
+            Err(RejectReason::Anonymous)
+        );
+        assert_eq!(
+            sanitize_player(Some("Join now"), Some(nil)),
+            Err(RejectReason::NilUuid)
+        );
+        assert_eq!(
+            sanitize_player(Some("§aWelcome"), Some(nil)),
+            Err(RejectReason::FormattingCodes)
+        );
+        assert_eq!(
+            sanitize_player(Some("Notch"), Some("x")),
+            Err(RejectReason::InvalidUuid)
+        );
+        assert_eq!(
+            sanitize_player(Some(" "), Some(nil)),
+            Err(RejectReason::MissingName)
+        );
+        assert_eq!(
+            sanitize_player(Some("Notch"), None),
+            Err(RejectReason::MissingId)
+        );
+    }
+}
@@ -0,0 +1,264 @@
+//! Heuristics for honeypots and fake status responses.
+//!
+//! Each check that fires adds a reason and its weight to the score (capped at
+//! 1.0). Checks on a single response run in the save path, the
+//! [`SHARED_RESPONSE`] check needs every server and runs as a separate pass.
+
+use std::{collections::HashSet, time::Duration};
+
+use serde_json::Value;
+
+use crate::{
+    packets::PROTOCOL_VERSION,
+    sanitize::{RejectReason, sanitize_player},
+    versions::parse_version_range,
+};
+
+/// More entries in `players.sample` than `players.online`.
+pub const SAMPLE_EXCEEDS_ONLINE: &str = "sample_exceeds_online";
+pub const ONLINE_EXCEEDS_MAX: &str = "online_exceeds_max";
+/// Negative counts, or more players than any real server ever had.
+pub const IMPOSSIBLE_PLAYER_COUNT: &str = "impossible_player_count";
+/// Sample entries that are MOTD lines rather than players, anything
+/// [`sanitize_player`] rejects except hidden players.
+pub const FAKE_SAMPLE_ENTRIES: &str = "fake_sample_entries";
+pub const DUPLICATE_SAMPLE_IDS: &str = "duplicate_sample_ids";
+/// `version.name` is an exact release, but `version.protocol` belongs to another one.
+pub const PROTOCOL_MISMATCH: &str = "protocol_mismatch";
+pub const PROTOCOL_OUT_OF_RANGE: &str = "protocol_out_of_range";
+/// Answered faster than any server across a real network can.
+pub const INSTANT_RESPONSE: &str = "instant_response";
+/// The exact same response is served by many addresses.
+pub const SHARED_RESPONSE: &str = "shared_response";
+
+const WEIGHTS: &[(&str, f32)] = &[
+    (SAMPLE_EXCEEDS_ONLINE, 0.3),
+    (ONLINE_EXCEEDS_MAX, 0.1),
+    (IMPOSSIBLE_PLAYER_COUNT, 0.4),
+    (FAKE_SAMPLE_ENTRIES, 0.3),
+    (DUPLICATE_SAMPLE_IDS, 0.2),
+    (PROTOCOL_MISMATCH, 0.2),
+    (PROTOCOL_OUT_OF_RANGE, 0.2),
+    (INSTANT_RESPONSE, 0.2),
+    (SHARED_RESPONSE, 0.4),
+];
+
+const MAX_PLAUSIBLE_PLAYERS: i64 = 1_000_000;
+const INSTANT_THRESHOLD: Duration = Duration::from_millis(1);
+/// Snapshots use protocol numbers from 0x40000000 upwards.
+const SNAPSHOT_PROTOCOL_BASE: i64 = 0x4000_0000;
+const MAX_RELEASE_PROTOCOL: i64 = 2000;
+/// MOTD of a freshly generated vanilla server, shared by thousands of real servers.
+const DEFAULT_MOTD: &str = "A Minecraft Server";
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Suspicion {
+    /// 0.0 - 1.0, sum of the weights of all reasons.
+    pub score: f32,
+    pub reasons: Vec<&'static str>,
+}
+
+impl Suspicion {
+    pub fn add(&mut self, reason: &'static str) {
+        if self.reasons.contains(&reason) {
+            return;
+        }
+        self.reasons.push(reason);
+        self.score = (self.score + weight(reason)).min(1.0);
+    }
+}
+
+pub fn weight(reason: &str) -> f32 {
+    WEIGHTS
+        .iter()
+        .find(|(r, _)| *r == reason)
+        .map(|(_, w)| *w)
+        .unwrap_or(0.0)
+}
+
+/// Score a single status response. `latency` is the time from sending the
+/// status request to having the whole response.
+pub fn score_response(json: &Value, latency: Duration) -> Suspicion {
+    let mut suspicion = Suspicion::default();
+    let players = json.get("players");
+    let online = players
+        .and_then(|p| p.get("online"))
+        .and_then(|v| v.as_i64());
+    let max = players.and_then(|p| p.get("max")).and_then(|v| v.as_i64());
+    let sample = players
+        .and_then(|p| p.get("sample"))
+        .and_then(|v| v.as_array())
+        .map(|a| a.as_slice())
+        .unwrap_or_default();
+
+    if [online, max]
+        .iter()
+        .flatten()
+        .any(|n| *n < 0 || *n > MAX_PLAUSIBLE_PLAYERS)
+    {
+        suspicion.add(IMPOSSIBLE_PLAYER_COUNT);
+    }
+    if let (Some(online), Some(max)) = (online, max)
+        && max > 0
+        && online > max
+    {
+        suspicion.add(ONLINE_EXCEEDS_MAX);
+    }
+    if let Some(online) = online
+        && sample.len() as i64 > online
+    {
+        suspicion.add(SAMPLE_EXCEEDS_ONLINE);
+    }
+
+    let mut ids = HashSet::new();
+    for entry in sample {
+        let name = entry.get("name").and_then(|v| v.as_str());
+        let id = entry.get("id").and_then(|v| v.as_str());
+        match sanitize_player(name, id) {
+            Ok(player) => {
+                if !ids.insert(player.uuid) {
+                    suspicion.add(DUPLICATE_SAMPLE_IDS);
+                }
+            }
+            Err(RejectReason::Anonymous) => {}
+            Err(_) => suspicion.add(FAKE_SAMPLE_ENTRIES),
+        }
+    }
+
+    let version = json.get("version");
+    let name = version.and_then(|v| v.get("name")).and_then(|v| v.as_str());
+    if let Some(protocol) = version
+        .and_then(|v| v.get("protocol"))
+        .and_then(|v| v.as_i64())
+    {
+        if protocol > MAX_RELEASE_PROTOCOL && protocol < SNAPSHOT_PROTOCOL_BASE {
+            suspicion.add(PROTOCOL_OUT_OF_RANGE);
+        }
+        // Proxies and ViaVersion answer with the protocol of our handshake.
+        let range = parse_version_range(name, None);
+        if protocol > 0
+            && protocol != i64::from(PROTOCOL_VERSION)
+            && range.min == range.max
+            && let (Some(low), Some(high)) = (range.min_protocol, range.max_protocol)
+            && low == high
+            && i64::from(low) != protocol
+        {
+            suspicion.add(PROTOCOL_MISMATCH);
+        }
+    }
+
+    if latency < INSTANT_THRESHOLD {
+        suspicion.add(INSTANT_RESPONSE);
+    }
+    suspicion
+}
+
+/// Hash used to cluster identical responses across addresses, `None` for the
+/// untouched vanilla default, which is not worth clustering.
+pub fn response_hash(json_str: &str, json: &Value) -> Option<String> {
+    let description = json.get("description");
+    let default_motd = description.and_then(|d| d.as_str()) == Some(DEFAULT_MOTD)
+        || description
+            .and_then(|d| d.get("text"))
+            .and_then(|t| t.as_str())
+            == Some(DEFAULT_MOTD);
+    if default_motd && json.get("favicon").is_none() {
+        return None;
+    }
+    Some(format!("{:x}", md5::compute(json_str)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LATENCY: Duration = Duration::from_millis(40);
+
+    fn score(json: &str) -> Suspicion {
+        score_response(&serde_json::from_str(json).unwrap(), LATENCY)
+    }
+
+    #[test]
+    fn test_plausible_server() {
+        let s = score(
+            r#"{"description":"hi","players":{"max":20,"online":2,"sample":[
+                {"name":"kybe","id":"4566e69f-c907-48ee-8d71-d7ba5aa00d20"},
+                {"name":".BedrockUser","id":"00000000-0000-0000-0009-01f64f65c7c3"}]},
+                "version":{"name":"Paper 1.20.4","protocol":765}}"#,
+        );
+        assert_eq!(s, Suspicion::default());
+    }
+
+    #[test]
+    fn test_fake_sample() {
+        let s = score(
+            r#"{"players":{"max":100,"online":0,"sample":[
+                {"name":"§6Welcome to","id":"00000000-0000-0000-0000-000000000000"},
+                {"name":"our network!","id":"00000000-0000-0000-0000-000000000000"}]}}"#,
+        );
+        assert_eq!(s.reasons, vec![SAMPLE_EXCEEDS_ONLINE, FAKE_SAMPLE_ENTRIES]);
+        assert!((s.score - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_anonymous_players() {
+        let s = score(
+            r#"{"players":{"max":100,"online":3,"sample":[
+                {"name":"kybe","id":"4566e69f-c907-48ee-8d71-d7ba5aa00d20"},
+                {"name":"Anonymous Player","id":"00000000-0000-0000-0000-000000000000"},
+                {"name":"Anonymous Player","id":"00000000-0000-0000-0000-000000000000"}]}}"#,
+        );
+        assert_eq!(s, Suspicion::default());
+    }
+
+    #[test]
+    fn test_impossible_counts() {
+        let s = score(r#"{"players":{"max":10,"online":99999999}}"#);
+        assert_eq!(s.reasons, vec![IMPOSSIBLE_PLAYER_COUNT, ONLINE_EXCEEDS_MAX]);
+    }
+
+    #[test]
+    fn test_protocol_checks() {
+        let s = score(r#"{"version":{"name":"1.20.4","protocol":47}}"#);
+        assert_eq!(s.reasons, vec![PROTOCOL_MISMATCH]);
+        // Protocol echoed back from our handshake (ViaVersion, proxies).
+        assert!(
+            score(r#"{"version":{"name":"1.20.4","protocol":757}}"#)
+                .reasons
+                .is_empty()
+        );
+        assert!(
+            score(r#"{"version":{"name":"24w14a","protocol":1073742009}}"#)
+                .reasons
+                .is_empty()
+        );
+        let s = score(r#"{"version":{"name":"Custom","protocol":99999}}"#);
+        assert_eq!(s.reasons, vec![PROTOCOL_OUT_OF_RANGE]);
+    }
+
+    #[test]
+    fn test_instant_response_and_cap() {
+        let json = serde_json::json!({"players": {"max": -1, "online": 5,
+            "sample": [{"name": "a b", "id": "x"},
+                {"name": "Steve", "id": "069a79f4-44e9-4726-a5be-fca90e38aaf5"},
+                {"name": "Alex", "id": "069a79f444e94726a5befca90e38aaf5"}]},
+            "version": {"name": "1.8.8", "protocol": 5000}});
+        let s = score_response(&json, Duration::from_micros(50));
+        assert!(s.reasons.contains(&INSTANT_RESPONSE));
+        assert!(s.reasons.contains(&DUPLICATE_SAMPLE_IDS));
+        assert_eq!(s.score, 1.0);
+    }
+
+    #[test]
+    fn test_response_hash() {
+        let default = r#"{"description":{"text":"A Minecraft Server"}}"#;
+        let json: Value = serde_json::from_str(default).unwrap();
+        assert_eq!(response_hash(default, &json), None);
+        let custom = r#"{"description":"Honeypot"}"#;
+        let json: Value = serde_json::from_str(custom).unwrap();
+        assert_eq!(
+            response_hash(custom, &json),
+            Some(format!("{:x}", md5::compute(custom)))
+        );
+    }
+}
@@ -0,0 +1,287 @@
+/// Release versions and their protocol numbers, oldest first.
+const PROTOCOLS: &[(&str, i32)] = &[
+    ("1.7.2", 4),
+    ("1.7.4", 4),
+    ("1.7.5", 4),
+    ("1.7.6", 5),
+    ("1.7.7", 5),
+    ("1.7.8", 5),
+    ("1.7.9", 5),
+    ("1.7.10", 5),
+    ("1.8", 47),
+    ("1.8.1", 47),
+    ("1.8.2", 47),
+    ("1.8.3", 47),
+    ("1.8.4", 47),
+    ("1.8.5", 47),
+    ("1.8.6", 47),
+    ("1.8.7", 47),
+    ("1.8.8", 47),
+    ("1.8.9", 47),
+    ("1.9", 107),
+    ("1.9.1", 108),
+    ("1.9.2", 109),
+    ("1.9.3", 110),
+    ("1.9.4", 110),
+    ("1.10", 210),
+    ("1.10.1", 210),
+    ("1.10.2", 210),
+    ("1.11", 315),
+    ("1.11.1", 316),
+    ("1.11.2", 316),
+    ("1.12", 335),
+    ("1.12.1", 338),
+    ("1.12.2", 340),
+    ("1.13", 393),
+    ("1.13.1", 401),
+    ("1.13.2", 404),
+    ("1.14", 477),
+    ("1.14.1", 480),
+    ("1.14.2", 485),
+    ("1.14.3", 490),
+    ("1.14.4", 498),
+    ("1.15", 573),
+    ("1.15.1", 575),
+    ("1.15.2", 578),
+    ("1.16", 735),
+    ("1.16.1", 736),
+    ("1.16.2", 751),
+    ("1.16.3", 753),
+    ("1.16.4", 754),
+    ("1.16.5", 754),
+    ("1.17", 755),
+    ("1.17.1", 756),
+    ("1.18", 757),
+    ("1.18.1", 757),
+    ("1.18.2", 758),
+    ("1.19", 759),
+    ("1.19.1", 760),
+    ("1.19.2", 760),
+    ("1.19.3", 761),
+    ("1.19.4", 762),
+    ("1.20", 763),
+    ("1.20.1", 763),
+    ("1.20.2", 764),
+    ("1.20.3", 765),
+    ("1.20.4", 765),
+    ("1.20.5", 766),
+    ("1.20.6", 766),
+    ("1.21", 767),
+    ("1.21.1", 767),
+    ("1.21.2", 768),
+    ("1.21.3", 768),
+    ("1.21.4", 769),
+    ("1.21.5", 770),
+    ("1.21.6", 771),
+    ("1.21.7", 772),
+    ("1.21.8", 772),
+    ("1.21.9", 773),
+    ("1.21.10", 773),
+];
+
+/// Normalized range of game versions a server accepts.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VersionRange {
+    pub min: Option<String>,
+    pub max: Option<String>,
+    pub min_protocol: Option<i32>,
+    pub max_protocol: Option<i32>,
+}
+
+/// `major.minor.patch`, `patch` is `None` for wildcards like `1.8.x`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct GameVersion {
+    major: u32,
+    minor: u32,
+    patch: Option<u32>,
+    /// Written without a patch, like `1.21`.
+    bare: bool,
+}
+
+impl GameVersion {
+    fn parse(s: &str) -> Option<Self> {
+        let mut parts = s.split('.');
+        let major = parts.next()?.parse().ok()?;
+        let minor = parts.next()?.parse().ok()?;
+        let (patch, bare) = match parts.next() {
+            None => (Some(0), true),
+            Some("x") | Some("X") => (None, false),
+            Some(p) => (Some(p.parse().ok()?), false),
+        };
+        if parts.next().is_some() || major != 1 {
+            return None;
+        }
+        Some(GameVersion {
+            major,
+            minor,
+            patch,
+            bare,
+        })
+    }
+
+    fn lowest(&self) -> (u32, u32, u32) {
+        (self.major, self.minor, self.patch.unwrap_or(0))
+    }
+
+    fn highest(&self) -> (u32, u32, u32) {
+        (self.major, self.minor, self.patch.unwrap_or(u32::MAX))
+    }
+}
+
+fn table_key(release: &str) -> (u32, u32, u32) {
+    GameVersion::parse(release)
+        .map(|v| v.lowest())
+        .unwrap_or_default()
+}
+
+/// Pull every `1.x`, `1.x.y` and `1.x.x` token out of a version name.
+fn version_tokens(name: &str) -> Vec<GameVersion> {
+    name.split(|c: char| !(c.is_ascii_digit() || c == '.' || c == 'x' || c == 'X'))
+        .map(|token| token.trim_matches('.'))
+        .filter_map(GameVersion::parse)
+        .collect()
+}
+
+/// First table entry at or above `key` (for the lower bound of a range).
+fn resolve_min(key: (u32, u32, u32)) -> Option<(&'static str, i32)> {
+    PROTOCOLS
+        .iter()
+        .find(|(release, _)| table_key(release) >= key)
+        .filter(|(release, _)| {
+            let k = table_key(release);
+            (k.0, k.1) == (key.0, key.1)
+        })
+        .copied()
+}
+
+/// Last table entry at or below `key` (for the upper bound of a range).
+fn resolve_max(key: (u32, u32, u32)) -> Option<(&'static str, i32)> {
+    PROTOCOLS
+        .iter()
+        .rev()
+        .find(|(release, _)| table_key(release) <= key)
+        .filter(|(release, _)| {
+            let k = table_key(release);
+            (k.0, k.1) == (key.0, key.1)
+        })
+        .copied()
+}
+
+fn format_version(v: &GameVersion) -> String {
+    match v.patch {
+        Some(0) => format!("{}.{}", v.major, v.minor),
+        Some(p) => format!("{}.{}.{}", v.major, v.minor, p),
+        None => format!("{}.{}.x", v.major, v.minor),
+    }
+}
+
+/// Turn `version.name` and `version.protocol` into a supported version range.
+///
+/// Handles plain versions ("Paper 1.20.4"), ranges ("1.8.x-1.21.x") and
+/// ViaVersion style lists ("1.8, 1.12, 1.20"). Without any version in the
+/// name the protocol number is looked up instead.
+pub fn parse_version_range(name: Option<&str>, protocol: Option<i32>) -> VersionRange {
+    let tokens = name.map(version_tokens).unwrap_or_default();
+
+    if let (Some(low), Some(high)) = (
+        tokens.iter().min_by_key(|v| v.lowest()),
+        tokens.iter().max_by_key(|v| v.highest()),
+    ) {
+        // "1.8-1.21" goes up to the last 1.21 release, like "1.8.x-1.21.x".
+        let is_range = tokens.len() > 1 && name.is_some_and(|n| n.contains('-'));
+        let mut high = *high;
+        if is_range && high.bare {
+            high.patch = None;
+        }
+        let min = resolve_min(low.lowest());
+        let max = resolve_max(high.highest());
+        let single = tokens.len() == 1 && low.patch.is_some();
+        let fallback = protocol.filter(|p| *p > 0 && single);
+        return VersionRange {
+            min: Some(match (min, low.patch) {
+                (Some((release, _)), None) => release.to_string(),
+                _ => format_version(low),
+            }),
+            max: Some(match (max, high.patch) {
+                (Some((release, _)), None) => release.to_string(),
+                _ => format_version(&high),
+            }),
+            min_protocol: min.map(|(_, p)| p).or(fallback),
+            max_protocol: max.map(|(_, p)| p).or(fallback),
+        };
+    }
+
+    let protocol = match protocol {
+        Some(p) if p > 0 => p,
+        _ => return VersionRange::default(),
+    };
+    let mut releases = PROTOCOLS.iter().filter(|(_, p)| *p == protocol);
+    let first = releases.next().map(|(release, _)| release.to_string());
+    let last = releases.next_back().map(|(release, _)| release.to_string());
+    VersionRange {
+        max: last.or_else(|| first.clone()),
+        min: first,
+        min_protocol: Some(protocol),
+        max_protocol: Some(protocol),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(name: &str, protocol: i32) -> VersionRange {
+        parse_version_range(Some(name), Some(protocol))
+    }
+
+    #[test]
+    fn test_single_version() {
+        let r = range("Paper 1.20.4", 765);
+        assert_eq!(r.min.as_deref(), Some("1.20.4"));
+        assert_eq!(r.max.as_deref(), Some("1.20.4"));
+        assert_eq!((r.min_protocol, r.max_protocol), (Some(765), Some(765)));
+    }
+
+    #[test]
+    fn test_wildcard_range() {
+        let r = range("BungeeCord 1.8.x-1.21.x", 757);
+        assert_eq!(r.min.as_deref(), Some("1.8"));
+        assert_eq!(r.max.as_deref(), Some("1.21.10"));
+        assert_eq!((r.min_protocol, r.max_protocol), (Some(47), Some(773)));
+    }
+
+    #[test]
+    fn test_bare_range() {
+        let r = range("MyNetwork 1.8-1.21", 757);
+        assert_eq!(r.min.as_deref(), Some("1.8"));
+        assert_eq!(r.max.as_deref(), Some("1.21.10"));
+        assert_eq!((r.min_protocol, r.max_protocol), (Some(47), Some(773)));
+        // An explicit patch stays as written.
+        let r = range("1.8 - 1.20.1", 757);
+        assert_eq!(r.max.as_deref(), Some("1.20.1"));
+        assert_eq!(r.max_protocol, Some(763));
+    }
+
+    #[test]
+    fn test_list() {
+        let r = range("Requires MC 1.12.2, 1.16.5, 1.20", 0);
+        assert_eq!(r.min.as_deref(), Some("1.12.2"));
+        assert_eq!(r.max.as_deref(), Some("1.20"));
+        assert_eq!((r.min_protocol, r.max_protocol), (Some(340), Some(763)));
+    }
+
+    #[test]
+    fn test_protocol_only() {
+        let r = range("§cMaintenance", 763);
+        assert_eq!(r.min.as_deref(), Some("1.20"));
+        assert_eq!(r.max.as_deref(), Some("1.20.1"));
+        assert_eq!(range("Custom", -1), VersionRange::default());
+    }
+
+    #[test]
+    fn test_unknown_release_uses_protocol() {
+        let r = range("1.22.3", 900);
+        assert_eq!(r.min.as_deref(), Some("1.22.3"));
+        assert_eq!((r.min_protocol, r.max_protocol), (Some(900), Some(900)));
+    }
+}
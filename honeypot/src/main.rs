@@ -1,22 +1,28 @@
 use std::{collections::HashSet, env};
 
 use dotenv::dotenv;
+use std::time::Duration;
+
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::AsyncWriteExt,
     net::{TcpListener, TcpStream},
+    time::Instant,
 };
 
-use crate::{
-    matrix::{matrix_join_room, matrix_log},
-    string::write_string,
-    u16::read_u16,
-    varint::{read_var_int, read_var_int_from_stream, write_var_int},
+use common::packets::{
+    Packet,
+    clientbound::{PongResponse, StatusResponse},
+    frame::{Limits, read_frame_until},
+    serverbound::{Handshake, PingRequest, StatusRequest},
+    varint::read_var_int,
 };
 
+use crate::matrix::{matrix_join_room, matrix_log};
+
 mod matrix;
-mod string;
-mod u16;
-mod varint;
+
+/// How long a client may stay silent before we drop it.
+const CLIENT_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -60,68 +66,63 @@ async fn main() -> anyhow::Result<()> {
 
 async fn handle_client(stream: &mut TcpStream) -> anyhow::Result<()> {
     let mut status = false;
+    let limits = Limits::default();
     loop {
-        let packet_length = read_var_int_from_stream(stream).await?;
-        if packet_length > 2_097_151 {
-            anyhow::bail!("Packet length too large: {}", packet_length);
-        }
-
-        let mut buffer = vec![0u8; packet_length as usize];
-        stream.read_exact(&mut buffer).await?;
+        let deadline = Instant::now() + CLIENT_IDLE_TIMEOUT;
+        let buffer = read_frame_until(stream, &limits, deadline).await?;
 
         let mut offset = 0usize;
-        let packet_id = {
-            let id = read_var_int(&buffer.clone(), Some(&mut offset));
-            id as i32
-        };
-
-        let data = &buffer[offset..];
-        offset = 0;
+        let packet_id = read_var_int(&buffer, Some(&mut offset))?;
 
         println!(
             "Received packet_id={} with {} bytes data",
             packet_id,
-            data.len()
+            buffer.len() - offset
         );
 
-        if !status && packet_id == 0x00 {
-            let protocol_version = read_var_int(data, Some(&mut offset));
-            let server_address = string::read_string(data, &mut offset)?;
-            let server_port = read_u16(data, Some(&mut offset))?;
-            let next_state = read_var_int(data, Some(&mut offset));
+        let response = if !status && packet_id == Handshake::ID {
+            let handshake = Handshake::decode(&buffer, &limits)?;
 
             println!(
                 "Client connected with protocol version {}, address {}, port {}, next state {}",
-                protocol_version, server_address, server_port, next_state
+                handshake.protocol_version,
+                handshake.server_address,
+                handshake.server_port,
+                handshake.next_state
             );
             matrix_log(&format!(
                 "Client connected with protocol version {}, address {}, port {}, next state {}",
-                protocol_version, server_address, server_port, next_state
+                handshake.protocol_version,
+                handshake.server_address,
+                handshake.server_port,
+                handshake.next_state
             ))
             .await?;
 
             status = true;
             continue;
-        } else if status && packet_id == 0x00 {
+        } else if status && packet_id == StatusRequest::ID {
             println!("sending payload");
-
-            let data = include_str!("../msg.txt");
-
-            let mut payload = Vec::new();
-            write_var_int(&mut payload, &0x0);
-            write_string(&mut payload, data);
-
-            let mut response = Vec::new();
-            write_var_int(&mut response, &(payload.len() as i32));
-            response.extend_from_slice(&payload);
-
-            if let Err(e) = stream.write_all(&response).await {
-                let peer = stream
-                    .peer_addr()
-                    .unwrap_or_else(|_| "unknown".parse().unwrap());
-                eprintln!("Failed to send response to client {}: {:?}", peer, e);
-                return Err(e.into());
+            StatusResponse {
+                json: include_str!("../msg.txt").to_string(),
+            }
+            .encode()?
+        } else if status && packet_id == PingRequest::ID {
+            let ping = PingRequest::decode(&buffer, &limits)?;
+            PongResponse {
+                payload: ping.payload,
             }
+            .encode()?
+        } else {
+            continue;
+        };
+
+        if let Err(e) = stream.write_all(&response).await {
+            let peer = stream
+                .peer_addr()
+                .unwrap_or_else(|_| "unknown".parse().unwrap());
+            eprintln!("Failed to send response to client {}: {:?}", peer, e);
+            return Err(e.into());
         }
     }
 }